@@ -7,41 +7,30 @@ extern crate r2d2;
 extern crate redis;
 #[macro_use]
 extern crate serde_json;
-#[macro_use]
-extern crate serde_derive;
 
 use redis::RedisError;
 use r2d2::Pool;
 use r2d2_redis::RedisConnectionManager;
 use std::io::{ Error, ErrorKind };
 use auth_rocket::redisdb::RedisEntity;
-use rocket::request::{self, Request, FromRequest };
-use auth_rocket::{ PrivateKey, AuthEntity, User, Role, user_from_request };
-use rocket::Outcome;
-use rocket::http::Status;
+use auth_rocket::{ PrivateKey, AuthEntity, Entity, Role, Permitted, RequiredPermission };
 
 fn main() {
     let redis = connect_pool("redis://127.0.0.1/", true);
     let redis_entity = RedisEntity::new(&redis, "test_example:".to_string());
 
+    pub struct EnterCave;
 
-    #[derive(Deserialize, Serialize)]
-    pub struct BatmanCustomUser(User);
-
-    impl<'a, 'r> FromRequest<'a, 'r> for BatmanCustomUser {
-        type Error = ();
-
-        fn from_request(request: &'a Request<'r>) -> request::Outcome<BatmanCustomUser, ()> {
-            match user_from_request(request, vec!(Role::Custom("Batman".to_string()))) {
-                Outcome::Success(user) => {
-                    Outcome::Success(BatmanCustomUser(user))
-                },
-                Outcome::Failure(e) => Outcome::Failure(e),
-                _ => Outcome::Failure((Status::Unauthorized, ()))
-            }
+    impl RequiredPermission for EnterCave {
+        fn permission() -> &'static str {
+            "cave:enter"
         }
     }
 
+    if let Some(e) = redis_entity.grant_permission(&Role::Custom("Batman".to_string()), EnterCave::permission()) {
+        panic!("cannot grant permission ({})", e);
+    }
+
     rocket::ignite().mount("/api", routes!(get_user, get_user_bat))
         .manage(PrivateKey::new("my_secret_key".to_string()))
         .manage(AuthEntity::new(Box::new(redis_entity))).launch();
@@ -51,8 +40,8 @@ fn main() {
         format!("{}", json!(user).to_string())
     }
     #[get("/user/batman")]
-    pub fn get_user_bat(user: BatmanCustomUser) -> String {
-        format!("{}", json!(user).to_string())
+    pub fn get_user_bat(user: Permitted<EnterCave>) -> String {
+        format!("{}", json!(user.get_user()).to_string())
     }
 }
 
@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use serde::{ Deserialize, Deserializer };
+use serde_json::Value;
 
 #[derive(Deserialize)]
 pub struct SignIn {
@@ -14,3 +16,57 @@ pub struct SignUp {
     pub re_password: String,
     pub attributes: HashMap<String, String>
 }
+
+#[derive(Deserialize)]
+pub struct Refresh {
+    pub refresh_token: String
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPassword {
+    pub username: String
+}
+
+#[derive(Deserialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+    pub re_password: String
+}
+
+#[derive(Deserialize)]
+pub struct ChangePassword {
+    pub password: String,
+    pub new_password: String,
+    pub re_password: String,
+    /// Sign out every other session of the user
+    #[serde(default)]
+    pub revoke_sessions: bool
+}
+
+#[derive(Deserialize)]
+pub struct SignInMfa {
+    pub mfa_ticket: String,
+    pub code: String
+}
+
+#[derive(Deserialize)]
+pub struct MfaCode {
+    pub code: String
+}
+
+/// Quota of the user instead of the one of its role, a number or `"unlimited"`
+///
+/// `None` leaves the quota as it is, `Some(Value::Null)` removes it.
+#[derive(Deserialize)]
+pub struct QuotaOverride {
+    #[serde(default, deserialize_with = "present")]
+    pub daily: Option<Value>,
+    #[serde(default, deserialize_with = "present")]
+    pub monthly: Option<Value>
+}
+
+/// `Some` for every value the field has in the body, null included, missing fields get `None` by `default`
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
@@ -3,37 +3,73 @@ mod form;
 
 use rocket_contrib::Json;
 use rocket::request::{ State };
-use ::net::key::{ generate_api_key, PrivateKey };
-use ::limitation::user::{ AuthorizedUser, AdminUser };
-use ::{ AuthEntity, Entity, Role };
+use ::net::key::ApiKeys;
+use ::limitation::user::{ AuthorizedUser, AccessToken };
+use ::{ AuthEntity, AuthError, Entity, User, UserStatus, ClientInfo, Session };
+use ::jwt::{ JwtConfig, JwtError, Claims };
+use ::limitation::cookie::{ CookieSession, Csrf };
+use ::limitation::lockout::LockoutPolicy;
+use ::limitation::rate::RateLimited;
+use ::limitation::quota::{ QuotaPolicy, QuotaPeriod, MeteredUser, UNLIMITED };
+use ::limitation::permission::{ Permitted, UsersRead, UsersWrite, USERS_READ, user_has_permission };
+use ::session::ticket::{ Verification, PasswordReset, VERIFY_EMAIL, RESET_PASSWORD, MFA_PENDING, new_ticket, hash_ticket };
+use ::mail::{ AuthMailer, Mailer, Message };
+use ::password::policy::PasswordPolicy;
+use ::mfa::MfaSecret;
+use ::mfa::totp::Totp;
+use ::mfa::recovery::{ RECOVERY_CODES, generate_recovery_codes, hash_recovery_code };
+use chrono::Local;
+use serde_json::Value;
 use ::api::condition::LimitOffset;
-use ::api::form::{ SignIn, SignUp };
+use ::api::form::{ SignIn, SignUp, Refresh, ForgotPassword, ResetPassword, ChangePassword, SignInMfa, MfaCode, QuotaOverride };
 use rocket::response::{ status, Redirect };
-use rocket::http::Status;
+use rocket::http::{ Status, Cookies };
 use rocket::Route;
 use ::net::uri::RequestedUriString;
 
+/// Create user, with `Verification` in the managed state the user stays `Created` until the emailed link is opened
 #[post("/users/sign_up", format = "application/json", data="<sign_up>")]
-pub fn sign_up(entity: State<AuthEntity>, sign_up: Json<SignUp>, uri: RequestedUriString) -> Result<status::Created<Json>, status::Custom<Json>> {
+pub fn sign_up(entity: State<AuthEntity>, _limit: RateLimited, sign_up: Json<SignUp>, uri: RequestedUriString, policy: Option<State<PasswordPolicy>>,
+               verification: Option<State<Verification>>, mailer: Option<State<AuthMailer>>) -> Result<status::Created<Json>, status::Custom<Json>> {
 
-    if sign_up.password != sign_up.re_password {
-        return Err(status::Custom(Status::BadRequest, Json(json!({
-            "error": ""
-        }))))
+    check_password(policy.as_ref().map(|p| p.inner()), sign_up.password.as_str(), sign_up.re_password.as_str(),
+                   sign_up.username.as_str(), sign_up.email.as_str())?;
+
+    // quota overrides are up to admins
+    let mut attributes = sign_up.attributes.clone();
+    for period in QuotaPeriod::all().iter() {
+        attributes.remove(period.attribute());
     }
 
-    match entity.inner().add_user(sign_up.username.as_str(), sign_up.email.as_str(), sign_up.password.as_str(), sign_up.attributes.clone()) {
+    match entity.inner().add_user(sign_up.username.as_str(), sign_up.email.as_str(), sign_up.password.as_str(), attributes) {
         Ok(user) => {
             let mut uri_str = uri.to_string();
             uri_str.push_str(format!("{}", user.id).as_str());
-            Ok(status::Created(uri_str.replace("sign_up/", "user/"), Some(Json(json!({
-                "data": match entity.inner().enable_user(user.name.as_str()) {
+
+            let user = match verification {
+                Some(verification) => match send_verification(entity.inner(), verification.inner(), mailer.as_ref().map(|m| m.inner()), &user) {
+                    None => user,
+                    Some(e) => {
+                        // nobody can verify the user without the email, let the sign up be repeated
+                        if let Some(e) = entity.inner().delete_user(user.id) {
+                            error!("cannot delete unverified user {} ({})", user.name, e);
+                        }
+                        return Err(status::Custom(Status::InternalServerError, Json(json!({
+                            "error": format!("{}", e)
+                        }))))
+                    }
+                },
+                None => match entity.inner().enable_user(user.name.as_str()) {
                     Ok(u) => u,
                     Err(e) => {
                         error!("{}", e);
                         user
                     }
                 }
+            };
+
+            Ok(status::Created(uri_str.replace("sign_up/", "user/"), Some(Json(json!({
+                "data": user
             })))))
         },
         Err(e) => Err(status::Custom(Status::Conflict, Json(json!({
@@ -42,20 +78,542 @@ pub fn sign_up(entity: State<AuthEntity>, sign_up: Json<SignUp>, uri: RequestedU
     }
 }
 
+/// New password must be repeated and satisfy the managed policy, or the default one
+fn check_password(policy: Option<&PasswordPolicy>, password: &str, re_password: &str, username: &str, email: &str) -> Result<(), status::Custom<Json>> {
+    if password != re_password {
+        return Err(status::Custom(Status::BadRequest, Json(json!({"error": "Passwords don't match"}))));
+    }
+
+    let default = PasswordPolicy::default();
+
+    policy.unwrap_or(&default).validate(password, username, email)
+        .map_err(|violations| status::Custom(Status::BadRequest, Json(json!({
+            "error": violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(". "),
+            "violations": violations
+        }))))
+}
+
+/// Store hash of a fresh verification ticket and mail the link with the ticket to the user
+fn send_verification(entity: &AuthEntity, verification: &Verification, mailer: Option<&AuthMailer>, user: &User) -> Option<AuthError> {
+    let mailer = match mailer {
+        Some(mailer) => mailer,
+        None => {
+            error!("email verification needs AuthMailer in the managed state");
+            return Some(AuthError::IOError);
+        }
+    };
+
+    let ticket = match new_ticket() {
+        Some(ticket) => ticket,
+        None => return Some(AuthError::IOError)
+    };
+
+    if let Some(e) = entity.add_ticket(VERIFY_EMAIL, hash_ticket(ticket.as_str()).as_str(), user.name.as_str(), verification.ttl()) {
+        return Some(e);
+    }
+
+    mailer.send(&Message::new(user.email.as_str(), "Confirm your email",
+                              format!("Open the link to confirm your email: {}", verification.link(ticket.as_str()))))
+        .err()
+}
+
+/// Activate the user by the ticket from the verification email, the ticket works once
+#[get("/users/verify/<token>")]
+pub fn verify_email(entity: State<AuthEntity>, token: String) -> status::Custom<Json> {
+    let username = match entity.inner().take_ticket(VERIFY_EMAIL, hash_ticket(token.as_str()).as_str()) {
+        Ok(username) => username,
+        Err(AuthError::NotFound) => return status::Custom(Status::NotFound, Json(json!({"error": format!("{}", AuthError::NotFound)}))),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    };
+
+    let user = match entity.inner().get_user_by_name(username.as_str()) {
+        Ok(u) => User::from(u),
+        Err(e) => return status::Custom(Status::NotFound, Json(json!({"error": format!("{}", e)})))
+    };
+
+    // a disabled user stays disabled whatever the email says
+    match user.status {
+        UserStatus::Created => match entity.inner().enable_user(username.as_str()) {
+            Ok(u) => status::Custom(Status::Ok, Json(json!({"data": u}))),
+            Err(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+        },
+        UserStatus::Active => status::Custom(Status::Ok, Json(json!({"data": user}))),
+        _ => status::Custom(Status::Forbidden, Json(json!({"error": format!("{}", AuthError::DisabledUser)})))
+    }
+}
+
+/// Mail a password reset link to the user
+///
+/// The answer is always `202 Accepted`, so it doesn't tell whether the user exists.
+#[post("/users/password/forgot", format = "application/json", data="<forgot>")]
+pub fn forgot_password(entity: State<AuthEntity>, _limit: RateLimited, forgot: Json<ForgotPassword>,
+                       reset: Option<State<PasswordReset>>, mailer: Option<State<AuthMailer>>) -> status::Custom<Json> {
+    let (reset, mailer) = match (reset, mailer) {
+        (Some(reset), Some(mailer)) => (reset, mailer),
+        _ => {
+            error!("password reset needs PasswordReset and AuthMailer in the managed state");
+            return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", AuthError::IOError)})));
+        }
+    };
+
+    let sent = entity.inner().get_user_by_name(forgot.username.as_str())
+        .and_then(|user| new_ticket().ok_or(AuthError::IOError).map(|ticket| (user, ticket)))
+        .and_then(|(user, ticket)| {
+            if let Some(e) = entity.inner().add_ticket(RESET_PASSWORD, hash_ticket(ticket.as_str()).as_str(), user.name.as_str(), reset.ttl()) {
+                return Err(e);
+            }
+
+            mailer.send(&Message::new(user.email.as_str(), "Reset your password",
+                                      format!("Open the link to set a new password: {}", reset.link(ticket.as_str()))))
+        });
+
+    match sent {
+        Ok(_) | Err(AuthError::NotFound) => (),
+        Err(e) => error!("cannot send password reset to {} ({})", forgot.username, e)
+    }
+
+    status::Custom(Status::Accepted, Json(json!({"data": {}})))
+}
+
+/// Set a new password by the ticket from the reset email and sign the user out everywhere
+///
+/// The ticket is taken only by a password the policy accepts, so the user can try another one till the ticket expires.
+#[post("/users/password/reset", format = "application/json", data="<reset>")]
+pub fn reset_password(entity: State<AuthEntity>, reset: Json<ResetPassword>, policy: Option<State<PasswordPolicy>>) -> status::Custom<Json> {
+    let ticket = hash_ticket(reset.token.as_str());
+    let bad_ticket = || status::Custom(Status::BadRequest, Json(json!({"error": format!("{}", AuthError::NotFound)})));
+
+    let user = match entity.inner().get_ticket(RESET_PASSWORD, ticket.as_str()).and_then(|username| entity.inner().get_user_by_name(username.as_str())) {
+        Ok(user) => user,
+        Err(AuthError::NotFound) => return bad_ticket(),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    };
+    let username = user.name.clone();
+
+    if let Err(response) = check_password(policy.as_ref().map(|p| p.inner()), reset.password.as_str(), reset.re_password.as_str(),
+                                          user.name.as_str(), user.email.as_str()) {
+        return response;
+    }
+
+    match entity.inner().take_ticket(RESET_PASSWORD, ticket.as_str()) {
+        Ok(ref taken) if *taken == username => (),
+        Ok(_) | Err(AuthError::NotFound) => return bad_ticket(),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+
+    if let Err(e) = entity.inner().set_password(username.as_str(), reset.password.as_str()) {
+        return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})));
+    }
+
+    match entity.inner().delete_sessions(username.as_str()) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {}}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Change the password of the current user, the old one is required
+#[post("/users/password", format = "application/json", data="<change>")]
+pub fn change_password(entity: State<AuthEntity>, token: AccessToken, user: AuthorizedUser, _csrf: Csrf, change: Json<ChangePassword>,
+                       policy: Option<State<PasswordPolicy>>) -> status::Custom<Json> {
+    if let Err(response) = check_password(policy.as_ref().map(|p| p.inner()), change.new_password.as_str(), change.re_password.as_str(),
+                                          user.get_user().name.as_str(), user.get_user().email.as_str()) {
+        return response;
+    }
+
+    let username = user.get_user().name.clone();
+
+    if let Err(e) = entity.inner().get_user_by_name_and_pwd(username.as_str(), change.password.as_str()) {
+        return status::Custom(Status::Forbidden, Json(json!({"error": format!("{}", e)})));
+    }
+
+    let updated = match entity.inner().set_password(username.as_str(), change.new_password.as_str()) {
+        Ok(u) => u,
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    };
+
+    if change.revoke_sessions {
+        if let Some(e) = revoke_other_sessions(entity.inner(), username.as_str(), token.as_str()) {
+            return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})));
+        }
+    }
+
+    status::Custom(Status::Ok, Json(json!({"data": updated})))
+}
+
+/// Revoke every session of the user except the one of the token
+fn revoke_other_sessions(entity: &AuthEntity, username: &str, token: &str) -> Option<AuthError> {
+    let current = match entity.get_session_by_token(token) {
+        Ok(session) => session.id,
+        Err(e) => return Some(e)
+    };
+
+    let sessions = match entity.list_sessions(username) {
+        Ok(sessions) => sessions,
+        Err(e) => return Some(e)
+    };
+
+    sessions.iter()
+        .filter(|s| s.id != current)
+        .filter_map(|s| match entity.delete_session(username, s.id.as_str()) {
+            // the session may expire meanwhile
+            Some(AuthError::NotFound) => None,
+            e => e
+        })
+        .next()
+}
+
 #[post("/users/sign_in", format = "application/json", data="<sign_in>")]
-pub fn sign_in(private_key: State<PrivateKey>, entity: State<AuthEntity>, sign_in: Json<SignIn>) -> status::Custom<Json> {
+pub fn sign_in(keys: ApiKeys, entity: State<AuthEntity>, _limit: RateLimited, sign_in: Json<SignIn>, client: ClientInfo, jwt: Option<State<JwtConfig>>,
+               totp: Option<State<Totp>>, lockout: Option<State<LockoutPolicy>>, cookie_session: Option<State<CookieSession>>,
+               mut cookies: Cookies) -> status::Custom<Json> {
+
+    let default = LockoutPolicy::default();
+    let lockout = lockout.as_ref().map(|l| l.inner()).unwrap_or(&default);
+    let username = sign_in.username.as_str();
+    let ip = client.ip.as_ref().map(|ip| ip.as_str());
+    let now = Local::now().timestamp();
+
+    // checked before the password, so a locked account doesn't tell whether the password is right
+    match lockout.retry_after(entity.inner(), username, ip, now) {
+        Ok(0) => (),
+        Ok(retry_after) => return status::Custom(Status::new(423, "Locked"), Json(json!({
+            "error": format!("{}", AuthError::Locked),
+            "retry_after": retry_after
+        }))),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+
+    // only wrong credentials are counted, an outage or an inactive user must not lock anybody out
+    let user = match entity.inner().get_user_by_name_and_pwd(username, sign_in.password.as_str()) {
+        Ok(user) => user,
+        Err(AuthError::NotFound) => {
+            if let Some(e) = lockout.record_failure(entity.inner(), username, ip, now) {
+                error!("cannot count failed sign in of {} ({})", username, e);
+            }
+            return status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", AuthError::NotFound)})))
+        },
+        Err(AuthError::IOError) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", AuthError::IOError)}))),
+        Err(e) => return status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", e)})))
+    };
+
+    match entity.inner().get_mfa_secret(user.name.as_str()) {
+        Ok(ref mfa) if mfa.confirmed => {
+            let default = Totp::default();
+            return mfa_challenge(entity.inner(), totp.as_ref().map(|t| t.inner()).unwrap_or(&default), &user);
+        },
+        Ok(_) | Err(AuthError::NotFound) => (),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+
+    start_session(&keys, entity.inner(), &client, jwt.as_ref().map(|j| j.inner()), cookie_session.as_ref().map(|c| c.inner()), lockout, &mut cookies, &user)
+}
+
+/// Password is right but the user has a second factor, answer with a short-lived ticket for `sign_in_mfa`
+fn mfa_challenge(entity: &AuthEntity, totp: &Totp, user: &User) -> status::Custom<Json> {
+    let ticket = match new_ticket() {
+        Some(ticket) => ticket,
+        None => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", AuthError::IOError)})))
+    };
+
+    match entity.add_ticket(MFA_PENDING, hash_ticket(ticket.as_str()).as_str(), user.name.as_str(), totp.ticket_ttl()) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {"mfa_required": true, "mfa_ticket": ticket}}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Second step of sign in, the ticket works for one attempt
+///
+/// `code` is either the TOTP code or one of the recovery codes, a wrong one counts as a failed sign in of the user.
+#[post("/users/sign_in/mfa", format = "application/json", data="<sign_in>")]
+pub fn sign_in_mfa(keys: ApiKeys, entity: State<AuthEntity>, _limit: RateLimited, sign_in: Json<SignInMfa>, client: ClientInfo, jwt: Option<State<JwtConfig>>,
+                   totp: Option<State<Totp>>, lockout: Option<State<LockoutPolicy>>, cookie_session: Option<State<CookieSession>>,
+                   mut cookies: Cookies) -> status::Custom<Json> {
+    let unauthorized = |e: AuthError| status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", e)})));
+
+    let user = match entity.inner().take_ticket(MFA_PENDING, hash_ticket(sign_in.mfa_ticket.as_str()).as_str())
+        .and_then(|username| entity.inner().get_user_by_name(username.as_str())) {
+        Ok(user) => User::from(user),
+        Err(e) => return unauthorized(e)
+    };
+
+    let default = LockoutPolicy::default();
+    let lockout = lockout.as_ref().map(|l| l.inner()).unwrap_or(&default);
+    let ip = client.ip.as_ref().map(|ip| ip.as_str());
+    let now = Local::now().timestamp();
 
-    if let Err(e) = entity.inner().get_user_by_name_and_pwd(sign_in.username.as_str(), sign_in.password.as_str()) {
-        return status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", e)})))
+    match lockout.retry_after(entity.inner(), user.name.as_str(), ip, now) {
+        Ok(0) => (),
+        Ok(retry_after) => return status::Custom(Status::new(423, "Locked"), Json(json!({
+            "error": format!("{}", AuthError::Locked),
+            "retry_after": retry_after
+        }))),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
     }
 
-    let token: String = generate_api_key(private_key.inner().as_str()).unwrap();
+    let default = Totp::default();
+    let verified = match verify_totp(entity.inner(), totp.as_ref().map(|t| t.inner()).unwrap_or(&default), user.name.as_str(), sign_in.code.as_str(), true) {
+        Err(AuthError::AccessDenied) => match entity.inner().take_recovery_code(user.name.as_str(), hash_recovery_code(sign_in.code.as_str()).as_str()) {
+            None => {
+                info!("user {} signed in with a recovery code", user.name);
+                None
+            },
+            Some(e) => Some(e)
+        },
+        result => result.err()
+    };
 
-    if let Some(e) = entity.inner().add_token(sign_in.username.as_str(), token.as_str()) {
-        return status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", e)})))
+    if let Some(e) = verified {
+        if let Some(e) = lockout.record_failure(entity.inner(), user.name.as_str(), ip, now) {
+            error!("cannot count failed sign in of {} ({})", user.name, e);
+        }
+        return unauthorized(e);
     }
 
-    status::Custom(Status::Ok,Json(json!({"data": {"token": token}})))
+    start_session(&keys, entity.inner(), &client, jwt.as_ref().map(|j| j.inner()), cookie_session.as_ref().map(|c| c.inner()), lockout, &mut cookies, &user)
+}
+
+/// Check the code against the stored secret and remember its step, so the code can't be replayed
+///
+/// `confirmed` tells whether an unconfirmed secret is acceptable, the enrollment uses it to confirm the secret.
+fn verify_totp(entity: &AuthEntity, totp: &Totp, username: &str, code: &str, confirmed: bool) -> Result<MfaSecret, AuthError> {
+    let mut secret = entity.get_mfa_secret(username)?;
+
+    if confirmed && !secret.confirmed {
+        return Err(AuthError::NotFound);
+    }
+
+    match totp.verify(secret.secret.as_str(), code, Local::now().timestamp(), secret.last_step) {
+        Some(step) => {
+            secret.last_step = step;
+            secret.confirmed = true;
+            match entity.set_mfa_secret(username, Some(secret.clone())) {
+                None => Ok(secret),
+                Some(e) => Err(e)
+            }
+        },
+        None => Err(AuthError::AccessDenied)
+    }
+}
+
+/// New session with the access token in the body, or in the cookie in the cookie session mode
+///
+/// Sign in is complete here, so the failed attempts of the user are forgotten.
+fn start_session(keys: &ApiKeys, entity: &AuthEntity, client: &ClientInfo, jwt: Option<&JwtConfig>, cookie_session: Option<&CookieSession>,
+                 lockout: &LockoutPolicy, cookies: &mut Cookies, user: &User) -> status::Custom<Json> {
+    if let Some(e) = lockout.unlock(entity, user.name.as_str()) {
+        warn!("cannot reset failed sign ins of {} ({})", user.name, e);
+    }
+
+    let token: String = keys.generate().unwrap();
+
+    match entity.add_session(user.name.as_str(), token.as_str(), client) {
+        Ok(session) => match cookie_session {
+            Some(cookie_session) => session_cookies(cookie_session, cookies, jwt, user, token, session),
+            None => session_tokens(keys, entity, jwt, user, token, session)
+        },
+        Err(e) => status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Start TOTP enrollment, the secret is used for sign in only after `confirm_totp`
+#[post("/users/mfa/totp", format = "application/json")]
+pub fn enroll_totp(entity: State<AuthEntity>, user: AuthorizedUser, _csrf: Csrf, totp: Option<State<Totp>>) -> status::Custom<Json> {
+    let username = user.get_user().name.as_str();
+    let default = Totp::default();
+    let totp = totp.as_ref().map(|t| t.inner()).unwrap_or(&default);
+
+    match entity.inner().get_mfa_secret(username) {
+        Ok(ref mfa) if mfa.confirmed => return status::Custom(Status::Conflict, Json(json!({"error": "Two-factor authentication is already on"}))),
+        Ok(_) | Err(AuthError::NotFound) => (),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+
+    let secret = match totp.generate_secret() {
+        Some(secret) => secret,
+        None => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", AuthError::IOError)})))
+    };
+
+    match entity.inner().set_mfa_secret(username, Some(MfaSecret::new(secret.as_str()))) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {"secret": secret, "uri": totp.uri(username, secret.as_str())}}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Finish TOTP enrollment with the first code of the authenticator app
+#[post("/users/mfa/totp/confirm", format = "application/json", data="<code>")]
+pub fn confirm_totp(entity: State<AuthEntity>, user: AuthorizedUser, _csrf: Csrf, code: Json<MfaCode>, totp: Option<State<Totp>>) -> status::Custom<Json> {
+    let default = Totp::default();
+
+    match verify_totp(entity.inner(), totp.as_ref().map(|t| t.inner()).unwrap_or(&default), user.get_user().name.as_str(), code.code.as_str(), false) {
+        Ok(_) => status::Custom(Status::Ok, Json(json!({"data": {"mfa": true}}))),
+        Err(AuthError::NotFound) => status::Custom(Status::NotFound, Json(json!({"error": format!("{}", AuthError::NotFound)}))),
+        Err(AuthError::AccessDenied) => status::Custom(Status::BadRequest, Json(json!({"error": "Invalid code"}))),
+        Err(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// New batch of recovery codes, the previous one stops working
+///
+/// The codes are shown only once, the entity keeps their hashes.
+#[post("/users/mfa/recovery_codes", format = "application/json")]
+pub fn regenerate_recovery_codes(entity: State<AuthEntity>, user: AuthorizedUser, _csrf: Csrf) -> status::Custom<Json> {
+    let username = user.get_user().name.as_str();
+
+    match entity.inner().get_mfa_secret(username) {
+        Ok(ref mfa) if mfa.confirmed => (),
+        Ok(_) | Err(AuthError::NotFound) => return status::Custom(Status::NotFound, Json(json!({"error": "Two-factor authentication is off"}))),
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+
+    let codes = match generate_recovery_codes(RECOVERY_CODES) {
+        Some(codes) => codes,
+        None => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", AuthError::IOError)})))
+    };
+
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+
+    match entity.inner().set_recovery_codes(username, &hashes) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {"recovery_codes": codes}}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Turn the second factor off, a current code is required
+#[delete("/users/mfa/totp", format = "application/json", data="<code>")]
+pub fn disable_totp(entity: State<AuthEntity>, user: AuthorizedUser, _csrf: Csrf, code: Json<MfaCode>, totp: Option<State<Totp>>) -> status::Custom<Json> {
+    let username = user.get_user().name.as_str();
+    let default = Totp::default();
+
+    match verify_totp(entity.inner(), totp.as_ref().map(|t| t.inner()).unwrap_or(&default), username, code.code.as_str(), false) {
+        Ok(_) => match entity.inner().set_mfa_secret(username, None).or_else(|| entity.inner().set_recovery_codes(username, &[])) {
+            None => status::Custom(Status::Ok, Json(json!({"data": {"mfa": false}}))),
+            Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+        },
+        Err(AuthError::NotFound) => status::Custom(Status::NotFound, Json(json!({"error": format!("{}", AuthError::NotFound)}))),
+        Err(AuthError::AccessDenied) => status::Custom(Status::BadRequest, Json(json!({"error": "Invalid code"}))),
+        Err(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Exchange refresh token for a new access token and a new refresh token
+///
+/// Every refresh token works once, a replayed one revokes the whole session.
+#[post("/users/token/refresh", format = "application/json", data="<refresh>")]
+pub fn refresh_token(keys: ApiKeys, entity: State<AuthEntity>, refresh: Json<Refresh>, client: ClientInfo, jwt: Option<State<JwtConfig>>) -> status::Custom<Json> {
+    let unauthorized = |e: AuthError| status::Custom(Status::Unauthorized, Json(json!({"error": format!("{}", e)})));
+
+    if !keys.validate(refresh.refresh_token.as_str()) {
+        return unauthorized(AuthError::NotFound);
+    }
+
+    let taken = match entity.inner().take_refresh_token(refresh.refresh_token.as_str()) {
+        Ok(taken) => taken,
+        Err(e) => return unauthorized(e)
+    };
+
+    if taken.used {
+        warn!("refresh token of session {} is used twice, revoke the session", taken.session_id);
+        if let Some(e) = entity.inner().delete_session(taken.username.as_str(), taken.session_id.as_str()) {
+            error!("cannot revoke session {} ({})", taken.session_id, e);
+        }
+        return unauthorized(AuthError::NotFound);
+    }
+
+    let user = match entity.inner().get_user_by_name(taken.username.as_str()) {
+        Ok(u) => match u.status == UserStatus::Active {
+            true => User::from(u),
+            false => return unauthorized(AuthError::NotActive)
+        },
+        Err(e) => return unauthorized(e)
+    };
+
+    let token: String = keys.generate().unwrap();
+
+    match entity.inner().renew_session(taken.username.as_str(), taken.session_id.as_str(), taken.session_created_at, token.as_str(), &client) {
+        Ok(session) => session_tokens(&keys, entity.inner(), jwt.as_ref().map(|j| j.inner()), &user, token, session),
+        Err(e) => unauthorized(e)
+    }
+}
+
+/// Access token of the session, in JWT mode the session token becomes the `jti` claim of the signed token
+fn access_token(jwt: Option<&JwtConfig>, user: &User, session_token: String) -> Result<String, JwtError> {
+    match jwt {
+        Some(jwt) => jwt.encode(&Claims::new(user, session_token.as_str(), Local::now().timestamp(), jwt.ttl())),
+        None => Ok(session_token)
+    }
+}
+
+/// Response with the access token of the session and a fresh refresh token for it
+fn session_tokens(keys: &ApiKeys, entity: &AuthEntity, jwt: Option<&JwtConfig>, user: &User, session_token: String, session: Session) -> status::Custom<Json> {
+    let token = match access_token(jwt, user, session_token) {
+        Ok(token) => token,
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    };
+
+    let refresh_token: String = keys.generate().unwrap();
+
+    match entity.add_refresh_token(user.name.as_str(), refresh_token.as_str(), &session) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {"token": token, "refresh_token": refresh_token, "session": session.id}}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Response of the cookie session mode, the access token goes only to the private cookie
+fn session_cookies(cookie_session: &CookieSession, cookies: &mut Cookies, jwt: Option<&JwtConfig>, user: &User, session_token: String, session: Session) -> status::Custom<Json> {
+    let token = match access_token(jwt, user, session_token) {
+        Ok(token) => token,
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    };
+
+    match cookie_session.start(cookies, token.as_str()) {
+        Some(csrf) => status::Custom(Status::Ok, Json(json!({"data": {"session": session.id, "csrf_token": csrf}}))),
+        None => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", AuthError::IOError)})))
+    }
+}
+
+/// Revoke the current session
+///
+/// `cookies` goes last, the token guards have to read the jar before the route borrows it.
+#[post("/users/sign_out", format = "application/json")]
+pub fn sign_out(entity: State<AuthEntity>, token: AccessToken, _user: AuthorizedUser, _csrf: Csrf,
+                cookie_session: Option<State<CookieSession>>, mut cookies: Cookies) -> status::Custom<Json> {
+    match entity.inner().delete_token(token.as_str()) {
+        None => {
+            if let Some(cookie_session) = cookie_session {
+                cookie_session.inner().end(&mut cookies);
+            }
+            status::Custom(Status::Ok, Json(json!({"data": {}})))
+        },
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+#[get("/users/sessions", format = "application/json")]
+pub fn get_sessions(entity: State<AuthEntity>, token: AccessToken, user: AuthorizedUser) -> status::Custom<Json> {
+    let current = entity.inner().get_session_by_token(token.as_str()).map(|s| s.id).ok();
+
+    match entity.inner().list_sessions(user.get_user().name.as_str()) {
+        Ok(sessions) => status::Custom(Status::Ok, Json(json!({"data": sessions, "current": current}))),
+        Err(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+#[delete("/users/sessions/<id>", format = "application/json")]
+pub fn delete_session(entity: State<AuthEntity>, user: AuthorizedUser, _csrf: Csrf, id: String) -> status::Custom<Json> {
+    match entity.inner().delete_session(user.get_user().name.as_str(), id.as_str()) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {}}))),
+        Some(AuthError::NotFound) => status::Custom(Status::NotFound, Json(json!({"error": format!("{}", AuthError::NotFound)}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Sign out everywhere, the current session included
+#[delete("/users/sessions", format = "application/json")]
+pub fn delete_sessions(entity: State<AuthEntity>, user: AuthorizedUser, _csrf: Csrf) -> status::Custom<Json> {
+    match entity.inner().delete_sessions(user.get_user().name.as_str()) {
+        None => status::Custom(Status::Ok, Json(json!({"data": {}}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
 }
 
 /*
@@ -67,10 +625,13 @@ pub fn up_user(entity: State<AuthEntity>, new_user: Json<User>, user: Authorized
 }*/
 
 #[get("/users/user/<id>", format = "application/json")]
-pub fn get_user(user: AuthorizedUser, id: i32, entity: State<AuthEntity>, uri: RequestedUriString) -> Result<status::Custom<Json>, Redirect>  {
+pub fn get_user(user: MeteredUser, id: i32, entity: State<AuthEntity>, uri: RequestedUriString) -> Result<status::Custom<Json>, Redirect>  {
     if user.get_user().id == id {
-        Ok(status::Custom(Status::Ok, Json(json!({"data": user}))))
-    } else if user.get_user().role == Role::Admins {
+        let name = user.get_user().name.as_str();
+        let mfa = entity.inner().get_mfa_secret(name).map(|mfa| mfa.confirmed).unwrap_or(false);
+        let recovery_codes = entity.inner().count_recovery_codes(name).unwrap_or(0);
+        Ok(status::Custom(Status::Ok, Json(json!({"data": user.get_user(), "mfa": {"enabled": mfa, "recovery_codes": recovery_codes}}))))
+    } else if user_has_permission(entity.inner(), user.get_user(), USERS_READ) {
         match entity.inner().get_user_by_id(id) {
             Ok(u) => Ok(status::Custom(Status::Ok, Json(json!({ "data": u })))),
             Err(e) => Ok(status::Custom(Status::NotFound, Json(json!({ "error": format!("{}", e) }))))
@@ -80,16 +641,91 @@ pub fn get_user(user: AuthorizedUser, id: i32, entity: State<AuthEntity>, uri: R
     }
 }
 
+/// Forget the failed sign ins of the user, so a locked user can sign in at once
+#[post("/users/user/<id>/unlock", format = "application/json")]
+pub fn unlock_user(entity: State<AuthEntity>, _admin: Permitted<UsersWrite>, _csrf: Csrf, id: i32, lockout: Option<State<LockoutPolicy>>) -> status::Custom<Json> {
+    let user = match entity.inner().get_user_by_id(id) {
+        Ok(user) => user,
+        Err(e) => return status::Custom(Status::NotFound, Json(json!({"error": format!("{}", e)})))
+    };
+
+    let default = LockoutPolicy::default();
+    match lockout.as_ref().map(|l| l.inner()).unwrap_or(&default).unlock(entity.inner(), user.name.as_str()) {
+        None => status::Custom(Status::Ok, Json(json!({"data": user}))),
+        Some(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Daily and monthly quotas of the user and their usage, the request itself is not counted
+#[get("/users/quota", format = "application/json")]
+pub fn get_quota(entity: State<AuthEntity>, user: AuthorizedUser, policy: Option<State<QuotaPolicy>>) -> status::Custom<Json> {
+    let default = QuotaPolicy::default();
+    match policy.as_ref().map(|p| p.inner()).unwrap_or(&default).usage(entity.inner(), user.get_user(), Local::now().timestamp()) {
+        Ok(usage) => status::Custom(Status::Ok, Json(json!({"data": usage}))),
+        Err(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+/// Override the quotas of the role for the user, only the periods in the body are changed
+#[put("/users/user/<id>/quota", format = "application/json", data="<quota>")]
+pub fn set_quota(entity: State<AuthEntity>, _admin: Permitted<UsersWrite>, _csrf: Csrf, id: i32, quota: Json<QuotaOverride>,
+                 policy: Option<State<QuotaPolicy>>) -> status::Custom<Json> {
+    let user = match entity.inner().get_user_by_id(id) {
+        Ok(user) => user,
+        Err(e) => return status::Custom(Status::NotFound, Json(json!({"error": format!("{}", e)})))
+    };
+
+    let mut attributes = user.attributes.clone();
+
+    for &(period, ref value) in [(QuotaPeriod::Daily, quota.daily.clone()), (QuotaPeriod::Monthly, quota.monthly.clone())].iter() {
+        match *value {
+            None => (),
+            Some(Value::Null) => {
+                attributes.remove(period.attribute());
+            },
+            Some(ref value) => match quota_attribute(value) {
+                Some(value) => {
+                    attributes.insert(period.attribute().to_string(), value);
+                },
+                None => return status::Custom(Status::BadRequest, Json(json!({
+                    "error": format!("Quota {} must be a non negative number or \"{}\"", period.attribute(), UNLIMITED)
+                })))
+            }
+        }
+    }
+
+    let user = match entity.inner().set_user_attributes(user.name.as_str(), attributes) {
+        Ok(user) => user,
+        Err(e) => return status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    };
+
+    let default = QuotaPolicy::default();
+    match policy.as_ref().map(|p| p.inner()).unwrap_or(&default).usage(entity.inner(), &user, Local::now().timestamp()) {
+        Ok(usage) => status::Custom(Status::Ok, Json(json!({"data": user, "quota": usage}))),
+        Err(e) => status::Custom(Status::InternalServerError, Json(json!({"error": format!("{}", e)})))
+    }
+}
+
+fn quota_attribute(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref s) if s == UNLIMITED => Some(UNLIMITED.to_string()),
+        Value::Number(ref n) => n.as_i64().and_then(|n| if n >= 0 { Some(n.to_string()) } else { None }),
+        _ => None
+    }
+}
+
 #[get("/users/list", format = "application/json")]
-pub fn get_user_list(entity: State<AuthEntity>, user: AdminUser) -> Json {
+pub fn get_user_list(entity: State<AuthEntity>, _admin: Permitted<UsersRead>) -> Json {
     Json(json!({"data": entity.list_users(0, 10).unwrap()}))
 }
 
 #[get("/users/list?<limit>", format = "application/json")]
-pub fn get_user_list_with_limit(entity: State<AuthEntity>, limit: LimitOffset, user: AdminUser) -> Json {
+pub fn get_user_list_with_limit(entity: State<AuthEntity>, limit: LimitOffset, _admin: Permitted<UsersRead>) -> Json {
     Json(json!({"data": entity.list_users(limit.get_limit(), limit.get_offset()).unwrap()}))
 }
 
 pub fn get_user_routes() -> Vec<Route> {
-    routes!( sign_up, get_user, sign_in, get_user_list, get_user_list_with_limit)
+    routes!( sign_up, verify_email, forgot_password, reset_password, change_password, get_user, sign_in, sign_in_mfa,
+             enroll_totp, confirm_totp, disable_totp, regenerate_recovery_codes, refresh_token, sign_out, get_sessions, delete_session, delete_sessions, unlock_user,
+             get_quota, set_quota, get_user_list, get_user_list_with_limit)
 }
@@ -1,5 +1,5 @@
-use super::{ Entity, User, AuthError, Role, PrivateUser };
-use std::collections::HashMap;
+use super::{ Entity, User, AuthError, Role, PrivateUser, Session, ClientInfo, RefreshToken, MfaSecret };
+use std::collections::{ HashMap, BTreeSet };
 
 pub struct AuthEntity {
     component: Box<Entity>
@@ -63,7 +63,119 @@ impl Entity for AuthEntity {
         self.component.delete_token(token)
     }
 
+    fn add_session(&self, username: &str, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        self.component.add_session(username, token, client)
+    }
+
+    fn renew_session(&self, username: &str, session_id: &str, created_at: i64, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        self.component.renew_session(username, session_id, created_at, token, client)
+    }
+
+    fn get_session_by_token(&self, token: &str) -> Result<Session, AuthError> {
+        self.component.get_session_by_token(token)
+    }
+
+    fn list_sessions(&self, username: &str) -> Result<Vec<Session>, AuthError> {
+        self.component.list_sessions(username)
+    }
+
+    fn delete_session(&self, username: &str, session_id: &str) -> Option<AuthError> {
+        self.component.delete_session(username, session_id)
+    }
+
+    fn delete_sessions(&self, username: &str) -> Option<AuthError> {
+        self.component.delete_sessions(username)
+    }
+
+    fn add_refresh_token(&self, username: &str, token: &str, session: &Session) -> Option<AuthError> {
+        self.component.add_refresh_token(username, token, session)
+    }
+
+    fn take_refresh_token(&self, token: &str) -> Result<RefreshToken, AuthError> {
+        self.component.take_refresh_token(token)
+    }
+
+    fn add_ticket(&self, kind: &str, ticket: &str, username: &str, ttl: i64) -> Option<AuthError> {
+        self.component.add_ticket(kind, ticket, username, ttl)
+    }
+
+    fn get_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        self.component.get_ticket(kind, ticket)
+    }
+
+    fn take_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        self.component.take_ticket(kind, ticket)
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        self.component.set_password(username, password)
+    }
+
+    fn set_mfa_secret(&self, username: &str, secret: Option<MfaSecret>) -> Option<AuthError> {
+        self.component.set_mfa_secret(username, secret)
+    }
+
+    fn get_mfa_secret(&self, username: &str) -> Result<MfaSecret, AuthError> {
+        self.component.get_mfa_secret(username)
+    }
+
+    fn set_recovery_codes(&self, username: &str, codes: &[String]) -> Option<AuthError> {
+        self.component.set_recovery_codes(username, codes)
+    }
+
+    fn take_recovery_code(&self, username: &str, code: &str) -> Option<AuthError> {
+        self.component.take_recovery_code(username, code)
+    }
+
+    fn count_recovery_codes(&self, username: &str) -> Result<usize, AuthError> {
+        self.component.count_recovery_codes(username)
+    }
+
+    fn incr_counter(&self, key: &str, ttl: i64) -> Result<i64, AuthError> {
+        self.component.incr_counter(key, ttl)
+    }
+
+    fn get_counter(&self, key: &str) -> Result<i64, AuthError> {
+        self.component.get_counter(key)
+    }
+
+    fn set_counter(&self, key: &str, value: i64, ttl: i64) -> Option<AuthError> {
+        self.component.set_counter(key, value, ttl)
+    }
+
+    fn reset_counter(&self, key: &str) -> Option<AuthError> {
+        self.component.reset_counter(key)
+    }
+
+    fn set_user_attributes(&self, username: &str, attributes: HashMap<String, String>) -> Result<User, AuthError> {
+        self.component.set_user_attributes(username, attributes)
+    }
+
     fn add_user_role(&self, username: &str, role: Role) -> Result<User, AuthError> {
         self.component.add_user_role(username, role)
     }
+
+    fn remove_user_role(&self, username: &str, role: &Role) -> Result<User, AuthError> {
+        self.component.remove_user_role(username, role)
+    }
+
+    fn list_user_roles(&self, username: &str) -> Result<Vec<Role>, AuthError> {
+        self.component.list_user_roles(username)
+    }
+
+    fn grant_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        self.component.grant_permission(role, permission)
+    }
+
+    fn revoke_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        self.component.revoke_permission(role, permission)
+    }
+
+    fn list_permissions(&self, role: &Role) -> Result<Vec<String>, AuthError> {
+        self.component.list_permissions(role)
+    }
+
+    fn user_permissions(&self, user: &User) -> Result<BTreeSet<String>, AuthError> {
+        self.component.user_permissions(user)
+    }
 }
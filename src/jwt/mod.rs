@@ -0,0 +1,412 @@
+use std::fmt;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::collections::{ HashMap, BTreeSet };
+use base64;
+use serde_json;
+use crypto::util::fixed_time_eq;
+use ring::rand::SystemRandom;
+use ring::signature::{ self, Ed25519KeyPair, RSAKeyPair, RSASigningState };
+use untrusted::Input;
+use ::{ User, Role, UserStatus };
+use ::net::key::{ PrivateKey, KeyRing, hmac_sha256 };
+
+/// Fifteen minutes
+const DEFAULT_TTL: i64 = 900;
+
+#[derive(Debug, PartialEq)]
+pub enum JwtError {
+    /// Token is not three base64url parts with JSON header and claims
+    Malformed,
+    /// Token is signed by another key or was changed
+    InvalidSignature,
+    /// Token is signed with another algorithm than the configured one
+    UnsupportedAlgorithm,
+    /// `exp` claim is in the past
+    Expired,
+    /// Key material cannot be parsed
+    InvalidKey,
+    /// Configuration has only the public key, or the signer failed
+    CannotSign
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.description())
+    }
+}
+
+impl Error for JwtError {
+    fn description(&self) -> &str {
+        match *self {
+            JwtError::Malformed => "Token is malformed",
+            JwtError::InvalidSignature => "Token signature is invalid",
+            JwtError::UnsupportedAlgorithm => "Token is signed with unsupported algorithm",
+            JwtError::Expired => "Token is expired",
+            JwtError::InvalidKey => "Cannot parse the key",
+            JwtError::CannotSign => "Cannot sign the token"
+        }
+    }
+}
+
+/// Claims of access tokens issued by `sign_in`
+///
+/// `jti` is the token of the session in the entity, so the session can be revoked.
+/// Only what the guards need is signed, email and attributes stay out of the token.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Claims {
+    /// User id
+    pub sub: String,
+    pub name: String,
+    pub status: UserStatus,
+    pub roles: BTreeSet<Role>,
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String
+}
+
+impl Claims {
+    pub fn new(user: &User, jti: &str, now: i64, ttl: i64) -> Self {
+        Claims {
+            sub: user.id.to_string(),
+            name: user.name.clone(),
+            status: user.status.clone(),
+            roles: user.roles.clone(),
+            exp: now + ttl,
+            iat: now,
+            jti: jti.to_string()
+        }
+    }
+
+    /// User known from the claims, without email and attributes which are not signed
+    ///
+    /// Turn the revocation check of `JwtConfig` on to get the whole user from the entity.
+    pub fn to_user(&self) -> Result<User, JwtError> {
+        i32::from_str(&self.sub)
+            .map_err(|_| JwtError::Malformed)
+            .map(|id| User {
+                id: id,
+                name: self.name.clone(),
+                email: String::new(),
+                status: self.status.clone(),
+                roles: self.roles.clone(),
+                attributes: HashMap::new()
+            })
+    }
+}
+
+enum Key {
+    Hmac(Vec<u8>),
+    HmacRing { current: String, secrets: HashMap<String, Vec<u8>> },
+    Rsa { private: Option<Arc<RSAKeyPair>>, public: Vec<u8> },
+    Ed25519 { private: Option<Ed25519KeyPair>, public: Vec<u8> }
+}
+
+/// JWT mode of access tokens, put it to the Rocket managed state to enable it
+///
+/// Guards validate tokens locally, the entity is asked only when the revocation check is on.
+///
+/// ```
+/// use auth_rocket::PrivateKey;
+/// use auth_rocket::jwt::{ JwtConfig, JwtError };
+///
+/// let jwt = JwtConfig::hs256(&PrivateKey::new("secret".to_string())).with_ttl(60);
+/// let token = jwt.encode_json(r#"{"sub":"1","exp":1060}"#).unwrap();
+/// assert_eq!(token.split('.').count(), 3);
+///
+/// let other = JwtConfig::hs256(&PrivateKey::new("other".to_string()));
+/// assert_eq!(other.verify_token(&token), Err(JwtError::InvalidSignature));
+/// ```
+pub struct JwtConfig {
+    key: Key,
+    ttl: i64,
+    check_revocation: bool
+}
+
+impl JwtConfig {
+    /// HMAC-SHA256 with the same secret that signs opaque api keys
+    pub fn hs256(private_key: &PrivateKey) -> Self {
+        JwtConfig::new(Key::Hmac(private_key.as_str().as_bytes().to_vec()))
+    }
+
+    /// HMAC-SHA256 with the secrets of the `KeyRing`, so they are rotated together with api keys
+    ///
+    /// Tokens are signed by the current secret and carry its id in the `kid` header,
+    /// the verification secret is chosen by that id. Tokens without `kid` are checked against every secret.
+    ///
+    /// ```
+    /// use auth_rocket::KeyRing;
+    /// use auth_rocket::jwt::{ JwtConfig, JwtError };
+    ///
+    /// let old = JwtConfig::hs256_ring(&KeyRing::new("2018-01", "old secret".to_string()));
+    /// let token = old.encode_json(r#"{"sub":"1","exp":1060}"#).unwrap();
+    ///
+    /// let ring = KeyRing::new("2018-06", "new secret".to_string())
+    ///     .with_verification_key("2018-01", "old secret".to_string());
+    /// assert!(JwtConfig::hs256_ring(&ring).verify_token(&token).is_ok());
+    ///
+    /// let rotated = KeyRing::new("2018-06", "new secret".to_string());
+    /// assert_eq!(JwtConfig::hs256_ring(&rotated).verify_token(&token), Err(JwtError::InvalidSignature));
+    /// ```
+    pub fn hs256_ring(ring: &KeyRing) -> Self {
+        let secrets = ring.secrets().iter()
+            .map(|(kid, secret)| (kid.clone(), secret.as_bytes().to_vec()))
+            .collect();
+
+        JwtConfig::new(Key::HmacRing { current: ring.current_kid().to_string(), secrets: secrets })
+    }
+
+    /// RSASSA-PKCS1-v1_5 with SHA-256, keys are DER encoded PKCS#1 `RSAPrivateKey` and `RSAPublicKey`
+    ///
+    /// Services which only validate tokens pass `None` instead of the private key.
+    pub fn rs256(private_key: Option<&[u8]>, public_key: &[u8]) -> Result<Self, JwtError> {
+        let private = match private_key {
+            Some(der) => Some(Arc::new(RSAKeyPair::from_der(Input::from(der)).map_err(|_| JwtError::InvalidKey)?)),
+            None => None
+        };
+
+        Ok(JwtConfig::new(Key::Rsa { private: private, public: public_key.to_vec() }))
+    }
+
+    /// Ed25519, the private key is PKCS#8 document and the public key is raw 32 bytes
+    ///
+    /// Services which only validate tokens pass `None` instead of the private key.
+    pub fn eddsa(private_key: Option<&[u8]>, public_key: &[u8]) -> Result<Self, JwtError> {
+        let private = match private_key {
+            Some(pkcs8) => Some(Ed25519KeyPair::from_pkcs8(Input::from(pkcs8)).map_err(|_| JwtError::InvalidKey)?),
+            None => None
+        };
+
+        Ok(JwtConfig::new(Key::Ed25519 { private: private, public: public_key.to_vec() }))
+    }
+
+    fn new(key: Key) -> Self {
+        JwtConfig {
+            key: key,
+            ttl: DEFAULT_TTL,
+            check_revocation: false
+        }
+    }
+
+    /// Seconds issued tokens live
+    pub fn with_ttl(mut self, ttl: i64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Ask the entity on every request whether the session of the token is still alive,
+    /// the guards then take the current user from the entity instead of the claims
+    pub fn with_revocation_check(mut self, check_revocation: bool) -> Self {
+        self.check_revocation = check_revocation;
+        self
+    }
+
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    pub fn check_revocation(&self) -> bool {
+        self.check_revocation
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self.key {
+            Key::Hmac(_) | Key::HmacRing { .. } => "HS256",
+            Key::Rsa { .. } => "RS256",
+            Key::Ed25519 { .. } => "EdDSA"
+        }
+    }
+
+    pub fn encode(&self, claims: &Claims) -> Result<String, JwtError> {
+        serde_json::to_string(claims)
+            .map_err(|_| JwtError::Malformed)
+            .and_then(|json| self.encode_json(&json))
+    }
+
+    /// Sign any JSON payload
+    pub fn encode_json(&self, claims: &str) -> Result<String, JwtError> {
+        let header = match self.key {
+            Key::HmacRing { ref current, .. } => json!({"alg": self.algorithm(), "kid": current, "typ": "JWT"}).to_string(),
+            _ => format!("{{\"alg\":\"{}\",\"typ\":\"JWT\"}}", self.algorithm())
+        };
+        let message = format!("{}.{}", encode_part(header.as_bytes()), encode_part(claims.as_bytes()));
+
+        self.sign(message.as_bytes())
+            .map(|signature| format!("{}.{}", message, encode_part(&signature)))
+    }
+
+    /// Validate signature and expiration of the token issued at or before `now`
+    pub fn decode(&self, token: &str, now: i64) -> Result<Claims, JwtError> {
+        let payload = self.verify_token(token)?;
+        let claims: Claims = serde_json::from_slice(&payload).map_err(|_| JwtError::Malformed)?;
+
+        match claims.exp > now {
+            true => Ok(claims),
+            false => Err(JwtError::Expired)
+        }
+    }
+
+    /// Check header and signature, returns the raw claims
+    pub fn verify_token(&self, token: &str) -> Result<Vec<u8>, JwtError> {
+        let parts: Vec<&str> = token.split('.').collect();
+
+        if parts.len() != 3 {
+            return Err(JwtError::Malformed);
+        }
+
+        let header: serde_json::Value = decode_part(parts[0])
+            .and_then(|h| serde_json::from_slice(&h).map_err(|_| JwtError::Malformed))?;
+
+        // the algorithm is fixed by the configuration, the header can't choose it
+        if header["alg"].as_str() != Some(self.algorithm()) {
+            return Err(JwtError::UnsupportedAlgorithm);
+        }
+
+        let signature = decode_part(parts[2])?;
+        let message_len = parts[0].len() + 1 + parts[1].len();
+
+        match self.verify(header["kid"].as_str(), token[..message_len].as_bytes(), &signature) {
+            true => decode_part(parts[1]),
+            false => Err(JwtError::InvalidSignature)
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, JwtError> {
+        match self.key {
+            Key::Hmac(ref secret) => Ok(hmac_sha256(secret, message)),
+            Key::HmacRing { ref current, ref secrets } => Ok(hmac_sha256(&secrets[current], message)),
+            Key::Rsa { private: Some(ref key_pair), .. } => {
+                let mut state = RSASigningState::new(key_pair.clone()).map_err(|_| JwtError::CannotSign)?;
+                let mut signature = vec![0u8; key_pair.public_modulus_len()];
+
+                state.sign(&signature::RSA_PKCS1_SHA256, &SystemRandom::new(), message, &mut signature)
+                    .map_err(|_| JwtError::CannotSign)
+                    .map(|_| signature)
+            },
+            Key::Ed25519 { private: Some(ref key_pair), .. } => Ok(key_pair.sign(message).as_ref().to_vec()),
+            _ => Err(JwtError::CannotSign)
+        }
+    }
+
+    fn verify(&self, kid: Option<&str>, message: &[u8], sig: &[u8]) -> bool {
+        match self.key {
+            Key::Hmac(ref secret) => fixed_time_eq(&hmac_sha256(secret, message), sig),
+            Key::HmacRing { ref secrets, .. } => match kid {
+                Some(kid) => secrets.get(kid).map(|secret| fixed_time_eq(&hmac_sha256(secret, message), sig)).unwrap_or(false),
+                None => secrets.values().any(|secret| fixed_time_eq(&hmac_sha256(secret, message), sig))
+            },
+            Key::Rsa { ref public, .. } => signature::verify(&signature::RSA_PKCS1_2048_8192_SHA256,
+                                                             Input::from(&public[..]), Input::from(message), Input::from(sig)).is_ok(),
+            Key::Ed25519 { ref public, .. } => signature::verify(&signature::ED25519,
+                                                                 Input::from(&public[..]), Input::from(message), Input::from(sig)).is_ok()
+        }
+    }
+}
+
+fn encode_part(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, JwtError> {
+    base64::decode_config(part, base64::URL_SAFE_NO_PAD).map_err(|_| JwtError::Malformed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ JwtConfig, JwtError, Claims, decode_part };
+    use ::{ User, Role, UserStatus };
+    use ::net::key::{ PrivateKey, KeyRing };
+    use ring::rand::SystemRandom;
+    use ring::signature::Ed25519KeyPair;
+    use untrusted::Input;
+    use std::collections::HashMap;
+
+    fn claims() -> Claims {
+        let mut attributes = HashMap::new();
+        attributes.insert("city".to_string(), "Gotham".to_string());
+
+        let user = User {
+            id: 7,
+            name: "batman".to_string(),
+            email: "bruce@example.com".to_string(),
+            status: UserStatus::Active,
+            roles: [Role::Admins].iter().cloned().collect(),
+            attributes: attributes
+        };
+
+        Claims::new(&user, "session token", 1000, 60)
+    }
+
+    #[test]
+    fn test_hs256() {
+        let jwt = JwtConfig::hs256(&PrivateKey::new("secret".to_string()));
+        let token = jwt.encode(&claims()).unwrap();
+
+        assert_eq!(jwt.decode(&token, 1000), Ok(claims()));
+        assert_eq!(jwt.decode(&token, 1060), Err(JwtError::Expired));
+        assert_eq!(jwt.decode(&token[1..], 1000), Err(JwtError::Malformed));
+
+        let user = jwt.decode(&token, 1000).unwrap().to_user().unwrap();
+        assert_eq!((user.id, user.name.as_str(), user.status.clone(), user.has_role(&Role::Admins)), (7, "batman", UserStatus::Active, true));
+
+        // the personal data doesn't leave the server
+        let payload = String::from_utf8(jwt.verify_token(&token).unwrap()).unwrap();
+        assert!(!payload.contains("bruce@example.com") && !payload.contains("Gotham"));
+        assert!(user.email.is_empty() && user.attributes.is_empty());
+
+        let parts: Vec<&str> = token.split('.').collect();
+        let forged = jwt.encode_json(r#"{"sub":"1"}"#).unwrap();
+        let forged: Vec<&str> = forged.split('.').collect();
+        assert_eq!(jwt.decode(&format!("{}.{}.{}", parts[0], forged[1], parts[2]), 1000), Err(JwtError::InvalidSignature));
+        assert_eq!(jwt.decode(&format!("eyJhbGciOiJub25lIn0.{}.", parts[1]), 1000), Err(JwtError::UnsupportedAlgorithm));
+    }
+
+    #[test]
+    fn test_hs256_ring() {
+        let ring = KeyRing::new("new", "new secret".to_string())
+            .with_verification_key("old", "old secret".to_string());
+        let jwt = JwtConfig::hs256_ring(&ring);
+        let token = jwt.encode(&claims()).unwrap();
+
+        let header = String::from_utf8(decode_part(token.split('.').next().unwrap()).unwrap()).unwrap();
+        assert_eq!(header, r#"{"alg":"HS256","kid":"new","typ":"JWT"}"#);
+        assert_eq!(jwt.decode(&token, 1000), Ok(claims()));
+        assert_eq!(JwtConfig::hs256(&PrivateKey::new("new secret".to_string())).decode(&token, 1000), Ok(claims()));
+
+        // issued before the rotation, by the ring or by the single secret
+        let old = JwtConfig::hs256_ring(&KeyRing::new("old", "old secret".to_string())).encode(&claims()).unwrap();
+        assert_eq!(jwt.decode(&old, 1000), Ok(claims()));
+        let single = JwtConfig::hs256(&PrivateKey::new("old secret".to_string())).encode(&claims()).unwrap();
+        assert_eq!(jwt.decode(&single, 1000), Ok(claims()));
+
+        // the id chooses the secret, a token of the old secret can't claim the new id
+        let unknown = JwtConfig::hs256_ring(&KeyRing::new("unknown", "new secret".to_string())).encode(&claims()).unwrap();
+        assert_eq!(jwt.decode(&unknown, 1000), Err(JwtError::InvalidSignature));
+        let forged = JwtConfig::hs256_ring(&KeyRing::new("new", "old secret".to_string())).encode(&claims()).unwrap();
+        assert_eq!(jwt.decode(&forged, 1000), Err(JwtError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_missing_claims() {
+        let jwt = JwtConfig::hs256(&PrivateKey::new("secret".to_string()));
+        let token = jwt.encode_json(r#"{"sub":"7","name":"batman","roles":["Admins"],"exp":1060,"iat":1000,"jti":"session token"}"#).unwrap();
+
+        // the status is signed by the issuer, it is never assumed
+        assert_eq!(jwt.decode(&token, 1000), Err(JwtError::Malformed));
+    }
+
+    #[test]
+    fn test_eddsa() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let public = Ed25519KeyPair::from_pkcs8(Input::from(&pkcs8[..])).unwrap().public_key_bytes().to_vec();
+
+        let issuer = JwtConfig::eddsa(Some(&pkcs8[..]), &public).unwrap();
+        let validator = JwtConfig::eddsa(None, &public).unwrap();
+        let token = issuer.encode(&claims()).unwrap();
+
+        assert_eq!(validator.decode(&token, 1000), Ok(claims()));
+        assert_eq!(validator.encode(&claims()), Err(JwtError::CannotSign));
+        assert_eq!(JwtConfig::hs256(&PrivateKey::new("secret".to_string())).decode(&token, 1000), Err(JwtError::UnsupportedAlgorithm));
+    }
+}
@@ -4,6 +4,7 @@ extern crate rocket;
 
 #[macro_use]
 extern crate log;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
@@ -11,27 +12,46 @@ extern crate r2d2;
 extern crate crypto;
 extern crate chrono;
 extern crate rand;
+extern crate argon2;
+extern crate bcrypt;
+extern crate base64;
+extern crate ring;
+extern crate untrusted;
 #[macro_use]
 extern crate rocket_contrib;
 
 #[cfg(feature="with-redis")] extern crate redis;
 #[cfg(feature="with-redis")] extern crate r2d2_redis;
 #[cfg(feature="with-redis")] pub mod redisdb;
+#[cfg(feature="with-sql")] extern crate rusqlite;
+#[cfg(feature="with-sql")] extern crate r2d2_sqlite;
+#[cfg(feature="with-sql")] pub mod sqldb;
 
 mod decorator;
+mod paging;
+pub mod memorydb;
 pub mod limitation;
 pub mod net;
+pub mod password;
+pub mod session;
+pub mod jwt;
+pub mod mail;
+pub mod mfa;
 pub mod api;
 
 use std::fmt;
 use std::error::Error;
 use std::str::FromStr;
-use std::collections::HashMap;
+use std::collections::{ HashMap, BTreeSet };
 use std::convert::From;
 
 pub use decorator::AuthEntity;
-pub use net::key::{ generate_api_key, PrivateKey };
-pub use limitation::user::{ AuthorizedUser, AdminUser, user_from_request };
+pub use net::key::{ generate_api_key, PrivateKey, KeyRing, ApiKeys };
+pub use limitation::user::{ AuthorizedUser, AdminUser, AccessToken, user_from_request, token_from_request };
+pub use limitation::permission::{ Permitted, RequiredPermission };
+pub use net::client::ClientInfo;
+pub use session::{ Session, TokenPolicy, RefreshToken };
+pub use mfa::MfaSecret;
 
 #[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 pub enum UserStatus {
@@ -52,7 +72,7 @@ impl fmt::Display for UserStatus {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Role {
     Users,
     Admins,
@@ -88,7 +108,7 @@ pub struct PrivateUser {
     pub email: String,
     password: String,
     pub status: UserStatus,
-    pub role: Role,
+    pub roles: BTreeSet<Role>,
     pub attributes: HashMap<String, String>
 }
 
@@ -98,7 +118,7 @@ pub struct User {
     pub name: String,
     pub email: String,
     pub status: UserStatus,
-    pub role: Role,
+    pub roles: BTreeSet<Role>,
     pub attributes: HashMap<String, String>
 }
 
@@ -109,16 +129,24 @@ impl From<PrivateUser> for User {
             name: user.name,
             email: user.email,
             status: user.status,
-            role: user.role,
+            roles: user.roles,
             attributes: user.attributes
         }
     }
 }
 
+impl User {
+    pub fn has_role(&self, role: &Role) -> bool {
+        self.roles.contains(role)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum AuthError {
     /// The error thrown by entity  if user with same name exists
     DuplicateUsername,
+    /// The error thrown by entity if user with same email exists
+    DuplicateEmail,
     /// The error thrown by entity if cannot found in DB
     NotFound,
     /// The error thrown by entity if have some error with DB
@@ -128,7 +156,11 @@ pub enum AuthError {
     /// The error thrown by entity if user not active
     NotActive,
     /// The error thrown by entity if user is disabled
-    DisabledUser
+    DisabledUser,
+    /// The error thrown if user is locked out after too many failed sign ins
+    Locked,
+    /// The error thrown if user has used up its request quota
+    QuotaExceeded
 }
 
 impl fmt::Display for AuthError {
@@ -141,11 +173,14 @@ impl Error for AuthError {
     fn description(&self) -> &str {
         match *self {
             AuthError::DuplicateUsername => "User with that name already exists",
+            AuthError::DuplicateEmail => "User with that email already exists",
             AuthError::NotFound => "Cannot find user with that parameters",
             AuthError::IOError => "Some problem with DB",
             AuthError::AccessDenied => "You don't have permissions to that resource",
             AuthError::NotActive => "Sorry, but this user is not activated",
             AuthError::DisabledUser => "Sorry, but this user is disabled",
+            AuthError::Locked => "Too many failed attempts, try again later",
+            AuthError::QuotaExceeded => "Request quota exceeded, try again later",
         }
     }
 }
@@ -159,9 +194,74 @@ pub trait Entity: Send + Sync + 'static {
     fn list_users(&self, from: isize, count:isize) -> Result<Vec<User>, AuthError>;
     fn enable_user(&self, username: &str) -> Result<User, AuthError>;
     fn disable_user(&self, username: &str) -> Result<User, AuthError>;
+    /// Token of the latest alive session of the user
     fn get_token(&self, username: &str) -> Result<String, AuthError>;
-    fn add_token(&self, username: &str, token: &str) -> Option<AuthError>;
+    fn add_token(&self, username: &str, token: &str) -> Option<AuthError> {
+        self.add_session(username, token, &ClientInfo::default()).err()
+    }
     fn get_user_by_token(&self, token: &str) -> Result<User, AuthError>;
+    /// Revoke the session which uses that token together with its refresh tokens
     fn delete_token(&self, token: &str) -> Option<AuthError>;
+    fn add_session(&self, username: &str, token: &str, client: &ClientInfo) -> Result<Session, AuthError>;
+    /// Replace access token of the session, the session is recreated if it has already expired
+    ///
+    /// The session keeps `created_at`, so it can't be renewed past `TokenPolicy::max_lifetime`, `AuthError::NotFound` then.
+    fn renew_session(&self, username: &str, session_id: &str, created_at: i64, token: &str, client: &ClientInfo) -> Result<Session, AuthError>;
+    fn get_session_by_token(&self, token: &str) -> Result<Session, AuthError>;
+    fn list_sessions(&self, username: &str) -> Result<Vec<Session>, AuthError>;
+    /// Revoke the session together with its refresh tokens
+    fn delete_session(&self, username: &str, session_id: &str) -> Option<AuthError>;
+    /// Revoke every session of the user together with their refresh tokens
+    fn delete_sessions(&self, username: &str) -> Option<AuthError>;
+    /// Store refresh token of the session, it expires after `TokenPolicy::refresh_lifetime` or with the session
+    fn add_refresh_token(&self, username: &str, token: &str, session: &Session) -> Option<AuthError>;
+    /// Mark refresh token as used and return it as it was, so a replayed token comes back with `used` set
+    fn take_refresh_token(&self, token: &str) -> Result<RefreshToken, AuthError>;
+    /// Store single-use ticket of that kind (e.g. `session::ticket::VERIFY_EMAIL`) for `ttl` seconds
+    fn add_ticket(&self, kind: &str, ticket: &str, username: &str, ttl: i64) -> Option<AuthError>;
+    /// Name of the user of the ticket without taking it, `AuthError::NotFound` if it is expired or already taken
+    fn get_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError>;
+    /// Delete the ticket and return the name of its user, `AuthError::NotFound` if it is expired or already taken
+    fn take_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError>;
+    /// Hash the new password with the current algorithm, existing sessions are left as they are
+    fn set_password(&self, username: &str, password: &str) -> Result<User, AuthError>;
+    /// Store the TOTP secret of the user, `None` turns the second factor off
+    fn set_mfa_secret(&self, username: &str, secret: Option<MfaSecret>) -> Option<AuthError>;
+    /// TOTP secret of the user, `AuthError::NotFound` if there is none
+    fn get_mfa_secret(&self, username: &str) -> Result<MfaSecret, AuthError>;
+    /// Replace the recovery codes of the user by the new batch of hashes
+    fn set_recovery_codes(&self, username: &str, codes: &[String]) -> Option<AuthError>;
+    /// Delete the recovery code, `AuthError::NotFound` if the user has no such code
+    fn take_recovery_code(&self, username: &str, code: &str) -> Option<AuthError>;
+    fn count_recovery_codes(&self, username: &str) -> Result<usize, AuthError>;
+    /// Increment the counter and return the new value, a new counter lives `ttl` seconds
+    fn incr_counter(&self, key: &str, ttl: i64) -> Result<i64, AuthError>;
+    /// Value of the counter, 0 if it doesn't exist or is expired
+    fn get_counter(&self, key: &str) -> Result<i64, AuthError>;
+    fn set_counter(&self, key: &str, value: i64, ttl: i64) -> Option<AuthError>;
+    fn reset_counter(&self, key: &str) -> Option<AuthError>;
+    /// Replace all the attributes of the user
+    fn set_user_attributes(&self, username: &str, attributes: HashMap<String, String>) -> Result<User, AuthError>;
+    /// Give the role to the user, the roles it already has are kept
     fn add_user_role(&self, username: &str, role: Role) -> Result<User, AuthError>;
+    /// Take the role from the user, nothing happens if the user doesn't have it
+    fn remove_user_role(&self, username: &str, role: &Role) -> Result<User, AuthError>;
+    fn list_user_roles(&self, username: &str) -> Result<Vec<Role>, AuthError> {
+        self.get_user_by_name(username).map(|user| user.roles.into_iter().collect())
+    }
+    /// Let every user of the role do what the permission allows, see `limitation::permission`
+    fn grant_permission(&self, role: &Role, permission: &str) -> Option<AuthError>;
+    fn revoke_permission(&self, role: &Role, permission: &str) -> Option<AuthError>;
+    fn list_permissions(&self, role: &Role) -> Result<Vec<String>, AuthError>;
+    /// Permissions of all the roles of the user, `Admins` are granted everything as before permissions
+    fn user_permissions(&self, user: &User) -> Result<BTreeSet<String>, AuthError> {
+        let mut permissions = BTreeSet::new();
+        if user.has_role(&Role::Admins) {
+            permissions.insert("*".to_string());
+        }
+        for role in &user.roles {
+            permissions.extend(self.list_permissions(role)?);
+        }
+        Ok(permissions)
+    }
 }
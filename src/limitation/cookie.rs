@@ -0,0 +1,135 @@
+use base64;
+use crypto::util::fixed_time_eq;
+use rocket::Outcome;
+use rocket::http::{ Status, Cookie, Cookies, SameSite };
+use rocket::request::{ self, Request, FromRequest, State };
+use ::net::secure_random_bytes;
+use ::limitation::source::{ TokenSources, TokenSource };
+
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// Cookie session mode for browsers, put it to the Rocket managed state to enable it
+///
+/// `sign_in` keeps the access token in a private (encrypted) HttpOnly cookie instead
+/// of the response body, and sets a readable CSRF cookie. The frontend sends the value
+/// of that cookie back in the CSRF header with every state-changing request.
+///
+/// ```
+/// use auth_rocket::limitation::cookie::CookieSession;
+///
+/// let cookie_session = CookieSession::new().with_secure(false);
+/// assert_eq!(cookie_session.csrf_header(), "X-CSRF-Token");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CookieSession {
+    name: String,
+    csrf_cookie: String,
+    csrf_header: String,
+    secure: bool
+}
+
+impl CookieSession {
+    pub fn new() -> Self {
+        CookieSession {
+            name: "auth_session".to_string(),
+            csrf_cookie: "csrf_token".to_string(),
+            csrf_header: "X-CSRF-Token".to_string(),
+            secure: true
+        }
+    }
+
+    /// Names of the session cookie, the CSRF cookie and the CSRF header
+    pub fn with_names(mut self, name: &str, csrf_cookie: &str, csrf_header: &str) -> Self {
+        self.name = name.to_string();
+        self.csrf_cookie = csrf_cookie.to_string();
+        self.csrf_header = csrf_header.to_string();
+        self
+    }
+
+    /// Send cookies only over HTTPS, turn it off for local development only
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn csrf_header(&self) -> &str {
+        &self.csrf_header
+    }
+
+    /// Default token sources followed by the session cookie
+    pub fn token_sources(&self) -> TokenSources {
+        let mut sources = TokenSources::default().sources().to_vec();
+        sources.push(TokenSource::Cookie(self.name.clone()));
+        TokenSources::new(sources)
+    }
+
+    /// Set the session and CSRF cookies, returns the CSRF token
+    pub fn start(&self, cookies: &mut Cookies, token: &str) -> Option<String> {
+        secure_random_bytes(CSRF_TOKEN_LENGTH).map(|bytes| {
+            let csrf = base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD);
+
+            cookies.add_private(Cookie::build(self.name.clone(), token.to_string())
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .secure(self.secure)
+                .finish());
+
+            cookies.add(Cookie::build(self.csrf_cookie.clone(), csrf.clone())
+                .path("/")
+                .http_only(false)
+                .same_site(SameSite::Strict)
+                .secure(self.secure)
+                .finish());
+
+            csrf
+        })
+    }
+
+    /// Remove the session and CSRF cookies
+    pub fn end(&self, cookies: &mut Cookies) {
+        cookies.remove_private(Cookie::build(self.name.clone(), "").path("/").finish());
+        cookies.remove(Cookie::build(self.csrf_cookie.clone(), "").path("/").finish());
+    }
+}
+
+impl Default for CookieSession {
+    fn default() -> Self {
+        CookieSession::new()
+    }
+}
+
+/// Double-submit CSRF check for state-changing routes, put it next to `AuthorizedUser`
+///
+/// Requests authorized by the session cookie must repeat the CSRF cookie in the CSRF header.
+/// Requests without the session cookie pass, headers can't be forged cross-site.
+pub struct Csrf(());
+
+impl<'a, 'r> FromRequest<'a, 'r> for Csrf {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Csrf, ()> {
+        let config = match request.guard::<State<CookieSession>>() {
+            Outcome::Success(config) => config.inner(),
+            _ => return Outcome::Success(Csrf(()))
+        };
+
+        let mut cookies = request.cookies();
+
+        if cookies.get_private(&config.name).is_none() {
+            return Outcome::Success(Csrf(()));
+        }
+
+        let expected = cookies.get(&config.csrf_cookie).map(|c| c.value().to_string()).unwrap_or(String::new());
+        let headers: Vec<_> = request.headers().get(&config.csrf_header).collect();
+
+        match !expected.is_empty() && headers.len() == 1 && fixed_time_eq(expected.as_bytes(), headers[0].as_bytes()) {
+            true => Outcome::Success(Csrf(())),
+            false => Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::cmp;
+use ::{ Entity, AuthError };
+
+/// Protection of `sign_in` against password guessing, put it to the Rocket managed state to change the default
+///
+/// Failures are counted per username and per client IP through the entity counters.
+/// After a few free attempts every next one has to wait twice as long as the previous,
+/// and the failure which reaches `max_failures` locks the username for `lock_duration` seconds.
+///
+/// ```
+/// use auth_rocket::limitation::lockout::LockoutPolicy;
+///
+/// let policy = LockoutPolicy::new().with_max_failures(5, 100).with_lock_duration(600);
+/// assert_eq!(policy.delay(3), 0);
+/// assert_eq!(policy.delay(4), 1);
+/// assert_eq!(policy.delay(5), 600);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockoutPolicy {
+    max_failures: i64,
+    max_ip_failures: i64,
+    free_attempts: i64,
+    base_delay: i64,
+    max_delay: i64,
+    lock_duration: i64
+}
+
+impl LockoutPolicy {
+    /// 10 failures of a username or 100 of an IP lock for 15 minutes, delays start after 3 failures
+    pub fn new() -> Self {
+        LockoutPolicy {
+            max_failures: 10,
+            max_ip_failures: 100,
+            free_attempts: 3,
+            base_delay: 1,
+            max_delay: 60,
+            lock_duration: 900
+        }
+    }
+
+    /// Failures before the lock, of a username and of an IP
+    pub fn with_max_failures(mut self, per_username: i64, per_ip: i64) -> Self {
+        self.max_failures = per_username;
+        self.max_ip_failures = per_ip;
+        self
+    }
+
+    /// Failures without any delay, then the delay starts at `base` seconds and doubles up to `max`
+    pub fn with_delays(mut self, free_attempts: i64, base: i64, max: i64) -> Self {
+        self.free_attempts = free_attempts;
+        self.base_delay = base;
+        self.max_delay = max;
+        self
+    }
+
+    /// Seconds of the lock, failures are also forgotten after that time
+    pub fn with_lock_duration(mut self, seconds: i64) -> Self {
+        self.lock_duration = seconds;
+        self
+    }
+
+    /// Seconds to wait after the last of `failures` failed attempts of a username
+    pub fn delay(&self, failures: i64) -> i64 {
+        self.delay_for(failures, self.max_failures)
+    }
+
+    fn delay_for(&self, failures: i64, max_failures: i64) -> i64 {
+        if failures >= max_failures {
+            return self.lock_duration;
+        }
+
+        match failures - self.free_attempts {
+            n if n <= 0 => 0,
+            n => cmp::min(self.base_delay.saturating_mul(1i64 << cmp::min(n - 1, 32)), self.max_delay)
+        }
+    }
+
+    /// Seconds the client has to wait before the next attempt, 0 if it may try now
+    pub fn retry_after(&self, entity: &Entity, username: &str, ip: Option<&str>, now: i64) -> Result<i64, AuthError> {
+        let mut wait = self.wait(entity, &user_key(username), self.max_failures, now)?;
+
+        if let Some(ip) = ip {
+            wait = cmp::max(wait, self.wait(entity, &ip_key(ip), self.max_ip_failures, now)?);
+        }
+
+        Ok(wait)
+    }
+
+    fn wait(&self, entity: &Entity, key: &str, max_failures: i64, now: i64) -> Result<i64, AuthError> {
+        let locked_until = entity.get_counter(&lock_key(key))?;
+
+        if locked_until > now {
+            return Ok(locked_until - now);
+        }
+
+        let failures = entity.get_counter(key)?;
+
+        if failures == 0 {
+            return Ok(0);
+        }
+
+        let last = entity.get_counter(&last_key(key))?;
+        Ok(cmp::max(last + self.delay_for(failures, max_failures) - now, 0))
+    }
+
+    /// Count the failed attempt for the username and the IP
+    pub fn record_failure(&self, entity: &Entity, username: &str, ip: Option<&str>, now: i64) -> Option<AuthError> {
+        let mut keys = vec!((user_key(username), self.max_failures));
+        if let Some(ip) = ip {
+            keys.push((ip_key(ip), self.max_ip_failures));
+        }
+
+        for &(ref key, max_failures) in &keys {
+            let failures = match entity.incr_counter(key, self.lock_duration) {
+                Ok(failures) => failures,
+                Err(e) => return Some(e)
+            };
+
+            if let Some(e) = entity.set_counter(&last_key(key), now, self.lock_duration) {
+                return Some(e);
+            }
+
+            // the counter expires `lock_duration` after the first failure, the lock has to last from this one
+            if failures >= max_failures {
+                if let Some(e) = entity.set_counter(&lock_key(key), now + self.lock_duration, self.lock_duration) {
+                    return Some(e);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Forget the failures of the username, after a successful sign in or by an admin
+    pub fn unlock(&self, entity: &Entity, username: &str) -> Option<AuthError> {
+        let key = user_key(username);
+        entity.reset_counter(&key)
+            .or_else(|| entity.reset_counter(&last_key(&key)))
+            .or_else(|| entity.reset_counter(&lock_key(&key)))
+    }
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy::new()
+    }
+}
+
+fn user_key(username: &str) -> String {
+    format!("lockout:user:{}", username)
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("lockout:ip:{}", ip)
+}
+
+fn last_key(key: &str) -> String {
+    format!("{}:last", key)
+}
+
+fn lock_key(key: &str) -> String {
+    format!("{}:locked", key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ LockoutPolicy, user_key };
+    use ::Entity;
+    use ::memorydb::MemoryEntity;
+
+    #[test]
+    fn test_delays() {
+        let policy = LockoutPolicy::new().with_delays(2, 2, 10);
+        assert_eq!((0..10).map(|n| policy.delay(n)).collect::<Vec<_>>(), vec!(0, 0, 0, 2, 4, 8, 10, 10, 10, 10));
+        assert_eq!(policy.delay(10), 900);
+        assert_eq!(policy.delay(1_000), 900);
+    }
+
+    #[test]
+    fn test_lockout() {
+        let entity = MemoryEntity::new();
+        let policy = LockoutPolicy::new().with_max_failures(3, 5).with_delays(1, 10, 60);
+        let now = 1_500_000_000;
+
+        assert_eq!(policy.retry_after(&entity, "user", Some("10.0.0.1"), now), Ok(0));
+        policy.record_failure(&entity, "user", Some("10.0.0.1"), now);
+        assert_eq!(policy.retry_after(&entity, "user", Some("10.0.0.1"), now), Ok(0));
+        policy.record_failure(&entity, "user", Some("10.0.0.1"), now);
+        assert_eq!(policy.retry_after(&entity, "user", Some("10.0.0.1"), now + 4), Ok(6));
+        policy.record_failure(&entity, "user", Some("10.0.0.1"), now + 10);
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 10), Ok(900));
+
+        // the IP isn't locked yet, other users from it only wait
+        assert_eq!(policy.retry_after(&entity, "other", Some("10.0.0.1"), now + 10), Ok(20));
+
+        assert_eq!(policy.unlock(&entity, "user"), None);
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 10), Ok(0));
+    }
+
+    #[test]
+    fn test_lock_counts_from_last_failure() {
+        let entity = MemoryEntity::new();
+        let policy = LockoutPolicy::new().with_max_failures(3, 100).with_delays(3, 1, 60);
+        let now = 1_500_000_000;
+
+        policy.record_failure(&entity, "user", None, now);
+        policy.record_failure(&entity, "user", None, now + 400);
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 800), Ok(0));
+        policy.record_failure(&entity, "user", None, now + 800);
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 800), Ok(900));
+
+        // the failures are forgotten 900 seconds after the first one, the lock stays
+        assert_eq!(entity.reset_counter(&user_key("user")), None);
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 1000), Ok(700));
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 1700), Ok(0));
+
+        policy.record_failure(&entity, "user", None, now + 900);
+        assert_eq!(policy.unlock(&entity, "user"), None);
+        assert_eq!(policy.retry_after(&entity, "user", None, now + 900), Ok(0));
+    }
+}
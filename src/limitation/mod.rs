@@ -1 +1,7 @@
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod source;
+pub mod cookie;
+pub mod lockout;
+pub mod rate;
+pub mod quota;
+pub mod permission;
@@ -0,0 +1,152 @@
+use std::marker::PhantomData;
+use rocket::Outcome;
+use rocket::http::Status;
+use rocket::request::{ self, Request, FromRequest, State };
+use ::{ Entity, AuthEntity, User };
+use ::limitation::user::user_from_request;
+
+/// Read users and their sessions
+pub const USERS_READ: &'static str = "users:read";
+/// Change, lock and unlock users
+pub const USERS_WRITE: &'static str = "users:write";
+
+/// Permission a route requires from `Permitted`
+///
+/// ```
+/// use auth_rocket::limitation::permission::{ RequiredPermission, Permitted };
+///
+/// struct EnterCave;
+///
+/// impl RequiredPermission for EnterCave {
+///     fn permission() -> &'static str {
+///         "cave:enter"
+///     }
+/// }
+///
+/// // a route guard which lets in only users whose roles are granted "cave:enter"
+/// type CaveVisitor = Permitted<EnterCave>;
+/// ```
+pub trait RequiredPermission {
+    fn permission() -> &'static str;
+}
+
+pub struct UsersRead;
+
+impl RequiredPermission for UsersRead {
+    fn permission() -> &'static str {
+        USERS_READ
+    }
+}
+
+pub struct UsersWrite;
+
+impl RequiredPermission for UsersWrite {
+    fn permission() -> &'static str {
+        USERS_WRITE
+    }
+}
+
+/// Whether the granted permission covers the required one
+///
+/// Permissions are `:` separated paths, `*` grants everything and `users:*` everything under `users`.
+///
+/// ```
+/// use auth_rocket::limitation::permission::permission_matches;
+///
+/// assert!(permission_matches("users:read", "users:read"));
+/// assert!(permission_matches("users:*", "users:read"));
+/// assert!(permission_matches("*", "users:read"));
+/// assert!(!permission_matches("users:read", "users:write"));
+/// assert!(!permission_matches("users", "users:read"));
+/// ```
+pub fn permission_matches(granted: &str, required: &str) -> bool {
+    if granted == "*" || granted == required {
+        return true;
+    }
+
+    match granted.len() > 1 && granted.ends_with(":*") {
+        true => required.starts_with(&granted[..granted.len() - 1]),
+        false => false
+    }
+}
+
+/// Whether any of the roles of the user is granted the permission
+pub fn user_has_permission(entity: &Entity, user: &User, permission: &str) -> bool {
+    match entity.user_permissions(user) {
+        Ok(granted) => granted.iter().any(|g| permission_matches(g, permission)),
+        Err(e) => {
+            error!("cannot load permissions of user {} ({})", user.name, e);
+            false
+        }
+    }
+}
+
+/// Authorized user with the permission `P`, 403 for users without it
+pub struct Permitted<P: RequiredPermission>(User, PhantomData<P>);
+
+impl<P: RequiredPermission> Permitted<P> {
+    pub fn get_user(&self) -> &User {
+        &self.0
+    }
+}
+
+impl<'a, 'r, P: RequiredPermission> FromRequest<'a, 'r> for Permitted<P> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Permitted<P>, ()> {
+        let user = match user_from_request(request, Vec::new()) {
+            Outcome::Success(user) => user,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            _ => return Outcome::Failure((Status::Unauthorized, ()))
+        };
+
+        let entity = match request.guard::<State<AuthEntity>>() {
+            Outcome::Success(entity) => entity,
+            _ => return Outcome::Failure((Status::InternalServerError, ()))
+        };
+
+        match user_has_permission(entity.inner(), &user, P::permission()) {
+            true => Outcome::Success(Permitted(user, PhantomData)),
+            false => Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ permission_matches, user_has_permission, USERS_READ, USERS_WRITE };
+    use ::{ Entity, Role };
+    use ::memorydb::MemoryEntity;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_permission_matches() {
+        assert!(permission_matches("users:*", "users:sessions:read"));
+        assert!(!permission_matches("users:*", "userspace:read"));
+        assert!(!permission_matches(":*", "users:read"));
+        assert!(!permission_matches("users:read:*", "users:read"));
+    }
+
+    #[test]
+    fn test_user_permissions() {
+        let entity = MemoryEntity::new();
+        let moderators = Role::Custom("moderators".to_string());
+        entity.add_user("user", "user@example.com", "password", HashMap::new()).unwrap();
+
+        assert_eq!(entity.grant_permission(&Role::Users, USERS_READ), None);
+        assert_eq!(entity.grant_permission(&moderators, "users:*"), None);
+        assert_eq!(entity.grant_permission(&moderators, "users:*"), None);
+        assert_eq!(entity.list_permissions(&moderators), Ok(vec!("users:*".to_string())));
+
+        let user = entity.get_user_by_id(1).unwrap();
+        assert!(user_has_permission(&entity, &user, USERS_READ));
+        assert!(!user_has_permission(&entity, &user, USERS_WRITE));
+
+        let user = entity.add_user_role("user", moderators.clone()).unwrap();
+        assert!(user_has_permission(&entity, &user, USERS_WRITE));
+
+        assert_eq!(entity.revoke_permission(&moderators, "users:*"), None);
+        assert!(!user_has_permission(&entity, &user, USERS_WRITE));
+        assert!(user_has_permission(&entity, &user, USERS_READ));
+    }
+}
@@ -0,0 +1,367 @@
+use std::str::FromStr;
+use chrono::{ Utc, TimeZone, Datelike, Local };
+use rocket::Outcome;
+use rocket::http::Status;
+use rocket::request::{ self, Request, FromRequest, State };
+use ::{ Entity, AuthEntity, AuthError, User, Role };
+use ::limitation::user::AuthorizedUser;
+use ::limitation::rate::Decision;
+
+/// Value of a quota attribute which lifts the limit of the user
+pub const UNLIMITED: &'static str = "unlimited";
+
+/// Calendar period of a quota, in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly
+}
+
+impl QuotaPeriod {
+    pub fn all() -> [QuotaPeriod; 2] {
+        [QuotaPeriod::Daily, QuotaPeriod::Monthly]
+    }
+
+    /// User attribute which overrides the limit of the role, a number or `UNLIMITED`
+    pub fn attribute(&self) -> &'static str {
+        match *self {
+            QuotaPeriod::Daily => "quota_daily",
+            QuotaPeriod::Monthly => "quota_monthly"
+        }
+    }
+
+    /// Label of the period the timestamp falls in and the timestamp of its end
+    fn bounds(&self, now: i64) -> (String, i64) {
+        let time = Utc.timestamp(now, 0);
+
+        match *self {
+            QuotaPeriod::Daily => {
+                let date = time.date();
+                (date.format("%Y-%m-%d").to_string(), date.succ().and_hms(0, 0, 0).timestamp())
+            },
+            QuotaPeriod::Monthly => {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1)
+                };
+                (time.format("%Y-%m").to_string(), Utc.ymd(year, month, 1).and_hms(0, 0, 0).timestamp())
+            }
+        }
+    }
+}
+
+/// Requests allowed per period, `None` is unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QuotaLimits {
+    pub daily: Option<i64>,
+    pub monthly: Option<i64>
+}
+
+impl QuotaLimits {
+    pub fn new(daily: Option<i64>, monthly: Option<i64>) -> Self {
+        QuotaLimits {
+            daily: daily,
+            monthly: monthly
+        }
+    }
+
+    pub fn get(&self, period: QuotaPeriod) -> Option<i64> {
+        match period {
+            QuotaPeriod::Daily => self.daily,
+            QuotaPeriod::Monthly => self.monthly
+        }
+    }
+
+    fn most_generous(self, other: QuotaLimits) -> QuotaLimits {
+        let max = |a: Option<i64>, b: Option<i64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None
+        };
+
+        QuotaLimits::new(max(self.daily, other.daily), max(self.monthly, other.monthly))
+    }
+
+    fn set(&mut self, period: QuotaPeriod, limit: Option<i64>) {
+        match period {
+            QuotaPeriod::Daily => self.daily = limit,
+            QuotaPeriod::Monthly => self.monthly = limit
+        }
+    }
+}
+
+/// Usage of the quota in the current period
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaUsage {
+    pub period: QuotaPeriod,
+    pub limit: Option<i64>,
+    pub used: i64,
+    pub remaining: Option<i64>,
+    /// Seconds until the period ends and the usage starts over
+    pub reset: i64
+}
+
+impl QuotaUsage {
+    fn new(period: QuotaPeriod, limit: Option<i64>, used: i64, reset: i64) -> Self {
+        QuotaUsage {
+            period: period,
+            limit: limit,
+            used: used,
+            remaining: limit.map(|limit| (limit - used).max(0)),
+            reset: reset.max(1)
+        }
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+/// Daily and monthly request quotas of users, put it to the Rocket managed state to turn them on
+///
+/// The limits of the role apply unless the user has its own in the `quota_daily` or `quota_monthly`
+/// attributes. Requests are counted through the entity counters by the `MeteredUser` guard.
+///
+/// ```
+/// use auth_rocket::limitation::quota::{ QuotaPolicy, QuotaLimits };
+/// use auth_rocket::{ User, UserStatus, Role };
+/// use std::collections::HashMap;
+///
+/// let policy = QuotaPolicy::new()
+///     .with_default(QuotaLimits::new(Some(1000), Some(20000)))
+///     .with_role(Role::Admins, QuotaLimits::new(None, None));
+///
+/// let mut attributes = HashMap::new();
+/// attributes.insert("quota_daily".to_string(), "5000".to_string());
+/// let user = User { id: 1, name: "batman".to_string(), email: "bruce@example.com".to_string(),
+///                   status: UserStatus::Active, roles: [Role::Users].iter().cloned().collect(), attributes: attributes };
+///
+/// assert_eq!(policy.limits(&user), QuotaLimits::new(Some(5000), Some(20000)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QuotaPolicy {
+    default: QuotaLimits,
+    roles: Vec<(Role, QuotaLimits)>
+}
+
+impl QuotaPolicy {
+    /// Unlimited for everyone
+    pub fn new() -> Self {
+        QuotaPolicy::default()
+    }
+
+    /// Limits of the roles without their own
+    pub fn with_default(mut self, limits: QuotaLimits) -> Self {
+        self.default = limits;
+        self
+    }
+
+    pub fn with_role(mut self, role: Role, limits: QuotaLimits) -> Self {
+        self.roles.retain(|&(ref r, _)| *r != role);
+        self.roles.push((role, limits));
+        self
+    }
+
+    /// Limits of the user, its attributes first, then the most generous of its roles
+    pub fn limits(&self, user: &User) -> QuotaLimits {
+        let mut matched = self.roles.iter()
+            .filter(|&&(ref role, _)| user.has_role(role))
+            .map(|&(_, limits)| limits);
+
+        let mut limits = match matched.next() {
+            Some(first) => matched.fold(first, |a, b| a.most_generous(b)),
+            None => self.default
+        };
+
+        for period in QuotaPeriod::all().iter() {
+            match user.attributes.get(period.attribute()).map(|v| v.as_str()) {
+                Some(UNLIMITED) => limits.set(*period, None),
+                Some(value) => match i64::from_str(value) {
+                    Ok(limit) => limits.set(*period, Some(limit)),
+                    Err(_) => warn!("Ignore {} of user {}, it is not a number: {}", period.attribute(), user.name, value)
+                },
+                None => ()
+            }
+        }
+
+        limits
+    }
+
+    /// Usage of every period without counting a request
+    pub fn usage(&self, entity: &Entity, user: &User, now: i64) -> Result<Vec<QuotaUsage>, AuthError> {
+        let limits = self.limits(user);
+        let mut usage = Vec::new();
+
+        for period in QuotaPeriod::all().iter() {
+            let (label, end) = period.bounds(now);
+            let used = entity.get_counter(&quota_key(*period, &label, user.id))?;
+            usage.push(QuotaUsage::new(*period, limits.get(*period), used, end - now));
+        }
+
+        Ok(usage)
+    }
+
+    /// Count the request unless a quota of the user is exhausted, `AuthError::QuotaExceeded` then
+    pub fn consume(&self, entity: &Entity, user: &User, now: i64) -> Result<Vec<QuotaUsage>, AuthError> {
+        let usage = self.usage(entity, user, now)?;
+
+        // requests to an exhausted quota are not counted, so they don't use up the other period
+        if usage.iter().any(|u| u.exceeded()) {
+            return Err(AuthError::QuotaExceeded);
+        }
+
+        let mut consumed = Vec::new();
+
+        for u in usage {
+            let (label, end) = u.period.bounds(now);
+            let used = entity.incr_counter(&quota_key(u.period, &label, user.id), end - now)?;
+            consumed.push(QuotaUsage::new(u.period, u.limit, used, u.reset));
+        }
+
+        // concurrent requests may pass the check above together, the incremented counter decides
+        match consumed.iter().any(|u| u.limit.map(|limit| u.used > limit).unwrap_or(false)) {
+            true => Err(AuthError::QuotaExceeded),
+            false => Ok(consumed)
+        }
+    }
+}
+
+fn quota_key(period: QuotaPeriod, label: &str, user_id: i32) -> String {
+    format!("quota:{}:{}:{}", period.attribute(), user_id, label)
+}
+
+/// Authorized user whose request is counted against the quotas of the managed `QuotaPolicy`
+///
+/// Fails with 429 when a quota is exhausted, without a managed policy it is the same as `AuthorizedUser`.
+pub struct MeteredUser(AuthorizedUser, Vec<QuotaUsage>);
+
+impl MeteredUser {
+    pub fn get_user(&self) -> &User {
+        self.0.get_user()
+    }
+
+    /// Usage after this request, empty without a managed policy
+    pub fn usage(&self) -> &[QuotaUsage] {
+        &self.1
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for MeteredUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<MeteredUser, ()> {
+        let user = match request.guard::<AuthorizedUser>() {
+            Outcome::Success(user) => user,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(f) => return Outcome::Forward(f)
+        };
+
+        let (policy, entity) = match (request.guard::<State<QuotaPolicy>>(), request.guard::<State<AuthEntity>>()) {
+            (Outcome::Success(policy), Outcome::Success(entity)) => (policy, entity),
+            _ => return Outcome::Success(MeteredUser(user, Vec::new()))
+        };
+
+        let consumed = policy.inner().consume(entity.inner(), user.get_user(), Local::now().timestamp());
+        match consumed {
+            Ok(usage) => Outcome::Success(MeteredUser(user, usage)),
+            Err(AuthError::QuotaExceeded) => Outcome::Failure((Status::TooManyRequests, ())),
+            Err(_) => Outcome::Failure((Status::InternalServerError, ()))
+        }
+    }
+}
+
+/// Limit of the exhausted quota of the request user for the 429 response, `None` if no quota is exhausted
+pub fn exhausted_quota(request: &Request) -> Option<Decision> {
+    let (policy, entity, user) = match (request.guard::<State<QuotaPolicy>>(), request.guard::<State<AuthEntity>>(), request.guard::<AuthorizedUser>()) {
+        (Outcome::Success(policy), Outcome::Success(entity), Outcome::Success(user)) => (policy, entity, user),
+        _ => return None
+    };
+
+    let usage = policy.inner().usage(entity.inner(), user.get_user(), Local::now().timestamp()).ok()?;
+
+    // the latest reset of the exhausted periods is when the user may come back
+    usage.into_iter()
+        .filter(|u| u.exceeded())
+        .max_by_key(|u| u.reset)
+        .map(|u| Decision {
+            allowed: false,
+            limit: u.limit.unwrap_or(0) as u64,
+            remaining: 0,
+            reset: u.reset,
+            retry_after: u.reset
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ QuotaPolicy, QuotaLimits, QuotaPeriod, UNLIMITED };
+    use ::{ Entity, AuthError, Role };
+    use ::memorydb::MemoryEntity;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_bounds() {
+        // 2017-12-31 23:00:00 UTC
+        let now = 1514761200;
+        assert_eq!(QuotaPeriod::Daily.bounds(now), ("2017-12-31".to_string(), now + 3600));
+        assert_eq!(QuotaPeriod::Monthly.bounds(now), ("2017-12".to_string(), now + 3600));
+        assert_eq!(QuotaPeriod::Monthly.bounds(now + 3600).0, "2018-01");
+    }
+
+    #[test]
+    fn test_consume() {
+        let entity = MemoryEntity::new();
+        let user = entity.add_user("user", "user@example.com", "password", HashMap::new()).unwrap();
+        let policy = QuotaPolicy::new().with_default(QuotaLimits::new(Some(2), Some(3)));
+        // 2018-01-15 12:00:00 UTC
+        let now = 1516017600;
+
+        assert_eq!(policy.consume(&entity, &user, now).unwrap()[0].remaining, Some(1));
+        assert_eq!(policy.consume(&entity, &user, now).unwrap()[0].remaining, Some(0));
+        assert_eq!(policy.consume(&entity, &user, now), Err(AuthError::QuotaExceeded));
+
+        // the next day starts over, but the month has only one request left
+        let usage = policy.consume(&entity, &user, now + 86400).unwrap();
+        assert_eq!((usage[0].used, usage[1].used), (1, 3));
+        assert_eq!(policy.consume(&entity, &user, now + 86400), Err(AuthError::QuotaExceeded));
+        assert_eq!(policy.usage(&entity, &user, now).unwrap()[0].used, 2);
+    }
+
+    #[test]
+    fn test_concurrent_consume() {
+        let entity = Arc::new(MemoryEntity::new());
+        let user = entity.add_user("user", "user@example.com", "password", HashMap::new()).unwrap();
+        let policy = QuotaPolicy::new().with_default(QuotaLimits::new(Some(5), None));
+        let now = 1516017600;
+
+        let workers: Vec<_> = (0..20).map(|_| {
+            let (entity, user, policy) = (entity.clone(), user.clone(), policy.clone());
+            thread::spawn(move || policy.consume(&*entity, &user, now).is_ok())
+        }).collect();
+
+        let allowed = workers.into_iter().map(|w| w.join().unwrap()).filter(|allowed| *allowed).count();
+        assert_eq!(allowed, 5);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let entity = MemoryEntity::new();
+        let mut attributes = HashMap::new();
+        attributes.insert("quota_daily".to_string(), UNLIMITED.to_string());
+        attributes.insert("quota_monthly".to_string(), "not a number".to_string());
+        let user = entity.add_user("user", "user@example.com", "password", attributes).unwrap();
+
+        let policy = QuotaPolicy::new()
+            .with_default(QuotaLimits::new(Some(1), Some(1)))
+            .with_role(Role::Users, QuotaLimits::new(Some(10), Some(100)))
+            .with_role(Role::Custom("partners".to_string()), QuotaLimits::new(Some(50), Some(70)));
+        assert_eq!(policy.limits(&user), QuotaLimits::new(None, Some(100)));
+
+        entity.add_user_role("user", Role::Custom("partners".to_string())).unwrap();
+        let user = entity.set_user_attributes("user", HashMap::new()).unwrap();
+        assert_eq!(user.roles.len(), 2);
+        assert_eq!(policy.limits(&user), QuotaLimits::new(Some(50), Some(100)));
+    }
+}
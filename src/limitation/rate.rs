@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Mutex;
+use chrono::Local;
+use rocket::{ Outcome, Data, Catcher };
+use rocket::fairing::{ Fairing, Info, Kind };
+use rocket::http::{ Status, Header, ContentType };
+use rocket::request::{ self, Request, FromRequest, State };
+use rocket::response::{ self, Response, Responder };
+use ::AuthError;
+use ::limitation::user::user_from_request;
+use ::limitation::quota::exhausted_quota;
+
+/// Internal request headers the fairing uses to pass its decision to the response
+const DENIED_HEADER: &'static str = "X-RateLimit-Denied";
+const KEY_HEADER: &'static str = "X-RateLimit-Key";
+/// Path the fairing sends denied requests to, so no route handles them
+const DENIED_PATH: &'static str = "/__rate_limited";
+
+/// How requests are counted
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateAlgorithm {
+    /// Bursts up to `capacity`, refilled continuously by `refill_per_second`
+    TokenBucket { capacity: u64, refill_per_second: f64 },
+    /// At most `limit` requests in any `window` seconds, estimated from the current and the previous fixed windows
+    SlidingWindow { limit: u64, window: i64 }
+}
+
+impl RateAlgorithm {
+    pub fn limit(&self) -> u64 {
+        match *self {
+            RateAlgorithm::TokenBucket { capacity, .. } => capacity,
+            RateAlgorithm::SlidingWindow { limit, .. } => limit
+        }
+    }
+}
+
+/// What requests share a limit
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateKey {
+    /// Client IP
+    Ip,
+    /// Id of the authorized user, the client IP for anonymous requests
+    User,
+    /// Method and path of the request, all clients together
+    Route
+}
+
+/// Outcome of a request for the limit, the source of the `X-RateLimit-*` headers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the limit is fully restored
+    pub reset: i64,
+    /// Seconds until the next request is allowed, 0 if it is allowed now
+    pub retry_after: i64
+}
+
+/// State of a token bucket: tokens left and the time of the last update in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketState {
+    pub tokens: f64,
+    pub updated_at: i64
+}
+
+/// State of a sliding window: number of the current fixed window and the counts of it and the previous one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowState {
+    pub window: i64,
+    pub current: u64,
+    pub previous: u64
+}
+
+/// Refill the bucket and take `cost` tokens from it if there are enough
+pub fn token_bucket(state: Option<BucketState>, capacity: u64, refill_per_second: f64, cost: u64, now: i64) -> (BucketState, Decision) {
+    let capacity = capacity as f64;
+    let tokens = match state {
+        Some(state) => (state.tokens + (now - state.updated_at).max(0) as f64 * refill_per_second / 1000.0).min(capacity),
+        None => capacity
+    };
+
+    let allowed = tokens >= cost as f64;
+    let tokens = if allowed { tokens - cost as f64 } else { tokens };
+    let seconds = |missing: f64| match refill_per_second > 0.0 {
+        true => (missing / refill_per_second).ceil() as i64,
+        false => i64::max_value()
+    };
+
+    (BucketState { tokens: tokens, updated_at: now }, Decision {
+        allowed: allowed,
+        limit: capacity as u64,
+        remaining: tokens.floor() as u64,
+        reset: seconds(capacity - tokens),
+        retry_after: if tokens >= 1.0 { 0 } else { seconds(1.0 - tokens) }
+    })
+}
+
+/// Count `cost` requests in the window if the estimate stays within the limit
+pub fn sliding_window(state: Option<WindowState>, limit: u64, window: i64, cost: u64, now: i64) -> (WindowState, Decision) {
+    let window_ms = window * 1000;
+    let index = now / window_ms;
+
+    let mut state = match state {
+        Some(state) if state.window == index => state,
+        Some(state) if state.window == index - 1 => WindowState { window: index, current: 0, previous: state.current },
+        _ => WindowState { window: index, current: 0, previous: 0 }
+    };
+
+    let elapsed = now - index * window_ms;
+    let weight = 1.0 - elapsed as f64 / window_ms as f64;
+    let estimate = |state: &WindowState| state.previous as f64 * weight + state.current as f64;
+
+    let allowed = estimate(&state) + cost as f64 <= limit as f64;
+    if allowed {
+        state.current += cost;
+    }
+
+    let used = estimate(&state).ceil() as u64;
+    let until_next_window = window_ms - elapsed;
+
+    // the previous window fades out linearly, find when one more request fits
+    let retry_after = if used < limit {
+        0
+    } else if state.current + 1 <= limit {
+        let share = (limit - state.current - 1) as f64 / state.previous as f64;
+        ((1.0 - share) * window_ms as f64) as i64 - elapsed
+    } else {
+        let share = (limit - 1) as f64 / state.current as f64;
+        until_next_window + ((1.0 - share) * window_ms as f64) as i64
+    };
+
+    (state, Decision {
+        allowed: allowed,
+        limit: limit,
+        remaining: limit.saturating_sub(used),
+        reset: (until_next_window + if state.current > 0 { window_ms } else { 0 } + 999) / 1000,
+        retry_after: (retry_after.max(0) + 999) / 1000
+    })
+}
+
+/// Storage of the limiter state, shared by all workers of the application
+pub trait RateStore: Send + Sync + 'static {
+    /// Atomically count `cost` requests under the key, cost 0 only reads the state
+    fn hit(&self, key: &str, algorithm: &RateAlgorithm, cost: u64, now: i64) -> Result<Decision, AuthError>;
+}
+
+enum StoredState {
+    Bucket(BucketState),
+    Window(WindowState)
+}
+
+/// Store in the process memory, every instance of the application counts on its own
+#[derive(Default)]
+pub struct MemoryRateStore {
+    states: Mutex<HashMap<String, StoredState>>
+}
+
+impl MemoryRateStore {
+    pub fn new() -> Self {
+        MemoryRateStore::default()
+    }
+}
+
+impl RateStore for MemoryRateStore {
+    fn hit(&self, key: &str, algorithm: &RateAlgorithm, cost: u64, now: i64) -> Result<Decision, AuthError> {
+        let mut states = self.states.lock().map_err(|e| {
+            error!("Cannot lock rate limiter storage: {}", e);
+            AuthError::IOError
+        })?;
+
+        let (state, decision) = match (algorithm, states.get(key)) {
+            (&RateAlgorithm::TokenBucket { capacity, refill_per_second }, Some(&StoredState::Bucket(state))) =>
+                bucket(token_bucket(Some(state), capacity, refill_per_second, cost, now)),
+            (&RateAlgorithm::TokenBucket { capacity, refill_per_second }, _) =>
+                bucket(token_bucket(None, capacity, refill_per_second, cost, now)),
+            (&RateAlgorithm::SlidingWindow { limit, window }, Some(&StoredState::Window(state))) =>
+                sliding(sliding_window(Some(state), limit, window, cost, now)),
+            (&RateAlgorithm::SlidingWindow { limit, window }, _) =>
+                sliding(sliding_window(None, limit, window, cost, now))
+        };
+
+        states.insert(key.to_string(), state);
+
+        Ok(decision)
+    }
+}
+
+fn bucket((state, decision): (BucketState, Decision)) -> (StoredState, Decision) {
+    (StoredState::Bucket(state), decision)
+}
+
+fn sliding((state, decision): (WindowState, Decision)) -> (StoredState, Decision) {
+    (StoredState::Window(state), decision)
+}
+
+/// Limit of requests with its store
+///
+/// Put it to the Rocket managed state and add `RateLimited` to the routes to limit, or attach
+/// `RateLimitFairing` with another limiter to limit every request of the application.
+///
+/// ```
+/// use auth_rocket::limitation::rate::{ RateLimiter, RateAlgorithm, RateKey, MemoryRateStore };
+///
+/// let limiter = RateLimiter::new("api", RateAlgorithm::SlidingWindow { limit: 100, window: 60 }, RateKey::Ip, Box::new(MemoryRateStore::new()));
+/// assert_eq!(limiter.algorithm().limit(), 100);
+/// ```
+pub struct RateLimiter {
+    name: String,
+    algorithm: RateAlgorithm,
+    key: RateKey,
+    store: Box<RateStore>
+}
+
+impl RateLimiter {
+    /// `name` separates limiters sharing a store
+    pub fn new(name: &str, algorithm: RateAlgorithm, key: RateKey, store: Box<RateStore>) -> Self {
+        RateLimiter {
+            name: name.to_string(),
+            algorithm: algorithm,
+            key: key,
+            store: store
+        }
+    }
+
+    pub fn algorithm(&self) -> &RateAlgorithm {
+        &self.algorithm
+    }
+
+    /// Key of the request in the store
+    pub fn key(&self, request: &Request) -> String {
+        let ip = || request.remote().map(|addr| addr.ip().to_string()).unwrap_or("unknown".to_string());
+
+        let key = match self.key {
+            RateKey::Ip => format!("ip:{}", ip()),
+            RateKey::User => match user_from_request(request, vec!()) {
+                Outcome::Success(user) => format!("user:{}", user.id),
+                _ => format!("ip:{}", ip())
+            },
+            RateKey::Route => format!("route:{}:{}", request.method(), request.uri().path())
+        };
+
+        format!("{}:{}", self.name, key)
+    }
+
+    /// Count the request
+    pub fn hit(&self, request: &Request) -> Result<Decision, AuthError> {
+        self.hit_key(&self.key(request), 1)
+    }
+
+    /// State of the limit for the request without counting it
+    pub fn peek(&self, request: &Request) -> Result<Decision, AuthError> {
+        self.hit_key(&self.key(request), 0)
+    }
+
+    fn hit_key(&self, key: &str, cost: u64) -> Result<Decision, AuthError> {
+        self.store.hit(key, &self.algorithm, cost, Local::now().timestamp_millis())
+    }
+}
+
+/// Request guard which counts the request against the managed `RateLimiter`, 429 when the limit is exceeded
+///
+/// Without a managed limiter every request passes.
+pub struct RateLimited(pub Option<Decision>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RateLimited {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RateLimited, ()> {
+        let limiter = match request.guard::<State<RateLimiter>>() {
+            Outcome::Success(limiter) => limiter,
+            _ => return Outcome::Success(RateLimited(None))
+        };
+
+        match limiter.inner().hit(request) {
+            Ok(ref decision) if !decision.allowed => Outcome::Failure((Status::TooManyRequests, ())),
+            Ok(decision) => Outcome::Success(RateLimited(Some(decision))),
+            Err(e) => {
+                // a broken store shouldn't take the application down
+                error!("cannot check rate limit ({})", e);
+                Outcome::Success(RateLimited(None))
+            }
+        }
+    }
+}
+
+/// 429 response with `Retry-After` and `X-RateLimit-*` headers
+pub struct TooManyRequests(pub Decision);
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        let body = json!({"error": "Too many requests", "retry_after": self.0.retry_after}).to_string();
+        let mut response = Response::build()
+            .status(Status::TooManyRequests)
+            .header(ContentType::JSON)
+            .header(Header::new("Retry-After", self.0.retry_after.max(1).to_string()))
+            .sized_body(Cursor::new(body))
+            .finalize();
+
+        add_headers(&mut response, &self.0);
+        Ok(response)
+    }
+}
+
+fn add_headers(response: &mut Response, decision: &Decision) {
+    response.set_header(Header::new("X-RateLimit-Limit", decision.limit.to_string()));
+    response.set_header(Header::new("X-RateLimit-Remaining", decision.remaining.to_string()));
+    response.set_header(Header::new("X-RateLimit-Reset", decision.reset.to_string()));
+}
+
+/// Catcher of the 429 of `RateLimited` and `MeteredUser`, adds the headers of the exceeded limit
+#[error(429)]
+pub fn too_many_requests(request: &Request) -> Result<TooManyRequests, Status> {
+    let limited = match request.guard::<State<RateLimiter>>() {
+        Outcome::Success(limiter) => limiter.inner().peek(request).ok(),
+        _ => None
+    };
+
+    match limited {
+        Some(decision) if decision.retry_after > 0 => Ok(TooManyRequests(decision)),
+        _ => exhausted_quota(request).map(TooManyRequests).ok_or(Status::TooManyRequests)
+    }
+}
+
+/// Catchers to register along with the routes guarded by `RateLimited` or `MeteredUser`
+pub fn get_rate_limit_catchers() -> Vec<Catcher> {
+    errors![too_many_requests]
+}
+
+/// Fairing which limits every request of the application and adds `X-RateLimit-*` headers to the responses
+///
+/// A denied request never reaches the routes, it is answered with 429.
+pub struct RateLimitFairing {
+    limiter: RateLimiter
+}
+
+impl RateLimitFairing {
+    pub fn new(limiter: RateLimiter) -> Self {
+        RateLimitFairing {
+            limiter: limiter
+        }
+    }
+}
+
+impl Fairing for RateLimitFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limit",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let key = self.limiter.key(request);
+        let denied = match self.limiter.hit_key(&key, 1) {
+            Ok(decision) => !decision.allowed,
+            Err(e) => {
+                error!("cannot check rate limit ({})", e);
+                false
+            }
+        };
+
+        // replaces whatever the client sent in the headers, the key is lost with the original uri
+        request.replace_header(Header::new(DENIED_HEADER, if denied { "1" } else { "0" }));
+        request.replace_header(Header::new(KEY_HEADER, key));
+
+        if denied {
+            request.set_uri(DENIED_PATH);
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let decision = match request.headers().get_one(KEY_HEADER).map(|key| self.limiter.hit_key(key, 0)) {
+            Some(Ok(decision)) => decision,
+            _ => return
+        };
+
+        if request.headers().get_one(DENIED_HEADER) == Some("1") {
+            if let Ok(denied) = TooManyRequests(decision).respond_to(request) {
+                response.merge(denied);
+            }
+            return;
+        }
+
+        add_headers(response, &decision);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ token_bucket, sliding_window };
+
+    #[test]
+    fn test_token_bucket() {
+        let (state, decision) = token_bucket(None, 2, 1.0, 1, 0);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+
+        let (state, decision) = token_bucket(Some(state), 2, 1.0, 1, 0);
+        assert!(decision.allowed);
+        assert_eq!((decision.remaining, decision.retry_after, decision.reset), (0, 1, 2));
+
+        let (state, decision) = token_bucket(Some(state), 2, 1.0, 1, 500);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, 1);
+
+        let (_, decision) = token_bucket(Some(state), 2, 1.0, 1, 1000);
+        assert!(decision.allowed);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let mut state = None;
+        for _ in 0..10 {
+            let (next, decision) = sliding_window(state, 10, 60, 1, 30_000);
+            assert!(decision.allowed);
+            state = Some(next);
+        }
+
+        let (next, decision) = sliding_window(state, 10, 60, 1, 30_000);
+        assert!(!decision.allowed);
+        assert_eq!((decision.remaining, decision.retry_after), (0, 36));
+
+        // half of the previous window still counts
+        let (next, decision) = sliding_window(Some(next), 10, 60, 1, 90_000);
+        assert_eq!(decision.remaining, 4);
+        assert!(decision.allowed);
+
+        let (_, decision) = sliding_window(Some(next), 10, 60, 0, 200_000);
+        assert_eq!((decision.allowed, decision.remaining), (true, 10));
+    }
+}
@@ -0,0 +1,95 @@
+use rocket::http::{ Status, RawStr };
+use rocket::request::Request;
+
+/// Place of the request the access token can be taken from
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenSource {
+    /// `Authorization: Bearer <token>`
+    Bearer,
+    /// Header with the bare token, `access_token` is the legacy one
+    Header(String),
+    /// Query parameter, for websocket upgrades which can't set headers
+    Query(String),
+    /// Private (encrypted) cookie, Rocket sets those HttpOnly
+    Cookie(String)
+}
+
+impl TokenSource {
+    /// All the values of that source in the request
+    fn values(&self, request: &Request) -> Vec<String> {
+        match *self {
+            TokenSource::Bearer => request.headers().get("Authorization")
+                .filter_map(|value| {
+                    let mut parts = value.splitn(2, ' ');
+                    match (parts.next(), parts.next()) {
+                        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim().to_string()),
+                        _ => None
+                    }
+                })
+                .collect(),
+            TokenSource::Header(ref name) => request.headers().get(name).map(|value| value.to_string()).collect(),
+            TokenSource::Query(ref name) => request.uri().query()
+                .map(|query| query.split('&')
+                    .filter_map(|pair| {
+                        let mut parts = pair.splitn(2, '=');
+                        match (parts.next(), parts.next()) {
+                            (Some(key), Some(value)) if key == name.as_str() => RawStr::from_str(value).url_decode().ok(),
+                            _ => None
+                        }
+                    })
+                    .collect())
+                .unwrap_or(Vec::new()),
+            TokenSource::Cookie(ref name) => request.cookies().get_private(name)
+                .map(|cookie| vec!(cookie.value().to_string()))
+                .unwrap_or(Vec::new())
+        }
+    }
+}
+
+/// Sources of the access token in priority order, put it to the Rocket managed state to change the default
+///
+/// The first source present in the request wins, a source with several values is rejected.
+///
+/// ```
+/// use auth_rocket::limitation::source::{ TokenSources, TokenSource };
+///
+/// let sources = TokenSources::new(vec!(TokenSource::Bearer, TokenSource::Query("access_token".to_string())));
+/// assert_eq!(sources.sources().len(), 2);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenSources(Vec<TokenSource>);
+
+impl TokenSources {
+    pub fn new(sources: Vec<TokenSource>) -> Self {
+        TokenSources(sources)
+    }
+
+    pub fn sources(&self) -> &[TokenSource] {
+        &self.0
+    }
+
+    /// Token from the first source present in the request
+    pub fn extract(&self, request: &Request) -> Result<String, Status> {
+        for source in &self.0 {
+            let values = source.values(request);
+
+            match values.len() {
+                0 => continue,
+                1 => return Ok(values[0].clone()),
+                _ => {
+                    debug!("Several access tokens in {:?}", source);
+                    return Err(Status::Unauthorized);
+                }
+            }
+        }
+
+        Err(Status::Unauthorized)
+    }
+}
+
+impl Default for TokenSources {
+    /// `Authorization: Bearer`, then the legacy `access_token` header
+    fn default() -> Self {
+        TokenSources::new(vec!(TokenSource::Bearer, TokenSource::Header("access_token".to_string())))
+    }
+}
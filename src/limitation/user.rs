@@ -3,7 +3,11 @@ use rocket::Outcome;
 use rocket::http::Status;
 use ::AuthEntity;
 use rocket::request::{self, Request, FromRequest, State};
-use ::net::key::{ PrivateKey, validate_api_key };
+use ::net::key::ApiKeys;
+use ::limitation::source::TokenSources;
+use ::limitation::cookie::CookieSession;
+use ::jwt::{ JwtConfig, Claims };
+use chrono::Local;
 
 #[derive(Deserialize, Serialize)]
 pub struct AuthorizedUser(User);
@@ -45,40 +49,115 @@ impl<'a, 'r> FromRequest<'a, 'r> for AdminUser {
     }
 }
 
-pub fn user_from_request(request: &Request, role: Vec<Role>) -> request::Outcome<User, ()> {
-    let keys: Vec<_> = request.headers().get("access_token").collect();
+/// Access token of the request, already checked against the `ApiKeys` signature, or the `jti` of a valid JWT
+///
+/// Doesn't touch the entity, so the token may belong to an expired session.
+pub struct AccessToken(String);
 
-    if keys.len() != 1 {
-        return Outcome::Failure((Status::Unauthorized, ()));
+impl AccessToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
+}
 
-    let header_key = keys[0];
+impl<'a, 'r> FromRequest<'a, 'r> for AccessToken {
+    type Error = ();
 
-    let key: &PrivateKey = match request.guard::<State<PrivateKey>>() {
-        Outcome::Success(key) => key.inner(),
-        _ => {
-            error!("Not found PrivateKey in request.");
-            return Outcome::Failure((Status::InternalServerError, ()));
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AccessToken, ()> {
+        match token_from_request(request) {
+            Ok(token) => Outcome::Success(AccessToken(token)),
+            Err(status) => Outcome::Failure((status, ()))
         }
+    }
+}
+
+/// Raw token from the managed `TokenSources`, or the default ones plus the `CookieSession` cookie
+fn raw_token(request: &Request) -> Result<String, Status> {
+    if let Outcome::Success(sources) = request.guard::<State<TokenSources>>() {
+        return sources.inner().extract(request);
+    }
+
+    match request.guard::<State<CookieSession>>() {
+        Outcome::Success(cookie_session) => cookie_session.inner().token_sources().extract(request),
+        _ => TokenSources::default().extract(request)
+    }
+}
+
+/// Claims of the JWT from the request, `None` if JWT mode is off
+fn claims_from_request(request: &Request) -> Option<Result<Claims, Status>> {
+    match request.guard::<State<JwtConfig>>() {
+        Outcome::Success(jwt) => Some(raw_token(request).and_then(|token| {
+            jwt.inner().decode(&token, Local::now().timestamp()).map_err(|e| {
+                debug!("Invalid JWT: {}", e);
+                Status::Unauthorized
+            })
+        })),
+        _ => None
+    }
+}
+
+/// Extract access token from the `TokenSources` and validate its signature with `ApiKeys`
+///
+/// In JWT mode that is the `jti` claim, the token the session is stored under.
+pub fn token_from_request(request: &Request) -> Result<String, Status> {
+    if let Some(claims) = claims_from_request(request) {
+        return claims.map(|c| c.jti);
+    }
+
+    let header_key = raw_token(request)?;
+
+    let keys: ApiKeys = match request.guard::<ApiKeys>() {
+        Outcome::Success(keys) => keys,
+        _ => return Err(Status::InternalServerError)
+    };
+
+    match keys.validate(&header_key) {
+        true => Ok(header_key),
+        false => Err(Status::Unauthorized)
+    }
+}
+
+/// User from the signed claims, or the current one of the session if the revocation check is on
+fn user_from_claims(request: &Request, claims: Claims) -> Result<User, Status> {
+    let check_revocation = match request.guard::<State<JwtConfig>>() {
+        Outcome::Success(jwt) => jwt.inner().check_revocation(),
+        _ => false
     };
 
-    if validate_api_key(header_key, key.as_str()) == false {
-        return Outcome::Failure((Status::Unauthorized, ()));
+    if !check_revocation {
+        return claims.to_user().map_err(|_| Status::Unauthorized);
     }
 
     match request.guard::<State<AuthEntity>>() {
-        Outcome::Success(entity) => {
-            match entity.inner().get_user_by_token(header_key) {
-                Ok(u) => {
-                    if role.len() == 0 || role.contains(&u.role) {
-                        Outcome::Success(u)
-                    } else {
-                        Outcome::Failure((Status::Unauthorized, ()))
-                    }
-                },
-                _ => Outcome::Failure((Status::Unauthorized, ()))
+        Outcome::Success(entity) => entity.inner().get_user_by_token(&claims.jti).map_err(|_| Status::Unauthorized),
+        _ => Err(Status::Unauthorized)
+    }
+}
+
+/// Authorized user of the request, it must have at least one of `roles` unless they are empty
+pub fn user_from_request(request: &Request, roles: Vec<Role>) -> request::Outcome<User, ()> {
+    let user = match claims_from_request(request) {
+        Some(claims) => claims.and_then(|c| user_from_claims(request, c)),
+        None => user_from_entity(request)
+    };
+
+    match user {
+        Ok(u) => {
+            if roles.is_empty() || roles.iter().any(|role| u.has_role(role)) {
+                Outcome::Success(u)
+            } else {
+                Outcome::Failure((Status::Unauthorized, ()))
             }
         },
-        _ => Outcome::Failure((Status::Unauthorized, ()))
+        Err(status) => Outcome::Failure((status, ()))
+    }
+}
+
+fn user_from_entity(request: &Request) -> Result<User, Status> {
+    let header_key = token_from_request(request)?;
+
+    match request.guard::<State<AuthEntity>>() {
+        Outcome::Success(entity) => entity.inner().get_user_by_token(&header_key).map_err(|_| Status::Unauthorized),
+        _ => Err(Status::Unauthorized)
     }
 }
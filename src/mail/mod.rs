@@ -0,0 +1,101 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use ::AuthError;
+
+/// Email sent to a user
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String
+}
+
+impl Message {
+    pub fn new(to: &str, subject: &str, body: String) -> Self {
+        Message {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body
+        }
+    }
+}
+
+/// Way the crate delivers emails, e.g. verification links
+pub trait Mailer: Send + Sync + 'static {
+    fn send(&self, message: &Message) -> Result<(), AuthError>;
+}
+
+/// Mailer for the Rocket managed state, like `AuthEntity` for entities
+pub struct AuthMailer {
+    component: Box<Mailer>
+}
+
+impl AuthMailer {
+    pub fn new(component: Box<Mailer>) -> Self {
+        AuthMailer {
+            component: component
+        }
+    }
+}
+
+impl Mailer for AuthMailer {
+    fn send(&self, message: &Message) -> Result<(), AuthError> {
+        self.component.send(message)
+    }
+}
+
+fn format_message(message: &Message) -> String {
+    format!("To: {}\nSubject: {}\n\n{}\n\n", message.to, message.subject, message.body)
+}
+
+/// Prints emails to stdout, for development
+pub struct StdoutMailer;
+
+impl Mailer for StdoutMailer {
+    fn send(&self, message: &Message) -> Result<(), AuthError> {
+        print!("{}", format_message(message));
+        Ok(())
+    }
+}
+
+/// Appends emails to a file, for tests and development
+///
+/// ```
+/// use auth_rocket::mail::{ FileMailer, Mailer, Message };
+/// use std::fs;
+///
+/// let path = std::env::temp_dir().join("auth_rocket_mailer_doc.txt");
+/// fs::remove_file(&path).unwrap_or(());
+///
+/// let mailer = FileMailer::new(path.clone());
+/// mailer.send(&Message::new("bruce@example.com", "Hello", "Body".to_string())).unwrap();
+/// assert!(fs::read_to_string(&path).unwrap().contains("To: bruce@example.com"));
+/// ```
+pub struct FileMailer {
+    path: PathBuf,
+    lock: Mutex<()>
+}
+
+impl FileMailer {
+    pub fn new(path: PathBuf) -> Self {
+        FileMailer {
+            path: path,
+            lock: Mutex::new(())
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, message: &Message) -> Result<(), AuthError> {
+        let _guard = self.lock.lock().map_err(|_| AuthError::IOError)?;
+
+        OpenOptions::new().create(true).append(true).open(&self.path)
+            .and_then(|mut file| file.write_all(format_message(message).as_bytes()))
+            .map_err(|e| {
+                error!("Cannot write email to {}: {}", self.path.display(), e);
+                AuthError::IOError
+            })
+    }
+}
@@ -0,0 +1,797 @@
+use std::collections::{ HashMap, BTreeSet };
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use super::{Entity, User, AuthError, Role, UserStatus, PrivateUser, MfaSecret};
+use chrono::Local;
+use ::password::{ PasswordHasher, Argon2Hasher, PasswordMatch, verify_password };
+use ::session::{ TokenPolicy, Session, RefreshToken };
+use ::net::client::ClientInfo;
+use ::paging::range_bounds;
+
+struct StoredSession {
+    token: String,
+    username: String,
+    session: Session
+}
+
+#[derive(Default)]
+struct Storage {
+    increment: i32,
+    users: HashMap<String, PrivateUser>,
+    ids: HashMap<i32, String>,
+    list: Vec<(i64, i32)>,
+    sessions: HashMap<String, StoredSession>,
+    refresh_tokens: HashMap<String, RefreshToken>,
+    tickets: HashMap<(String, String), (String, i64)>,
+    mfa_secrets: HashMap<String, MfaSecret>,
+    recovery_codes: HashMap<String, Vec<String>>,
+    counters: HashMap<String, (i64, i64)>,
+    permissions: HashMap<Role, BTreeSet<String>>
+}
+
+/// Entity which keeps everything in the process memory.
+///
+/// Useful for tests and small tools which don't want to run Redis,
+/// all data is lost when the entity is dropped.
+///
+/// ```
+/// use auth_rocket::memorydb::MemoryEntity;
+/// use auth_rocket::Entity;
+/// use std::collections::HashMap;
+///
+/// let entity = MemoryEntity::new();
+/// let user = entity.add_user("batman", "bruce@example.com", "secret", HashMap::new()).unwrap();
+/// assert_eq!(entity.get_user_by_name_and_pwd("batman", "secret").unwrap(), user);
+/// ```
+pub struct MemoryEntity {
+    storage: RwLock<Storage>,
+    hasher: Box<PasswordHasher>,
+    token_policy: TokenPolicy
+}
+
+impl MemoryEntity {
+    pub fn new() -> MemoryEntity {
+        MemoryEntity {
+            storage: RwLock::new(Storage::default()),
+            hasher: Box::new(Argon2Hasher::default()),
+            token_policy: TokenPolicy::default()
+        }
+    }
+
+    /// Use another algorithm for new passwords
+    pub fn with_hasher(mut self, hasher: Box<PasswordHasher>) -> MemoryEntity {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Use another expiration rules for new and prolonged tokens
+    pub fn with_token_policy(mut self, token_policy: TokenPolicy) -> MemoryEntity {
+        self.token_policy = token_policy;
+        self
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<Storage>, AuthError> {
+        self.storage.read().map_err(|e| {
+            error!("Cannot lock memory storage: {}", e);
+            AuthError::IOError
+        })
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<Storage>, AuthError> {
+        self.storage.write().map_err(|e| {
+            error!("Cannot lock memory storage: {}", e);
+            AuthError::IOError
+        })
+    }
+
+    fn rehash_password(&self, username: &str, password: &str) -> Option<AuthError> {
+        self.hasher.hash(password)
+            .and_then(|hash| self.update_user(username, |u| u.password = hash))
+            .err()
+    }
+
+    fn update_user<F>(&self, username: &str, f: F) -> Result<User, AuthError> where F: FnOnce(&mut PrivateUser) {
+        let mut storage = self.write()?;
+        match storage.users.get_mut(username) {
+            Some(user) => {
+                f(user);
+                Ok(User::from(user.clone()))
+            },
+            None => Err(AuthError::NotFound)
+        }
+    }
+}
+
+impl Default for MemoryEntity {
+    fn default() -> Self {
+        MemoryEntity::new()
+    }
+}
+
+impl Entity for MemoryEntity {
+    fn add_user(&self, name: &str, email: &str, password: &str, attributes: HashMap<String, String>) -> Result<User, AuthError> {
+        let hash = self.hasher.hash(password)?;
+        let mut storage = self.write()?;
+
+        if storage.users.contains_key(name) {
+            return Err(AuthError::DuplicateUsername);
+        }
+
+        storage.increment += 1;
+        let id = storage.increment;
+
+        let user = PrivateUser {
+            id: id,
+            name: name.to_string(),
+            email: email.to_string(),
+            password: hash,
+            status: UserStatus::Created,
+            roles: [Role::Users].iter().cloned().collect(),
+            attributes: attributes
+        };
+
+        storage.ids.insert(id, name.to_string());
+        storage.list.push((Local::now().timestamp(), id));
+        storage.list.sort();
+        storage.users.insert(name.to_string(), user.clone());
+
+        Ok(User::from(user))
+    }
+
+    fn get_user_by_id(&self, user_id: i32) -> Result<User, AuthError> {
+        let storage = self.read()?;
+        storage.ids.get(&user_id)
+            .and_then(|name| storage.users.get(name))
+            .map(|user| User::from(user.clone()))
+            .ok_or(AuthError::NotFound)
+    }
+
+    fn get_user_by_name(&self, username: &str) -> Result<PrivateUser, AuthError> {
+        self.read()?.users.get(username).cloned().ok_or(AuthError::NotFound)
+    }
+
+    fn get_user_by_name_and_pwd(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        self.get_user_by_name(username)
+            .and_then(|u| match verify_password(&*self.hasher, password, &u.password) {
+                PasswordMatch::Valid => Ok(User::from(u)),
+                PasswordMatch::Outdated => {
+                    if let Some(e) = self.rehash_password(&u.name, password) {
+                        warn!("cannot rehash password of user {} ({})", u.name, e);
+                    }
+                    Ok(User::from(u))
+                },
+                PasswordMatch::Invalid => Err(AuthError::NotFound)
+            })
+    }
+
+    fn delete_user(&self, user_id: i32) -> Option<AuthError> {
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        match storage.ids.remove(&user_id) {
+            // everything else is keyed by the name, a new user of that name must not inherit it
+            Some(name) => {
+                storage.users.remove(&name);
+                storage.sessions.retain(|_, s| s.username != name);
+                storage.refresh_tokens.retain(|_, t| t.username != name);
+                storage.tickets.retain(|_, &mut (ref username, _)| *username != name);
+                storage.mfa_secrets.remove(&name);
+                storage.recovery_codes.remove(&name);
+            },
+            None => {
+                warn!("username by id ({}) not found in memory DB", user_id);
+            }
+        }
+
+        storage.list.retain(|&(_, id)| id != user_id);
+
+        None
+    }
+
+    fn list_users(&self, from: isize, count:isize) -> Result<Vec<User>, AuthError> {
+        let storage = self.read()?;
+
+        let ids: Vec<i32> = match range_bounds(storage.list.len(), from, count) {
+            Some((start, end)) => storage.list[start..end].iter().map(|&(_, id)| id).collect(),
+            None => Vec::new()
+        };
+
+        let mut v: Vec<User> = Vec::new();
+        for id in &ids {
+            match storage.ids.get(id).and_then(|name| storage.users.get(name)) {
+                Some(u) => v.push(User::from(u.clone())),
+                None => {
+                    warn!("user from list with id {} not found in memory DB", id);
+                }
+            }
+        }
+
+        Ok(v)
+    }
+
+    fn enable_user(&self, username: &str) -> Result<User, AuthError> {
+        self.update_user(username, |u| u.status = UserStatus::Active)
+    }
+
+    fn disable_user(&self, username: &str) -> Result<User, AuthError> {
+        self.update_user(username, |u| u.status = UserStatus::Disabled)
+    }
+
+    fn get_token(&self, username: &str) -> Result<String, AuthError> {
+        let now = Local::now().timestamp();
+
+        self.read()?.sessions.values()
+            .filter(|s| s.username == username && s.session.is_alive(now))
+            .max_by_key(|s| s.session.created_at)
+            .map(|s| s.token.clone())
+            .ok_or(AuthError::NotFound)
+    }
+
+    fn get_user_by_token(&self, token: &str) -> Result<User, AuthError> {
+        let now = Local::now().timestamp();
+        let username = self.read()?.sessions.get(token)
+            .and_then(|s| match s.session.is_alive(now) {
+                true => Some(s.username.clone()),
+                false => None
+            })
+            .ok_or(AuthError::NotFound)?;
+
+        let u = self.get_user_by_name(&username)?;
+
+        if u.status != UserStatus::Active {
+            return Err(AuthError::NotActive);
+        }
+
+        let mut storage = self.write()?;
+
+        let alive = match storage.sessions.get_mut(token) {
+            Some(s) => s.session.touch(&self.token_policy, now),
+            None => return Err(AuthError::NotFound)
+        };
+
+        match alive {
+            true => Ok(User::from(u)),
+            false => {
+                storage.sessions.remove(token);
+                Err(AuthError::NotFound)
+            }
+        }
+    }
+
+    fn delete_token(&self, token: &str) -> Option<AuthError> {
+        match self.write() {
+            Ok(mut storage) => {
+                let removed = storage.sessions.remove(token);
+                match removed {
+                    Some(s) => {
+                        storage.refresh_tokens.retain(|_, r| r.session_id != s.session.id);
+                        None
+                    },
+                    None => Some(AuthError::NotFound)
+                }
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn add_session(&self, username: &str, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        let now = Local::now().timestamp();
+        let session = Session::new(client, now, self.token_policy.expires_at(now, now).unwrap_or(now));
+        let mut storage = self.write()?;
+
+        if !storage.users.contains_key(username) {
+            return Err(AuthError::NotFound);
+        }
+
+        storage.sessions.retain(|_, s| s.session.is_alive(now));
+        storage.sessions.insert(token.to_string(), StoredSession {
+            token: token.to_string(),
+            username: username.to_string(),
+            session: session.clone()
+        });
+
+        Ok(session)
+    }
+
+    fn renew_session(&self, username: &str, session_id: &str, created_at: i64, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        let now = Local::now().timestamp();
+        let mut session = Session::new(client, now, self.token_policy.expires_at(created_at, now).ok_or(AuthError::NotFound)?);
+        session.id = session_id.to_string();
+        session.created_at = created_at;
+        let mut storage = self.write()?;
+
+        if !storage.users.contains_key(username) {
+            return Err(AuthError::NotFound);
+        }
+
+        storage.sessions.retain(|_, s| s.session.id != session_id && s.session.is_alive(now));
+        storage.sessions.insert(token.to_string(), StoredSession {
+            token: token.to_string(),
+            username: username.to_string(),
+            session: session.clone()
+        });
+
+        Ok(session)
+    }
+
+    fn get_session_by_token(&self, token: &str) -> Result<Session, AuthError> {
+        let now = Local::now().timestamp();
+
+        self.read()?.sessions.get(token)
+            .and_then(|s| match s.session.is_alive(now) {
+                true => Some(s.session.clone()),
+                false => None
+            })
+            .ok_or(AuthError::NotFound)
+    }
+
+    fn list_sessions(&self, username: &str) -> Result<Vec<Session>, AuthError> {
+        let now = Local::now().timestamp();
+
+        let mut list: Vec<Session> = self.read()?.sessions.values()
+            .filter(|s| s.username == username && s.session.is_alive(now))
+            .map(|s| s.session.clone())
+            .collect();
+        list.sort_by_key(|s| s.created_at);
+
+        Ok(list)
+    }
+
+    fn delete_session(&self, username: &str, session_id: &str) -> Option<AuthError> {
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        let before = storage.sessions.len() + storage.refresh_tokens.len();
+        storage.sessions.retain(|_, s| !(s.username == username && s.session.id == session_id));
+        storage.refresh_tokens.retain(|_, r| !(r.username == username && r.session_id == session_id));
+
+        match storage.sessions.len() + storage.refresh_tokens.len() < before {
+            true => None,
+            false => Some(AuthError::NotFound)
+        }
+    }
+
+    fn delete_sessions(&self, username: &str) -> Option<AuthError> {
+        match self.write() {
+            Ok(mut storage) => {
+                storage.sessions.retain(|_, s| s.username != username);
+                storage.refresh_tokens.retain(|_, r| r.username != username);
+                None
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn add_refresh_token(&self, username: &str, token: &str, session: &Session) -> Option<AuthError> {
+        let now = Local::now().timestamp();
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        if !storage.users.contains_key(username) {
+            return Some(AuthError::NotFound);
+        }
+
+        storage.refresh_tokens.retain(|_, r| r.is_alive(now));
+        storage.refresh_tokens.insert(token.to_string(), RefreshToken::new(username, &session.id, session.created_at,
+                                                                           self.token_policy.refresh_expires_at(session.created_at, now)));
+
+        None
+    }
+
+    fn take_refresh_token(&self, token: &str) -> Result<RefreshToken, AuthError> {
+        let now = Local::now().timestamp();
+        let mut storage = self.write()?;
+
+        match storage.refresh_tokens.get_mut(token) {
+            Some(r) => match r.is_alive(now) {
+                true => {
+                    let taken = r.clone();
+                    r.used = true;
+                    Ok(taken)
+                },
+                false => Err(AuthError::NotFound)
+            },
+            None => Err(AuthError::NotFound)
+        }
+    }
+
+    fn add_ticket(&self, kind: &str, ticket: &str, username: &str, ttl: i64) -> Option<AuthError> {
+        let now = Local::now().timestamp();
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        if !storage.users.contains_key(username) {
+            return Some(AuthError::NotFound);
+        }
+
+        storage.tickets.retain(|_, &mut (_, expires_at)| expires_at > now);
+        storage.tickets.insert((kind.to_string(), ticket.to_string()), (username.to_string(), now + ttl));
+
+        None
+    }
+
+    fn get_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        let now = Local::now().timestamp();
+
+        match self.read()?.tickets.get(&(kind.to_string(), ticket.to_string())) {
+            Some(&(ref username, expires_at)) if expires_at > now => Ok(username.clone()),
+            _ => Err(AuthError::NotFound)
+        }
+    }
+
+    fn take_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        let now = Local::now().timestamp();
+
+        match self.write()?.tickets.remove(&(kind.to_string(), ticket.to_string())) {
+            Some((username, expires_at)) if expires_at > now => Ok(username),
+            _ => Err(AuthError::NotFound)
+        }
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        self.hasher.hash(password).and_then(|hash| self.update_user(username, |u| u.password = hash))
+    }
+
+    fn set_mfa_secret(&self, username: &str, secret: Option<MfaSecret>) -> Option<AuthError> {
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        if !storage.users.contains_key(username) {
+            return Some(AuthError::NotFound);
+        }
+
+        match secret {
+            Some(secret) => storage.mfa_secrets.insert(username.to_string(), secret),
+            None => storage.mfa_secrets.remove(username)
+        };
+
+        None
+    }
+
+    fn get_mfa_secret(&self, username: &str) -> Result<MfaSecret, AuthError> {
+        self.read()?.mfa_secrets.get(username).cloned().ok_or(AuthError::NotFound)
+    }
+
+    fn set_recovery_codes(&self, username: &str, codes: &[String]) -> Option<AuthError> {
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        if !storage.users.contains_key(username) {
+            return Some(AuthError::NotFound);
+        }
+
+        storage.recovery_codes.insert(username.to_string(), codes.to_vec());
+
+        None
+    }
+
+    fn take_recovery_code(&self, username: &str, code: &str) -> Option<AuthError> {
+        let mut storage = match self.write() {
+            Ok(storage) => storage,
+            Err(e) => return Some(e)
+        };
+
+        let codes = match storage.recovery_codes.get_mut(username) {
+            Some(codes) => codes,
+            None => return Some(AuthError::NotFound)
+        };
+
+        match codes.iter().position(|c| c == code) {
+            Some(index) => {
+                codes.remove(index);
+                None
+            },
+            None => Some(AuthError::NotFound)
+        }
+    }
+
+    fn count_recovery_codes(&self, username: &str) -> Result<usize, AuthError> {
+        Ok(self.read()?.recovery_codes.get(username).map(|codes| codes.len()).unwrap_or(0))
+    }
+
+    fn incr_counter(&self, key: &str, ttl: i64) -> Result<i64, AuthError> {
+        let now = Local::now().timestamp();
+        let mut storage = self.write()?;
+
+        storage.counters.retain(|_, &mut (_, expires_at)| expires_at > now);
+        let counter = storage.counters.entry(key.to_string()).or_insert((0, now + ttl));
+        counter.0 += 1;
+
+        Ok(counter.0)
+    }
+
+    fn get_counter(&self, key: &str) -> Result<i64, AuthError> {
+        let now = Local::now().timestamp();
+
+        Ok(match self.read()?.counters.get(key) {
+            Some(&(value, expires_at)) if expires_at > now => value,
+            _ => 0
+        })
+    }
+
+    fn set_counter(&self, key: &str, value: i64, ttl: i64) -> Option<AuthError> {
+        let now = Local::now().timestamp();
+
+        match self.write() {
+            Ok(mut storage) => {
+                storage.counters.insert(key.to_string(), (value, now + ttl));
+                None
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn reset_counter(&self, key: &str) -> Option<AuthError> {
+        match self.write() {
+            Ok(mut storage) => {
+                storage.counters.remove(key);
+                None
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn set_user_attributes(&self, username: &str, attributes: HashMap<String, String>) -> Result<User, AuthError> {
+        self.update_user(username, |u| u.attributes = attributes)
+    }
+
+    fn add_user_role(&self, username: &str, role: Role) -> Result<User, AuthError> {
+        self.update_user(username, |u| {
+            u.roles.insert(role);
+        })
+    }
+
+    fn remove_user_role(&self, username: &str, role: &Role) -> Result<User, AuthError> {
+        self.update_user(username, |u| {
+            u.roles.remove(role);
+        })
+    }
+
+    fn grant_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        match self.write() {
+            Ok(mut storage) => {
+                storage.permissions.entry(role.clone()).or_insert_with(BTreeSet::new).insert(permission.to_string());
+                None
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn revoke_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        match self.write() {
+            Ok(mut storage) => {
+                if let Some(permissions) = storage.permissions.get_mut(role) {
+                    permissions.remove(permission);
+                }
+                None
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn list_permissions(&self, role: &Role) -> Result<Vec<String>, AuthError> {
+        let storage = self.read()?;
+        Ok(storage.permissions.get(role).map(|p| p.iter().cloned().collect()).unwrap_or(Vec::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MemoryEntity;
+    use ::{ Entity, AuthError, MfaSecret };
+    use ::session::TokenPolicy;
+    use ::net::client::ClientInfo;
+    use ::password::Pbkdf2Hasher;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_token_absolute_lifetime() {
+        let entity = MemoryEntity::new()
+            .with_hasher(Box::new(Pbkdf2Hasher::new(10)))
+            .with_token_policy(TokenPolicy::new(3600, Some(7200), true));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+        entity.enable_user("user").unwrap();
+        assert_eq!(entity.add_token("user", "token"), None);
+        assert!(entity.get_user_by_token("token").is_ok());
+
+        entity.write().unwrap().sessions.get_mut("token").unwrap().session.created_at -= 7200;
+        assert_eq!(entity.get_user_by_token("token"), Err(AuthError::NotFound));
+    }
+
+    #[test]
+    fn test_sessions() {
+        let entity = MemoryEntity::new().with_hasher(Box::new(Pbkdf2Hasher::new(10)));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+        entity.enable_user("user").unwrap();
+
+        let phone = entity.add_session("user", "phone_token", &ClientInfo::new(Some("Phone".to_string()), Some("10.0.0.1".to_string()))).unwrap();
+        let laptop = entity.add_session("user", "laptop_token", &ClientInfo::default()).unwrap();
+        assert_eq!(entity.add_session("nobody", "token", &ClientInfo::default()), Err(AuthError::NotFound));
+
+        let list = entity.list_sessions("user").unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.contains(&phone));
+        assert!(list.contains(&laptop));
+        assert_eq!(entity.get_session_by_token("phone_token").unwrap().user_agent, Some("Phone".to_string()));
+
+        assert_eq!(entity.delete_session("other", &phone.id), Some(AuthError::NotFound));
+        assert_eq!(entity.delete_session("user", &phone.id), None);
+        assert_eq!(entity.get_user_by_token("phone_token"), Err(AuthError::NotFound));
+        assert!(entity.get_user_by_token("laptop_token").is_ok());
+
+        assert_eq!(entity.delete_sessions("user"), None);
+        assert_eq!(entity.list_sessions("user").unwrap().len(), 0);
+        assert_eq!(entity.get_user_by_token("laptop_token"), Err(AuthError::NotFound));
+    }
+
+    #[test]
+    fn test_refresh_tokens() {
+        let entity = MemoryEntity::new()
+            .with_hasher(Box::new(Pbkdf2Hasher::new(10)))
+            .with_token_policy(TokenPolicy::default().with_refresh_lifetime(60));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+        entity.enable_user("user").unwrap();
+
+        let session = entity.add_session("user", "access", &ClientInfo::default()).unwrap();
+        assert_eq!(entity.add_refresh_token("user", "refresh", &session), None);
+        assert_eq!(entity.add_refresh_token("nobody", "refresh", &session), Some(AuthError::NotFound));
+
+        let taken = entity.take_refresh_token("refresh").unwrap();
+        assert_eq!(taken.session_id, session.id);
+        assert_eq!(taken.session_created_at, session.created_at);
+        assert!(!taken.used);
+        assert!(entity.take_refresh_token("refresh").unwrap().used);
+
+        let renewed = entity.renew_session("user", &session.id, taken.session_created_at, "access_2", &ClientInfo::default()).unwrap();
+        assert_eq!(renewed.id, session.id);
+        assert_eq!(renewed.created_at, session.created_at);
+        assert_eq!(entity.get_user_by_token("access"), Err(AuthError::NotFound));
+        assert!(entity.get_user_by_token("access_2").is_ok());
+        assert_eq!(entity.list_sessions("user").unwrap().len(), 1);
+
+        assert_eq!(entity.add_refresh_token("user", "refresh_2", &renewed), None);
+        assert_eq!(entity.delete_token("access_2"), None);
+        assert_eq!(entity.take_refresh_token("refresh_2"), Err(AuthError::NotFound));
+
+        entity.add_refresh_token("user", "refresh_3", &session);
+        entity.write().unwrap().refresh_tokens.get_mut("refresh_3").unwrap().expires_at -= 60;
+        assert_eq!(entity.take_refresh_token("refresh_3"), Err(AuthError::NotFound));
+    }
+
+    #[test]
+    fn test_refresh_keeps_session_lifetime() {
+        let entity = MemoryEntity::new()
+            .with_hasher(Box::new(Pbkdf2Hasher::new(10)))
+            .with_token_policy(TokenPolicy::new(3600, Some(7200), true));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+
+        let mut session = entity.add_session("user", "access", &ClientInfo::default()).unwrap();
+        session.created_at -= 5000;
+
+        // the refresh token dies with the session, not 30 days later
+        assert_eq!(entity.add_refresh_token("user", "refresh", &session), None);
+        assert_eq!(entity.take_refresh_token("refresh").unwrap().expires_at, session.created_at + 7200);
+
+        let renewed = entity.renew_session("user", &session.id, session.created_at, "access_2", &ClientInfo::default()).unwrap();
+        assert_eq!(renewed.created_at, session.created_at);
+        assert_eq!(renewed.expires_at, session.created_at + 7200);
+
+        assert_eq!(entity.renew_session("user", &session.id, session.created_at - 2200, "access_3", &ClientInfo::default()),
+                   Err(AuthError::NotFound));
+    }
+
+    #[test]
+    fn test_tickets() {
+        let entity = MemoryEntity::new().with_hasher(Box::new(Pbkdf2Hasher::new(10)));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+
+        assert_eq!(entity.add_ticket("verify", "ticket", "user", 60), None);
+        assert_eq!(entity.add_ticket("verify", "ticket", "nobody", 60), Some(AuthError::NotFound));
+        assert_eq!(entity.take_ticket("other", "ticket"), Err(AuthError::NotFound));
+        assert_eq!(entity.get_ticket("verify", "ticket"), Ok("user".to_string()));
+        assert_eq!(entity.take_ticket("verify", "ticket"), Ok("user".to_string()));
+        assert_eq!(entity.get_ticket("verify", "ticket"), Err(AuthError::NotFound));
+        assert_eq!(entity.take_ticket("verify", "ticket"), Err(AuthError::NotFound));
+
+        assert_eq!(entity.add_ticket("verify", "expired", "user", 0), None);
+        assert_eq!(entity.take_ticket("verify", "expired"), Err(AuthError::NotFound));
+    }
+
+    #[test]
+    fn test_delete_user() {
+        let entity = MemoryEntity::new().with_hasher(Box::new(Pbkdf2Hasher::new(10)));
+        let user = entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+        entity.enable_user("user").unwrap();
+
+        let session = entity.add_session("user", "access", &ClientInfo::default()).unwrap();
+        assert_eq!(entity.add_refresh_token("user", "refresh", &session), None);
+        assert_eq!(entity.add_ticket("verify", "ticket", "user", 60), None);
+        assert_eq!(entity.set_mfa_secret("user", Some(MfaSecret::new("JBSWY3DPEHPK3PXP"))), None);
+        assert_eq!(entity.set_recovery_codes("user", &["first".to_string()]), None);
+
+        assert_eq!(entity.delete_user(user.id), None);
+        entity.add_user("user", "other@example.com", "other secret", HashMap::new()).unwrap();
+        entity.enable_user("user").unwrap();
+
+        assert_eq!(entity.get_user_by_token("access"), Err(AuthError::NotFound));
+        assert_eq!(entity.take_refresh_token("refresh"), Err(AuthError::NotFound));
+        assert_eq!(entity.take_ticket("verify", "ticket"), Err(AuthError::NotFound));
+        assert_eq!(entity.get_mfa_secret("user"), Err(AuthError::NotFound));
+        assert_eq!(entity.count_recovery_codes("user"), Ok(0));
+        assert!(entity.list_sessions("user").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mfa_secret() {
+        let entity = MemoryEntity::new().with_hasher(Box::new(Pbkdf2Hasher::new(10)));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+
+        assert_eq!(entity.get_mfa_secret("user"), Err(AuthError::NotFound));
+        assert_eq!(entity.set_mfa_secret("nobody", Some(MfaSecret::new("JBSWY3DPEHPK3PXP"))), Some(AuthError::NotFound));
+        assert_eq!(entity.set_mfa_secret("user", Some(MfaSecret::new("JBSWY3DPEHPK3PXP"))), None);
+        assert_eq!(entity.get_mfa_secret("user"), Ok(MfaSecret::new("JBSWY3DPEHPK3PXP")));
+        assert_eq!(entity.set_mfa_secret("user", None), None);
+        assert_eq!(entity.get_mfa_secret("user"), Err(AuthError::NotFound));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let entity = MemoryEntity::new().with_hasher(Box::new(Pbkdf2Hasher::new(10)));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+
+        assert_eq!(entity.count_recovery_codes("user"), Ok(0));
+        assert_eq!(entity.set_recovery_codes("user", &["first".to_string(), "second".to_string()]), None);
+        assert_eq!(entity.take_recovery_code("user", "first"), None);
+        assert_eq!(entity.take_recovery_code("user", "first"), Some(AuthError::NotFound));
+        assert_eq!(entity.count_recovery_codes("user"), Ok(1));
+
+        assert_eq!(entity.set_recovery_codes("user", &["third".to_string()]), None);
+        assert_eq!(entity.take_recovery_code("user", "second"), Some(AuthError::NotFound));
+        assert_eq!(entity.count_recovery_codes("user"), Ok(1));
+    }
+
+    #[test]
+    fn test_counters() {
+        let entity = MemoryEntity::new();
+
+        assert_eq!(entity.get_counter("failures"), Ok(0));
+        assert_eq!(entity.incr_counter("failures", 60), Ok(1));
+        assert_eq!(entity.incr_counter("failures", 60), Ok(2));
+        assert_eq!(entity.get_counter("failures"), Ok(2));
+        assert_eq!(entity.reset_counter("failures"), None);
+        assert_eq!(entity.get_counter("failures"), Ok(0));
+
+        assert_eq!(entity.set_counter("last", 1_500_000_000, 60), None);
+        assert_eq!(entity.get_counter("last"), Ok(1_500_000_000));
+
+        assert_eq!(entity.incr_counter("expired", 0), Ok(1));
+        assert_eq!(entity.get_counter("expired"), Ok(0));
+        assert_eq!(entity.incr_counter("expired", 60), Ok(1));
+    }
+
+    #[test]
+    fn test_rehash_outdated_password() {
+        let entity = MemoryEntity::new().with_hasher(Box::new(Pbkdf2Hasher::new(10)));
+        entity.add_user("user", "user@example.com", "secret", HashMap::new()).unwrap();
+
+        let entity = entity.with_hasher(Box::new(Pbkdf2Hasher::new(20)));
+        entity.get_user_by_name_and_pwd("user", "secret").unwrap();
+        assert!(entity.get_user_by_name("user").unwrap().password.starts_with("$pbkdf2-sha256$i=20$"));
+
+        entity.update_user("user", |u| u.password = "5ebe2294ecd0e0f08eab7690d2a6ee69".to_string()).unwrap();
+        assert!(entity.get_user_by_name_and_pwd("user", "Secret").is_err());
+        entity.get_user_by_name_and_pwd("user", "secret").unwrap();
+        assert!(entity.get_user_by_name("user").unwrap().password.starts_with("$pbkdf2-sha256$i=20$"));
+    }
+}
@@ -0,0 +1,23 @@
+pub mod totp;
+pub mod recovery;
+
+/// TOTP secret of a user as the entity stores it
+///
+/// The secret is `confirmed` once the user proved the authenticator app has it,
+/// only then sign in asks for the code. `last_step` keeps every code single use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MfaSecret {
+    pub secret: String,
+    pub confirmed: bool,
+    pub last_step: i64
+}
+
+impl MfaSecret {
+    pub fn new(secret: &str) -> Self {
+        MfaSecret {
+            secret: secret.to_string(),
+            confirmed: false,
+            last_step: 0
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use ::mfa::totp::base32_encode;
+use ::net::secure_random_bytes;
+use ::session::ticket::hash_ticket;
+
+/// Size of a batch of recovery codes
+pub const RECOVERY_CODES: usize = 10;
+/// Characters of a code, 50 random bits are enough for a single-use code which is only checked during sign in
+const CODE_LENGTH: usize = 10;
+
+/// Fresh batch of codes like `K3XQ7-MZ2PA` to show the user once, only their hashes are stored
+pub fn generate_recovery_codes(count: usize) -> Option<Vec<String>> {
+    let mut codes = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let bytes = secure_random_bytes(CODE_LENGTH)?;
+        let code = base32_encode(&bytes);
+        codes.push(format!("{}-{}", &code[..CODE_LENGTH / 2], &code[CODE_LENGTH / 2..CODE_LENGTH]));
+    }
+
+    Some(codes)
+}
+
+/// Hash the code is stored under, case, spaces and dashes don't matter
+///
+/// ```
+/// use auth_rocket::mfa::recovery::hash_recovery_code;
+///
+/// assert_eq!(hash_recovery_code("k3xq7 mz2pa"), hash_recovery_code("K3XQ7-MZ2PA"));
+/// ```
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars()
+        .filter(|c| c.is_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    hash_ticket(normalized.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ generate_recovery_codes, hash_recovery_code };
+
+    #[test]
+    fn test_generate_recovery_codes() {
+        let mut codes = generate_recovery_codes(10).unwrap();
+        assert_eq!(codes.len(), 10);
+        assert!(codes.iter().all(|c| c.len() == 11 && c.chars().nth(5) == Some('-')));
+
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), 10);
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+    }
+}
@@ -0,0 +1,230 @@
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use crypto::util::fixed_time_eq;
+use rocket::http::uri::URI;
+use ::net::secure_random_bytes;
+
+/// 160 bits, the length of the SHA-1 output as RFC 4226 recommends
+const SECRET_LENGTH: usize = 20;
+const BASE32_ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 without padding, the form authenticator apps expect secrets in
+///
+/// ```
+/// use auth_rocket::mfa::totp::{ base32_encode, base32_decode };
+///
+/// assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+/// assert_eq!(base32_decode("mzxw 6ytb oi======"), Some(b"foobar".to_vec()));
+/// ```
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            out.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 31) as usize] as char);
+            bits -= 5;
+        }
+    }
+
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    out
+}
+
+/// Case insensitive, spaces and padding are ignored
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in data.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase()) {
+            Some(value) => value as u32,
+            None => return None
+        };
+
+        buffer = (buffer << 5) | value;
+        bits += 5;
+
+        if bits >= 8 {
+            out.push((buffer >> (bits - 8)) as u8);
+            bits -= 8;
+        }
+    }
+
+    Some(out)
+}
+
+/// RFC 4226 HMAC-SHA1 one-time password of the counter
+fn hotp(key: &[u8], counter: u64, digits: u32) -> String {
+    let mut bytes = [0u8; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (counter >> (8 * (7 - i))) as u8;
+    }
+
+    let mut mac = Hmac::new(Sha1::new(), key);
+    mac.input(&bytes);
+    let hash = mac.result();
+    let hash = hash.code();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+
+    format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+}
+
+/// RFC 6238 time-based one-time passwords, put it to the Rocket managed state to change the default
+///
+/// ```
+/// use auth_rocket::mfa::totp::{ Totp, base32_encode };
+///
+/// let totp = Totp::new("Example").with_digits(8);
+/// let secret = base32_encode(b"12345678901234567890");
+///
+/// // test vector of RFC 6238
+/// assert_eq!(totp.code(&secret, totp.step(59)), Some("94287082".to_string()));
+/// assert_eq!(totp.verify(&secret, "94287082", 59, 0), Some(1));
+/// assert_eq!(totp.verify(&secret, "94287082", 59, 1), None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totp {
+    issuer: String,
+    digits: u32,
+    period: i64,
+    skew: i64,
+    ticket_ttl: i64
+}
+
+impl Totp {
+    /// `issuer` is the name authenticator apps show next to the code
+    pub fn new(issuer: &str) -> Self {
+        Totp {
+            issuer: issuer.to_string(),
+            digits: 6,
+            period: 30,
+            skew: 1,
+            ticket_ttl: 300
+        }
+    }
+
+    pub fn with_digits(mut self, digits: u32) -> Self {
+        self.digits = digits;
+        self
+    }
+
+    /// Accept codes of that many steps before and after the current one, for clock drift
+    pub fn with_skew(mut self, steps: i64) -> Self {
+        self.skew = steps;
+        self
+    }
+
+    /// Seconds between the password and the code during sign in, 5 minutes by default
+    pub fn with_ticket_ttl(mut self, ttl: i64) -> Self {
+        self.ticket_ttl = ttl;
+        self
+    }
+
+    pub fn ticket_ttl(&self) -> i64 {
+        self.ticket_ttl
+    }
+
+    /// Fresh base32 secret for enrollment
+    pub fn generate_secret(&self) -> Option<String> {
+        secure_random_bytes(SECRET_LENGTH).map(|bytes| base32_encode(&bytes))
+    }
+
+    /// Key URI for the QR code of authenticator apps
+    pub fn uri(&self, account: &str, secret: &str) -> String {
+        format!("otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+                URI::percent_encode(&self.issuer), URI::percent_encode(account), secret,
+                URI::percent_encode(&self.issuer), self.digits, self.period)
+    }
+
+    /// Number of the time step of the timestamp
+    pub fn step(&self, now: i64) -> i64 {
+        now / self.period
+    }
+
+    /// Code of the step, `None` if the secret is not base32
+    pub fn code(&self, secret: &str, step: i64) -> Option<String> {
+        base32_decode(secret).map(|key| hotp(&key, step as u64, self.digits))
+    }
+
+    /// Step of the code if it is valid now and later than `last_step`, so a code works only once
+    pub fn verify(&self, secret: &str, code: &str, now: i64, last_step: i64) -> Option<i64> {
+        let key = match base32_decode(secret) {
+            Some(key) => key,
+            None => return None
+        };
+
+        let current = self.step(now);
+
+        ((current - self.skew)..(current + self.skew + 1))
+            .filter(|&step| step > last_step && step >= 0)
+            .filter(|&step| fixed_time_eq(hotp(&key, step as u64, self.digits).as_bytes(), code.trim().as_bytes()))
+            .next()
+    }
+}
+
+impl Default for Totp {
+    fn default() -> Self {
+        Totp::new("auth_rocket")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Totp, base32_encode, base32_decode, hotp };
+
+    #[test]
+    fn test_base32() {
+        for data in &[&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(base32_decode(&base32_encode(data)).unwrap(), data.to_vec());
+        }
+        assert_eq!(base32_encode(b"fooba"), "MZXW6YTB");
+        assert_eq!(base32_decode("MZXW1"), None);
+    }
+
+    #[test]
+    fn test_hotp() {
+        // test vectors of RFC 4226
+        let key = b"12345678901234567890";
+        let codes = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp(key, counter as u64, 6), code.to_string());
+        }
+    }
+
+    #[test]
+    fn test_totp() {
+        let totp = Totp::new("Example").with_digits(8);
+        let secret = base32_encode(b"12345678901234567890");
+
+        // test vectors of RFC 6238
+        assert_eq!(totp.code(&secret, totp.step(1111111109)), Some("07081804".to_string()));
+        assert_eq!(totp.code(&secret, totp.step(1234567890)), Some("89005924".to_string()));
+        assert_eq!(totp.code(&secret, totp.step(2000000000)), Some("69279037".to_string()));
+
+        assert_eq!(totp.verify(&secret, "07081804", 1111111109 + 30, 0), Some(totp.step(1111111109)));
+        assert_eq!(totp.verify(&secret, "07081804", 1111111109 + 60, 0), None);
+        assert_eq!(totp.verify(&secret, "00000000", 1111111109, 0), None);
+    }
+
+    #[test]
+    fn test_uri() {
+        let totp = Totp::new("Wayne Enterprises");
+        assert_eq!(totp.uri("batman", "JBSWY3DPEHPK3PXP"),
+                   "otpauth://totp/Wayne%20Enterprises:batman?secret=JBSWY3DPEHPK3PXP&issuer=Wayne%20Enterprises&algorithm=SHA1&digits=6&period=30");
+    }
+}
@@ -0,0 +1,29 @@
+use rocket::request::{self, Request, FromRequest};
+use rocket::Outcome;
+
+/// Information about the client stored with its session
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>
+}
+
+impl ClientInfo {
+    pub fn new(user_agent: Option<String>, ip: Option<String>) -> Self {
+        ClientInfo {
+            user_agent: user_agent,
+            ip: ip
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ClientInfo, ()> {
+        Outcome::Success(ClientInfo::new(
+            request.headers().get_one("User-Agent").map(|ua| ua.to_string()),
+            request.remote().map(|addr| addr.ip().to_string())
+        ))
+    }
+}
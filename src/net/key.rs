@@ -1,43 +1,289 @@
 use crypto::sha1;
 use crypto::digest::Digest;
-use ::net::random_string;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use base64;
+use std::collections::HashMap;
+use rocket::Outcome;
+use rocket::Config;
+use rocket::http::Status;
+use rocket::request::{ self, Request, FromRequest, State };
+use ::net::secure_random_bytes;
+
+const VERSION_PREFIX: &'static str = "v2.";
+const RING_VERSION_PREFIX: &'static str = "v3.";
+const BODY_LENGTH: usize = 24;
+const LEGACY_LENGTH: usize = 32;
 
 pub struct PrivateKey {
-    key: String
+    key: String,
+    accept_legacy: bool
 }
 
 impl PrivateKey {
 
     pub fn new(key: String) -> Self {
         PrivateKey {
-            key: key
+            key: key,
+            accept_legacy: true
         }
     }
 
+    /// Accept keys of the old truncated SHA-1 format, on by default until all of them expire
+    pub fn with_legacy_keys(mut self, accept_legacy: bool) -> Self {
+        self.accept_legacy = accept_legacy;
+        self
+    }
+
     pub fn as_str(&self) -> &str {
         &self.key
     }
+
+    /// Check the key was signed by that secret
+    ///
+    /// ```
+    /// use auth_rocket::{ generate_api_key, PrivateKey };
+    ///
+    /// let legacy = "abcdefghijklmnopqrstuvwxyz012345";
+    /// let private_key = PrivateKey::new("secret word".to_string()).with_legacy_keys(false);
+    /// assert!(private_key.validate(&generate_api_key("secret word").unwrap()));
+    /// assert!(!private_key.validate(legacy));
+    /// ```
+    pub fn validate(&self, key: &str) -> bool {
+        match is_signed_key(key) {
+            true => validate_signed_key(key, &self.key),
+            false => self.accept_legacy && validate_legacy_key(key, &self.key)
+        }
+    }
+}
+
+/// Several secrets identified by key ids, so the signing secret can be rotated
+///
+/// New keys are signed by the current secret and carry its id, `v3.<kid>.<body>.<tag>`.
+/// Verification-only secrets keep keys issued before the rotation valid until they expire.
+/// Key ids must not contain dots, the constructors panic on them.
+///
+/// ```
+/// use auth_rocket::net::key::KeyRing;
+///
+/// let old = KeyRing::new("2018-01", "old secret".to_string());
+/// let old_key = old.generate().unwrap();
+/// assert!(old_key.starts_with("v3.2018-01."));
+///
+/// let ring = KeyRing::new("2018-06", "new secret".to_string())
+///     .with_verification_key("2018-01", "old secret".to_string());
+/// assert!(ring.validate(&old_key));
+/// assert!(ring.validate(&ring.generate().unwrap()));
+/// assert!(!old.validate(&ring.generate().unwrap()));
+/// ```
+pub struct KeyRing {
+    current: String,
+    keys: HashMap<String, String>,
+    accept_legacy: bool
+}
+
+impl KeyRing {
+    /// Ring signing by that secret
+    ///
+    /// # Panics
+    ///
+    /// If the key id contains a dot, keys with it could never be validated.
+    pub fn new(kid: &str, secret: String) -> Self {
+        check_kid(kid);
+
+        let mut keys = HashMap::new();
+        keys.insert(kid.to_string(), secret);
+
+        KeyRing {
+            current: kid.to_string(),
+            keys: keys,
+            accept_legacy: true
+        }
+    }
+
+    /// Secret which only validates keys issued with it
+    ///
+    /// # Panics
+    ///
+    /// If the key id contains a dot, as `KeyRing::new`.
+    pub fn with_verification_key(mut self, kid: &str, secret: String) -> Self {
+        check_kid(kid);
+
+        if kid != self.current {
+            self.keys.insert(kid.to_string(), secret);
+        }
+        self
+    }
+
+    /// Accept keys without key id (`v2` and the old truncated SHA-1 ones) signed by any of the secrets
+    pub fn with_legacy_keys(mut self, accept_legacy: bool) -> Self {
+        self.accept_legacy = accept_legacy;
+        self
+    }
+
+    /// Load ring from the `api_keys` table of Rocket config
+    ///
+    /// ```toml
+    /// [global.api_keys]
+    /// current = "2018-06"
+    /// keys = { "2018-06" = "new secret", "2018-01" = "old secret" }
+    /// ```
+    pub fn from_config(config: &Config) -> Option<KeyRing> {
+        let table = match config.get_table("api_keys") {
+            Ok(table) => table,
+            Err(e) => {
+                error!("Cannot read api_keys from config: {}", e);
+                return None;
+            }
+        };
+
+        let keys = match table.get("keys").and_then(|keys| keys.as_table()) {
+            Some(keys) => keys,
+            None => {
+                error!("api_keys.keys must be a table of secrets by key id");
+                return None;
+            }
+        };
+
+        let current = match table.get("current").and_then(|c| c.as_str()) {
+            Some(current) => current,
+            None => {
+                error!("api_keys.current must be a key id");
+                return None;
+            }
+        };
+
+        if current.contains('.') {
+            error!("api_keys.current {} can't have dots", current);
+            return None;
+        }
+
+        let mut ring = match keys.get(current).and_then(|secret| secret.as_str()) {
+            Some(secret) => KeyRing::new(current, secret.to_string()),
+            None => {
+                error!("api_keys.keys has no secret for the current key id {}", current);
+                return None;
+            }
+        };
+
+        for (kid, secret) in keys.iter() {
+            match (kid.contains('.'), secret.as_str()) {
+                (false, Some(secret)) => ring = ring.with_verification_key(kid, secret.to_string()),
+                _ => error!("api_keys.keys.{} is skipped, key id can't have dots and secret must be a string", kid)
+            }
+        }
+
+        Some(ring)
+    }
+
+    /// Key id of the secret new keys are signed by
+    pub fn current_kid(&self) -> &str {
+        &self.current
+    }
+
+    /// Secrets by key id, the current one included
+    pub(crate) fn secrets(&self) -> &HashMap<String, String> {
+        &self.keys
+    }
+
+    /// Generate key signed by the current secret
+    pub fn generate(&self) -> Option<String> {
+        let secret = &self.keys[&self.current];
+        sign_random_body(&format!("{}{}.", RING_VERSION_PREFIX, self.current), secret)
+    }
+
+    pub fn validate(&self, key: &str) -> bool {
+        if key.starts_with(RING_VERSION_PREFIX) {
+            return key[RING_VERSION_PREFIX.len()..].split('.').next()
+                .and_then(|kid| self.keys.get(kid))
+                .map(|secret| validate_signed_key(key, secret))
+                .unwrap_or(false);
+        }
+
+        match key.starts_with(VERSION_PREFIX) {
+            true => self.keys.values().any(|secret| validate_signed_key(key, secret)),
+            false => self.accept_legacy && self.keys.values().any(|secret| validate_legacy_key(key, secret))
+        }
+    }
+}
+
+fn check_kid(kid: &str) {
+    if kid.contains('.') {
+        panic!("Key id {} of KeyRing can't contain dots", kid);
+    }
+}
+
+/// Keys the application signs api keys with: `KeyRing` if it is managed, `PrivateKey` otherwise
+pub enum ApiKeys<'r> {
+    Ring(&'r KeyRing),
+    Single(&'r PrivateKey)
+}
+
+impl<'r> ApiKeys<'r> {
+    pub fn generate(&self) -> Option<String> {
+        match *self {
+            ApiKeys::Ring(ring) => ring.generate(),
+            ApiKeys::Single(key) => generate_api_key(key.as_str())
+        }
+    }
+
+    pub fn validate(&self, key: &str) -> bool {
+        match *self {
+            ApiKeys::Ring(ring) => ring.validate(key),
+            ApiKeys::Single(private_key) => private_key.validate(key)
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ApiKeys<'r> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ApiKeys<'r>, ()> {
+        if let Outcome::Success(ring) = request.guard::<State<KeyRing>>() {
+            return Outcome::Success(ApiKeys::Ring(ring.inner()));
+        }
+
+        match request.guard::<State<PrivateKey>>() {
+            Outcome::Success(key) => Outcome::Success(ApiKeys::Single(key.inner())),
+            _ => {
+                error!("Not found KeyRing or PrivateKey in request.");
+                Outcome::Failure((Status::InternalServerError, ()))
+            }
+        }
+    }
 }
 
 /// Generate valid api key
 ///
+/// The key is `v2.<random body>.<HMAC-SHA256 of "v2.<body>" in hex>`.
+///
 /// ```
 /// use auth_rocket::generate_api_key;
 /// use auth_rocket::net::key::validate_api_key;
 ///
 /// let key = generate_api_key("secret word").unwrap();
+/// assert!(key.starts_with("v2."));
 /// assert!(validate_api_key(key.as_str(), "secret word"));
 /// ```
 pub fn generate_api_key(secret: &str) -> Option<String> {
-    let random = random_string(26);
-    let mut sh = sha1::Sha1::new();
-    sh.input_str(&format!("{}{}", random, secret));
-    let hex = sh.result_str();
-    hex.get(0_..6).map(|hex_x| { format!("{}{}", random, hex_x) })
+    sign_random_body(VERSION_PREFIX, secret)
+}
+
+fn sign_random_body(prefix: &str, secret: &str) -> Option<String> {
+    secure_random_bytes(BODY_LENGTH).map(|body| {
+        let message = format!("{}{}", prefix, base64::encode_config(&body, base64::URL_SAFE_NO_PAD));
+        let tag = to_hex(&hmac_sha256(secret.as_bytes(), message.as_bytes()));
+        format!("{}.{}", message, tag)
+    })
 }
 
 /// Validate api key
 ///
+/// Keys of the old format are still accepted, use `PrivateKey::validate` to control that.
+/// Keys with a key id are checked against `secret` whatever the id is.
+///
 /// ```
 /// use auth_rocket::generate_api_key;
 /// use auth_rocket::net::key::validate_api_key;
@@ -47,41 +293,112 @@ pub fn generate_api_key(secret: &str) -> Option<String> {
 /// assert_eq!(validate_api_key(key.as_str(), "secret Word"), false);
 /// ```
 pub fn validate_api_key(key: &str, secret: &str) -> bool {
+    match is_signed_key(key) {
+        true => validate_signed_key(key, secret),
+        false => validate_legacy_key(key, secret)
+    }
+}
+
+fn is_signed_key(key: &str) -> bool {
+    key.starts_with(VERSION_PREFIX) || key.starts_with(RING_VERSION_PREFIX)
+}
 
-    if key.len() != 32 {
+fn validate_signed_key(key: &str, secret: &str) -> bool {
+    match key.rfind('.') {
+        Some(dot) if dot > VERSION_PREFIX.len() => {
+            let expected = to_hex(&hmac_sha256(secret.as_bytes(), key[..dot].as_bytes()));
+            fixed_time_eq(expected.as_bytes(), key[dot + 1..].as_bytes())
+        },
+        _ => false
+    }
+}
+
+/// Random 26 chars followed by the first 6 hex chars of `sha1(random + secret)`
+fn validate_legacy_key(key: &str, secret: &str) -> bool {
+    if key.len() != LEGACY_LENGTH {
         return false
     }
 
-    match key.get(26..) {
-        Some(hash) => {
-            match key.get(0..26) {
-                Some(key_real) => {
-                    let st = format!("{}{}", key_real, secret);
-                    let mut sh = sha1::Sha1::new();
-                    sh.input_str(st.as_str());
-                    let hex = sh.result_str();
-                    match hex.get(0..6) {
-                        Some(hex_x) => {
-                            hex_x == hash
-                        },
-                        _ => false
-                    }
-                },
-                _ => false
-            }
+    match (key.get(0..26), key.get(26..)) {
+        (Some(key_real), Some(hash)) => {
+            let mut sh = sha1::Sha1::new();
+            sh.input_str(&format!("{}{}", key_real, secret));
+            let hex = sh.result_str();
+            fixed_time_eq(hex[0..6].as_bytes(), hash.as_bytes())
         },
         _ => false
     }
 }
 
+pub(crate) fn hmac_sha256(secret: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::new(Sha256::new(), secret);
+    mac.input(message);
+    mac.result().code().to_vec()
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 #[cfg(test)]
 mod test {
-    use ::net::key::{ generate_api_key, validate_api_key };
+    use ::net::key::{ generate_api_key, validate_api_key, PrivateKey, KeyRing };
+    use crypto::sha1;
+    use crypto::digest::Digest;
 
     #[test]
     fn test_validate_api_key() {
         let key = generate_api_key("secret word").unwrap();
         assert_eq!(validate_api_key(key.as_str(), "secret Word"), false);
         assert_eq!(validate_api_key(key.as_str(), "secret word"), true);
+        assert_eq!(validate_api_key(&key[..key.len() - 1], "secret word"), false);
+        assert_eq!(validate_api_key(&key.replace("v2.", "v2.A"), "secret word"), false);
+    }
+
+    #[test]
+    fn test_legacy_api_key() {
+        let random = "abcdefghijklmnopqrstuvwxyz";
+        let mut sh = sha1::Sha1::new();
+        sh.input_str(&format!("{}{}", random, "secret word"));
+        let legacy = format!("{}{}", random, &sh.result_str()[0..6]);
+
+        assert!(validate_api_key(&legacy, "secret word"));
+        assert!(!validate_api_key(&legacy, "secret Word"));
+        assert!(PrivateKey::new("secret word".to_string()).validate(&legacy));
+        assert!(!PrivateKey::new("secret word".to_string()).with_legacy_keys(false).validate(&legacy));
+    }
+
+    #[test]
+    fn test_key_ring() {
+        let ring = KeyRing::new("new", "new secret".to_string())
+            .with_verification_key("old", "old secret".to_string());
+
+        let key = ring.generate().unwrap();
+        assert!(key.starts_with("v3.new."));
+        assert!(ring.validate(&key));
+        assert!(validate_api_key(&key, "new secret"));
+        assert!(!validate_api_key(&key, "old secret"));
+
+        let old = KeyRing::new("old", "old secret".to_string()).generate().unwrap();
+        assert!(ring.validate(&old));
+        assert!(!ring.validate(&old.replace("v3.old.", "v3.new.")));
+        assert!(!ring.validate(&KeyRing::new("old", "other secret".to_string()).generate().unwrap()));
+        assert!(!ring.validate(&KeyRing::new("unknown", "new secret".to_string()).generate().unwrap()));
+
+        assert!(ring.validate(&generate_api_key("old secret").unwrap()));
+        assert!(!ring.with_legacy_keys(false).validate("abcdefghijklmnopqrstuvwxyz012345"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Key id 2018.06 of KeyRing can't contain dots")]
+    fn test_key_ring_dotted_kid() {
+        KeyRing::new("2018.06", "new secret".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Key id 2018.01 of KeyRing can't contain dots")]
+    fn test_key_ring_dotted_verification_kid() {
+        KeyRing::new("2018-06", "new secret".to_string())
+            .with_verification_key("2018.01", "old secret".to_string());
     }
 }
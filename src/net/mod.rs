@@ -1,7 +1,9 @@
+pub mod client;
 pub mod key;
 pub mod uri;
 
 use rand;
+use rand::{ OsRng, Rng };
 
 /// Get random string
 ///
@@ -37,6 +39,27 @@ pub fn random_string_with_consonants(len: u8, consonants: Option<&str>) -> Strin
     result
 }
 
+/// Get bytes from the OS random generator, suitable for salts and secrets
+///
+/// ```
+/// use auth_rocket::net::secure_random_bytes;
+///
+/// assert_eq!(secure_random_bytes(16).unwrap().len(), 16);
+/// ```
+pub fn secure_random_bytes(len: usize) -> Option<Vec<u8>> {
+    match OsRng::new() {
+        Ok(mut rng) => {
+            let mut bytes = vec![0u8; len];
+            rng.fill_bytes(&mut bytes);
+            Some(bytes)
+        },
+        Err(e) => {
+            error!("Cannot get OS random generator: {}", e);
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use ::net::{ random_string, random_string_with_consonants };
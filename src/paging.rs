@@ -0,0 +1,30 @@
+/// Translate redis like `ZRANGE` bounds (inclusive, negative from the end) to slice bounds
+///
+/// Every entity lists users by these bounds, so `list_users` pages the same way whatever the storage.
+pub(crate) fn range_bounds(len: usize, from: isize, to: isize) -> Option<(usize, usize)> {
+    let len = len as isize;
+    let from = if from < 0 { len + from } else { from };
+    let to = if to < 0 { len + to } else { to };
+    let from = if from < 0 { 0 } else { from };
+    let to = if to >= len { len - 1 } else { to };
+
+    match from <= to && from < len {
+        true => Some((from as usize, to as usize + 1)),
+        false => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::range_bounds;
+
+    #[test]
+    fn test_range_bounds() {
+        assert_eq!(range_bounds(3, 0, 1_000_000), Some((0, 3)));
+        assert_eq!(range_bounds(3, 1, 10), Some((1, 3)));
+        assert_eq!(range_bounds(3, 0, -1), Some((0, 3)));
+        assert_eq!(range_bounds(3, -2, -2), Some((1, 2)));
+        assert_eq!(range_bounds(3, 5, 10), None);
+        assert_eq!(range_bounds(0, 0, 10), None);
+    }
+}
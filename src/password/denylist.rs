@@ -0,0 +1,238 @@
+use std::fmt;
+use std::fs::{ self, File };
+use std::io::{ BufRead, BufReader };
+use std::path::Path;
+use crypto::sha1::Sha1;
+use crypto::digest::Digest;
+use ::AuthError;
+
+const HASH_LENGTH: usize = 20;
+/// Length in hex digits of the prefixes which name the k-anonymity range files
+const PREFIX_LENGTH: usize = 5;
+
+type Hash = [u8; HASH_LENGTH];
+
+/// Breached or common passwords, kept as a sorted list of SHA-1 hashes
+///
+/// Load it once at startup and add it to the password policy with `PasswordPolicy::with_denylist`.
+///
+/// ```
+/// use auth_rocket::password::denylist::Denylist;
+///
+/// let denylist = Denylist::from_passwords(&["123456", "password", "qwerty"]);
+/// assert!(denylist.contains("password"));
+/// assert!(!denylist.contains("correct horse battery staple"));
+/// ```
+#[derive(Clone, PartialEq, Default)]
+pub struct Denylist {
+    hashes: Vec<Hash>
+}
+
+impl Denylist {
+    pub fn new() -> Self {
+        Denylist::default()
+    }
+
+    pub fn from_passwords<S: AsRef<str>>(passwords: &[S]) -> Self {
+        Denylist::from_hashes(passwords.iter().map(|p| sha1(p.as_ref())).collect())
+    }
+
+    /// File with one password per line
+    pub fn load_plain(path: &Path) -> Result<Self, AuthError> {
+        let mut hashes = Vec::new();
+
+        for_each_line(path, |line| {
+            if !line.is_empty() {
+                hashes.push(sha1(line));
+            }
+            true
+        })?;
+
+        Ok(Denylist::from_hashes(hashes))
+    }
+
+    /// File with one hex SHA-1 per line, optionally followed by `:count` like the HIBP dump
+    pub fn load_hashes(path: &Path) -> Result<Self, AuthError> {
+        let mut hashes = Vec::new();
+
+        for_each_line(path, |line| match parse_hash("", line) {
+            Some(hash) => {
+                hashes.push(hash);
+                true
+            },
+            None => line.is_empty()
+        })?;
+
+        Ok(Denylist::from_hashes(hashes))
+    }
+
+    /// Directory of k-anonymity range files, every file is named by the first 5 hex digits
+    /// of the hashes (`21BD1.txt` or just `21BD1`) and lists the remaining digits as `SUFFIX:count`
+    pub fn load_ranges(dir: &Path) -> Result<Self, AuthError> {
+        let entries = fs::read_dir(dir).map_err(|e| {
+            error!("Cannot read password denylist directory {}: {}", dir.display(), e);
+            AuthError::IOError
+        })?;
+
+        let mut hashes = Vec::new();
+
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    error!("Cannot read password denylist directory {}: {}", dir.display(), e);
+                    return Err(AuthError::IOError);
+                }
+            };
+
+            let prefix = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(prefix) if prefix.len() == PREFIX_LENGTH && prefix.chars().all(|c| c.is_digit(16)) => prefix.to_string(),
+                _ => {
+                    debug!("Skip {}, it is not a range file", path.display());
+                    continue;
+                }
+            };
+
+            for_each_line(&path, |line| match parse_hash(&prefix, line) {
+                Some(hash) => {
+                    hashes.push(hash);
+                    true
+                },
+                None => line.is_empty()
+            })?;
+        }
+
+        Ok(Denylist::from_hashes(hashes))
+    }
+
+    fn from_hashes(mut hashes: Vec<Hash>) -> Self {
+        hashes.sort();
+        hashes.dedup();
+        hashes.shrink_to_fit();
+
+        Denylist {
+            hashes: hashes
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.hashes.binary_search(&sha1(password)).is_ok()
+    }
+}
+
+impl fmt::Debug for Denylist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Denylist({} hashes)", self.hashes.len())
+    }
+}
+
+fn sha1(password: &str) -> Hash {
+    let mut sh = Sha1::new();
+    sh.input_str(password);
+
+    let mut hash = [0u8; HASH_LENGTH];
+    sh.result(&mut hash);
+    hash
+}
+
+/// Hash of the `prefix` followed by the hex digits of the line up to the optional `:count`
+fn parse_hash(prefix: &str, line: &str) -> Option<Hash> {
+    let digits = format!("{}{}", prefix, line.split(':').next().unwrap_or("").trim());
+
+    if digits.len() != HASH_LENGTH * 2 || !digits.is_ascii() {
+        return None;
+    }
+
+    let mut hash = [0u8; HASH_LENGTH];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = match u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16) {
+            Ok(byte) => byte,
+            Err(_) => return None
+        };
+    }
+
+    Some(hash)
+}
+
+/// Call `f` with every trimmed line of the file, `f` returns false for malformed lines which are skipped
+fn for_each_line<F>(path: &Path, mut f: F) -> Result<(), AuthError> where F: FnMut(&str) -> bool {
+    let file = File::open(path).map_err(|e| {
+        error!("Cannot open password denylist {}: {}", path.display(), e);
+        AuthError::IOError
+    })?;
+
+    let mut malformed = 0;
+
+    for line in BufReader::new(file).lines() {
+        match line {
+            Ok(line) => if !f(line.trim()) {
+                malformed += 1;
+            },
+            Err(e) => {
+                error!("Cannot read password denylist {}: {}", path.display(), e);
+                return Err(AuthError::IOError);
+            }
+        }
+    }
+
+    if malformed > 0 {
+        warn!("{} malformed lines skipped in password denylist {}", malformed, path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Denylist;
+    use std::env;
+    use std::fs::{ self, File };
+    use std::io::Write;
+
+    #[test]
+    fn test_load_plain() {
+        let path = env::temp_dir().join("auth_rocket_denylist_plain.txt");
+        File::create(&path).unwrap().write_all(b"123456\npassword\n\npassword\n").unwrap();
+
+        let denylist = Denylist::load_plain(&path).unwrap();
+        assert_eq!(denylist.len(), 2);
+        assert!(denylist.contains("123456"));
+        assert!(!denylist.contains("Password"));
+    }
+
+    #[test]
+    fn test_load_hashes() {
+        let path = env::temp_dir().join("auth_rocket_denylist_hashes.txt");
+        // SHA-1 of "password" and "123456" in the HIBP dump format, and a broken line
+        File::create(&path).unwrap().write_all(b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\n\
+                                                  7c4a8d09ca3762af61e59520943dc26494f8941b\n\
+                                                  NOT A HASH\n").unwrap();
+
+        let denylist = Denylist::load_hashes(&path).unwrap();
+        assert_eq!(denylist.len(), 2);
+        assert!(denylist.contains("password"));
+        assert!(denylist.contains("123456"));
+    }
+
+    #[test]
+    fn test_load_ranges() {
+        let dir = env::temp_dir().join("auth_rocket_denylist_ranges");
+        fs::create_dir_all(&dir).unwrap();
+        File::create(dir.join("5BAA6.txt")).unwrap().write_all(b"1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\r\n\
+                                                                 1E56E66E3E4E9A2F5BB23E3F52C3F3D3A08:2\r\n").unwrap();
+        File::create(dir.join("README")).unwrap().write_all(b"range files").unwrap();
+
+        let denylist = Denylist::load_ranges(&dir).unwrap();
+        assert_eq!(denylist.len(), 2);
+        assert!(denylist.contains("password"));
+        assert!(!denylist.contains("123456"));
+    }
+}
@@ -0,0 +1,295 @@
+pub mod policy;
+pub mod denylist;
+
+use argon2;
+use bcrypt;
+use base64;
+use crypto::pbkdf2::pbkdf2;
+use crypto::hmac::Hmac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use crypto::md5::Md5;
+use crypto::digest::Digest;
+use std::str::FromStr;
+use ::net::secure_random_bytes;
+use ::AuthError;
+
+const SALT_LENGTH: usize = 16;
+
+/// Algorithm used by entity to store user passwords
+///
+/// Every implementation produces self-describing strings (PHC format, or the
+/// modular crypt format for bcrypt), so stored hashes carry their own parameters.
+///
+/// ```
+/// use auth_rocket::password::{ PasswordHasher, Pbkdf2Hasher };
+///
+/// let hasher = Pbkdf2Hasher::new(1_000);
+/// let hash = hasher.hash("secret").unwrap();
+/// assert!(hash.starts_with("$pbkdf2-sha256$i=1000$"));
+/// assert!(hasher.verify("secret", &hash));
+/// assert!(!hasher.verify("Secret", &hash));
+/// ```
+pub trait PasswordHasher: Send + Sync + 'static {
+    /// Hash password with fresh random salt
+    fn hash(&self, password: &str) -> Result<String, AuthError>;
+    /// Check password against hash produced by that hasher
+    fn verify(&self, password: &str, hash: &str) -> bool;
+    /// Is the hash made by another algorithm or with other parameters than the current ones
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+/// Result of `verify_password`
+#[derive(Debug, PartialEq)]
+pub enum PasswordMatch {
+    /// Password is wrong
+    Invalid,
+    /// Password is right and the hash is up to date
+    Valid,
+    /// Password is right, but the hash should be replaced by a fresh one
+    Outdated
+}
+
+/// Check password against any hash format the crate ever stored
+///
+/// Hashes made by other algorithms, with outdated parameters or the legacy
+/// unsalted MD5 hex digests are reported as `PasswordMatch::Outdated`, so
+/// the entity can re-hash the password with `hasher` while it is known.
+///
+/// ```
+/// use auth_rocket::password::{ verify_password, PasswordMatch, Pbkdf2Hasher };
+///
+/// let hasher = Pbkdf2Hasher::new(1_000);
+/// let md5 = "5ebe2294ecd0e0f08eab7690d2a6ee69";
+/// assert_eq!(verify_password(&hasher, "secret", md5), PasswordMatch::Outdated);
+/// assert_eq!(verify_password(&hasher, "Secret", md5), PasswordMatch::Invalid);
+/// ```
+pub fn verify_password(hasher: &PasswordHasher, password: &str, hash: &str) -> PasswordMatch {
+    if hasher.verify(password, hash) {
+        return match hasher.needs_rehash(hash) {
+            true => PasswordMatch::Outdated,
+            false => PasswordMatch::Valid
+        };
+    }
+
+    let valid = if hash.starts_with("$argon2") {
+        Argon2Hasher::default().verify(password, hash)
+    } else if hash.starts_with("$2") {
+        BcryptHasher::default().verify(password, hash)
+    } else if hash.starts_with("$pbkdf2-sha256$") {
+        Pbkdf2Hasher::default().verify(password, hash)
+    } else if is_legacy_md5(hash) {
+        let mut sh = Md5::new();
+        sh.input_str(password);
+        fixed_time_eq(sh.result_str().as_bytes(), hash.to_lowercase().as_bytes())
+    } else {
+        false
+    };
+
+    match valid {
+        true => PasswordMatch::Outdated,
+        false => PasswordMatch::Invalid
+    }
+}
+
+fn is_legacy_md5(hash: &str) -> bool {
+    hash.len() == 32 && hash.chars().all(|c| c.is_digit(16))
+}
+
+fn salt() -> Result<Vec<u8>, AuthError> {
+    secure_random_bytes(SALT_LENGTH).ok_or(AuthError::IOError)
+}
+
+/// Argon2id hasher, the default one
+pub struct Argon2Hasher {
+    mem_cost: u32,
+    time_cost: u32,
+    lanes: u32
+}
+
+impl Argon2Hasher {
+    /// `mem_cost` in KiB, `time_cost` is number of passes
+    pub fn new(mem_cost: u32, time_cost: u32, lanes: u32) -> Self {
+        Argon2Hasher {
+            mem_cost: mem_cost,
+            time_cost: time_cost,
+            lanes: lanes
+        }
+    }
+}
+
+impl Default for Argon2Hasher {
+    fn default() -> Self {
+        Argon2Hasher::new(4096, 3, 1)
+    }
+}
+
+impl PasswordHasher for Argon2Hasher {
+    fn hash(&self, password: &str) -> Result<String, AuthError> {
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            ..argon2::Config::default()
+        };
+
+        argon2::hash_encoded(password.as_bytes(), &salt()?, &config).map_err(|e| {
+            error!("Cannot hash password with argon2: {}", e);
+            AuthError::IOError
+        })
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        hash.starts_with("$argon2") && argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let params = format!("$m={},t={},p={}$", self.mem_cost, self.time_cost, self.lanes);
+        !(hash.starts_with("$argon2id$v=19$") && hash[14..].starts_with(&params))
+    }
+}
+
+/// Bcrypt hasher, hashes look like `$2y$<cost>$...`
+pub struct BcryptHasher {
+    cost: u32
+}
+
+impl BcryptHasher {
+    pub fn new(cost: u32) -> Self {
+        BcryptHasher {
+            cost: cost
+        }
+    }
+}
+
+impl Default for BcryptHasher {
+    fn default() -> Self {
+        BcryptHasher::new(bcrypt::DEFAULT_COST)
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> Result<String, AuthError> {
+        bcrypt::hash(password, self.cost).map_err(|e| {
+            error!("Cannot hash password with bcrypt: {}", e);
+            AuthError::IOError
+        })
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        hash.starts_with("$2") && bcrypt::verify(password, hash).unwrap_or(false)
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let parts: Vec<&str> = hash.split('$').collect();
+        parts.len() != 4 || u32::from_str(parts[2]).ok() != Some(self.cost)
+    }
+}
+
+/// PBKDF2 with HMAC-SHA256, hashes look like `$pbkdf2-sha256$i=<iterations>$<salt>$<hash>`
+pub struct Pbkdf2Hasher {
+    iterations: u32
+}
+
+impl Pbkdf2Hasher {
+    pub fn new(iterations: u32) -> Self {
+        Pbkdf2Hasher {
+            iterations: iterations
+        }
+    }
+
+    fn derive(password: &str, salt: &[u8], iterations: u32, length: usize) -> Vec<u8> {
+        let mut mac = Hmac::new(Sha256::new(), password.as_bytes());
+        let mut output = vec![0u8; length];
+        pbkdf2(&mut mac, salt, iterations, &mut output);
+        output
+    }
+}
+
+impl Default for Pbkdf2Hasher {
+    fn default() -> Self {
+        Pbkdf2Hasher::new(100_000)
+    }
+}
+
+impl PasswordHasher for Pbkdf2Hasher {
+    fn hash(&self, password: &str) -> Result<String, AuthError> {
+        let salt = salt()?;
+        let hash = Pbkdf2Hasher::derive(password, &salt, self.iterations, 32);
+
+        Ok(format!("$pbkdf2-sha256$i={}${}${}",
+                   self.iterations,
+                   base64::encode_config(&salt, base64::STANDARD_NO_PAD),
+                   base64::encode_config(&hash, base64::STANDARD_NO_PAD)))
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        let parts: Vec<&str> = hash.split('$').collect();
+
+        if parts.len() != 5 || parts[0] != "" || parts[1] != "pbkdf2-sha256" || !parts[2].starts_with("i=") {
+            return false;
+        }
+
+        let iterations = match u32::from_str(&parts[2][2..]) {
+            Ok(i) if i > 0 => i,
+            _ => return false
+        };
+
+        match (base64::decode_config(parts[3], base64::STANDARD_NO_PAD), base64::decode_config(parts[4], base64::STANDARD_NO_PAD)) {
+            (Ok(salt), Ok(expected)) => {
+                fixed_time_eq(&Pbkdf2Hasher::derive(password, &salt, iterations, expected.len()), &expected)
+            },
+            _ => false
+        }
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        !hash.starts_with(&format!("$pbkdf2-sha256$i={}$", self.iterations))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ PasswordHasher, Argon2Hasher, BcryptHasher, Pbkdf2Hasher, PasswordMatch, verify_password };
+
+    fn check(hasher: &PasswordHasher, prefix: &str) {
+        let hash = hasher.hash("correct horse").unwrap();
+        assert!(hash.starts_with(prefix));
+        assert_ne!(hash, hasher.hash("correct horse").unwrap());
+        assert!(hasher.verify("correct horse", &hash));
+        assert!(!hasher.verify("correct horse ", &hash));
+        assert!(!hasher.verify("correct horse", "5f4dcc3b5aa765d61d8327deb882cf99"));
+        assert!(!hasher.needs_rehash(&hash));
+        assert_eq!(verify_password(hasher, "correct horse", &hash), PasswordMatch::Valid);
+    }
+
+    #[test]
+    fn test_argon2() {
+        check(&Argon2Hasher::new(256, 1, 1), "$argon2id$v=19$m=256,t=1,p=1$");
+    }
+
+    #[test]
+    fn test_bcrypt() {
+        check(&BcryptHasher::new(4), "$2y$04$");
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        check(&Pbkdf2Hasher::new(10), "$pbkdf2-sha256$i=10$");
+    }
+
+    #[test]
+    fn test_verify_password_outdated() {
+        let current = Pbkdf2Hasher::new(20);
+        let old = Pbkdf2Hasher::new(10).hash("correct horse").unwrap();
+        let bcrypt = BcryptHasher::new(4).hash("correct horse").unwrap();
+
+        assert_eq!(verify_password(&current, "correct horse", &old), PasswordMatch::Outdated);
+        assert_eq!(verify_password(&current, "correct horse", &bcrypt), PasswordMatch::Outdated);
+        assert_eq!(verify_password(&current, "correct horse", "3cb4e732631f47e6eb961f34554b7cde"), PasswordMatch::Outdated);
+        assert_eq!(verify_password(&current, "wrong horse", &bcrypt), PasswordMatch::Invalid);
+        assert_eq!(verify_password(&current, "wrong horse", "3cb4e732631f47e6eb961f34554b7cde"), PasswordMatch::Invalid);
+        assert!(BcryptHasher::new(5).needs_rehash(&bcrypt));
+    }
+}
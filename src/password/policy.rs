@@ -0,0 +1,269 @@
+use std::fmt;
+use std::sync::Arc;
+use ::password::denylist::Denylist;
+
+/// Kind of characters a password can be required to contain
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol
+}
+
+impl CharacterClass {
+    fn of(c: char) -> Option<CharacterClass> {
+        if c.is_lowercase() {
+            Some(CharacterClass::Lowercase)
+        } else if c.is_uppercase() {
+            Some(CharacterClass::Uppercase)
+        } else if c.is_digit(10) {
+            Some(CharacterClass::Digit)
+        } else if c.is_ascii() && !c.is_control() {
+            Some(CharacterClass::Symbol)
+        } else {
+            None
+        }
+    }
+
+    /// Number of characters of the class an attacker has to try
+    fn pool_size(&self) -> f64 {
+        match *self {
+            CharacterClass::Lowercase | CharacterClass::Uppercase => 26.0,
+            CharacterClass::Digit => 10.0,
+            CharacterClass::Symbol => 33.0
+        }
+    }
+}
+
+/// Rule of the policy the password breaks, serialized as `{"rule": "too_short", "min": 8}` and alike
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Violation {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    MissingCharacterClass { class: CharacterClass },
+    ContainsUsername,
+    ContainsEmail,
+    TooPredictable { bits: f64, min: f64 },
+    Denylisted
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::TooShort { min } => write!(f, "Password must be at least {} characters long", min),
+            Violation::TooLong { max } => write!(f, "Password must be at most {} characters long", max),
+            Violation::MissingCharacterClass { class } => write!(f, "Password must contain a character of class {:?}", class),
+            Violation::ContainsUsername => f.write_str("Password must not contain the username"),
+            Violation::ContainsEmail => f.write_str("Password must not contain the email"),
+            Violation::TooPredictable { bits, min } => write!(f, "Password is too predictable ({:.0} of {:.0} bits)", bits, min),
+            Violation::Denylisted => f.write_str("Password is too common or known from a breach")
+        }
+    }
+}
+
+/// Rules for new passwords of sign up, change and reset, put it to the Rocket managed state to change the default
+///
+/// ```
+/// use auth_rocket::password::policy::{ PasswordPolicy, CharacterClass, Violation };
+///
+/// let policy = PasswordPolicy::new()
+///     .with_length(10, 64)
+///     .with_required_classes(&[CharacterClass::Digit]);
+///
+/// assert_eq!(policy.validate("correct horse 1", "batman", "bruce@example.com"), Ok(()));
+/// assert_eq!(policy.validate("batman", "batman", "bruce@example.com"), Err(vec!(
+///     Violation::TooShort { min: 10 },
+///     Violation::MissingCharacterClass { class: CharacterClass::Digit },
+///     Violation::ContainsUsername
+/// )));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    required_classes: Vec<CharacterClass>,
+    forbid_user_info: bool,
+    min_entropy: f64,
+    denylist: Option<Arc<Denylist>>
+}
+
+impl PasswordPolicy {
+    /// 8 to 128 characters without the username or the email, nothing else is required
+    pub fn new() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            required_classes: Vec::new(),
+            forbid_user_info: true,
+            min_entropy: 0.0,
+            denylist: None
+        }
+    }
+
+    /// Length in characters, not bytes
+    pub fn with_length(mut self, min: usize, max: usize) -> Self {
+        self.min_length = min;
+        self.max_length = max;
+        self
+    }
+
+    pub fn with_required_classes(mut self, classes: &[CharacterClass]) -> Self {
+        self.required_classes = classes.to_vec();
+        self
+    }
+
+    /// Reject passwords which contain the username or the email, case insensitive
+    pub fn with_user_info_check(mut self, check: bool) -> Self {
+        self.forbid_user_info = check;
+        self
+    }
+
+    /// Minimal estimated entropy in bits, 0 turns the check off
+    pub fn with_min_entropy(mut self, bits: f64) -> Self {
+        self.min_entropy = bits;
+        self
+    }
+
+    /// Reject passwords from the denylist, it is shared as the list may be large
+    pub fn with_denylist(mut self, denylist: Arc<Denylist>) -> Self {
+        self.denylist = Some(denylist);
+        self
+    }
+
+    /// All the rules the password breaks
+    pub fn validate(&self, password: &str, username: &str, email: &str) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            violations.push(Violation::TooShort { min: self.min_length });
+        }
+
+        if length > self.max_length {
+            violations.push(Violation::TooLong { max: self.max_length });
+        }
+
+        let classes: Vec<CharacterClass> = password.chars().filter_map(CharacterClass::of).collect();
+
+        for class in &self.required_classes {
+            if !classes.contains(class) {
+                violations.push(Violation::MissingCharacterClass { class: *class });
+            }
+        }
+
+        if self.forbid_user_info {
+            let lowercase = password.to_lowercase();
+
+            if !username.is_empty() && lowercase.contains(&username.to_lowercase()) {
+                violations.push(Violation::ContainsUsername);
+            }
+
+            if !email.is_empty() && lowercase.contains(&email.to_lowercase()) {
+                violations.push(Violation::ContainsEmail);
+            }
+        }
+
+        if self.min_entropy > 0.0 {
+            let bits = entropy(password);
+            if bits < self.min_entropy {
+                violations.push(Violation::TooPredictable { bits: bits, min: self.min_entropy });
+            }
+        }
+
+        if let Some(ref denylist) = self.denylist {
+            if denylist.contains(password) {
+                violations.push(Violation::Denylisted);
+            }
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations)
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy::new()
+    }
+}
+
+/// Rough brute force estimate: distinct characters times log2 of the pool of the classes used
+///
+/// Repeated characters don't count, so `aaaaaaaa` is as weak as `a`.
+pub fn entropy(password: &str) -> f64 {
+    let mut seen: Vec<char> = password.chars().collect();
+    seen.sort();
+    seen.dedup();
+
+    let mut classes: Vec<CharacterClass> = seen.iter().filter_map(|&c| CharacterClass::of(c)).collect();
+    classes.sort_by_key(|c| *c as u8);
+    classes.dedup();
+
+    let mut pool: f64 = classes.iter().map(|c| c.pool_size()).sum();
+    if seen.iter().any(|&c| CharacterClass::of(c).is_none()) {
+        // anything out of ASCII, assume a generous alphabet
+        pool += 100.0;
+    }
+
+    match pool > 0.0 {
+        true => seen.len() as f64 * pool.log2(),
+        false => 0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ PasswordPolicy, CharacterClass, Violation, entropy };
+    use ::password::denylist::Denylist;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_length_is_counted_in_characters() {
+        let policy = PasswordPolicy::new().with_length(4, 4);
+        assert_eq!(policy.validate("пароль", "", ""), Err(vec!(Violation::TooLong { max: 4 })));
+        assert_eq!(policy.validate("паро", "", ""), Ok(()));
+    }
+
+    #[test]
+    fn test_character_classes() {
+        let policy = PasswordPolicy::new().with_required_classes(&[CharacterClass::Lowercase, CharacterClass::Uppercase,
+                                                                   CharacterClass::Digit, CharacterClass::Symbol]);
+        assert_eq!(policy.validate("Secret-password-1", "", ""), Ok(()));
+        assert_eq!(policy.validate("secretpassword", "", ""), Err(vec!(
+            Violation::MissingCharacterClass { class: CharacterClass::Uppercase },
+            Violation::MissingCharacterClass { class: CharacterClass::Digit },
+            Violation::MissingCharacterClass { class: CharacterClass::Symbol }
+        )));
+    }
+
+    #[test]
+    fn test_user_info() {
+        let policy = PasswordPolicy::new();
+        assert_eq!(policy.validate("my-BATMAN-password", "batman", "bruce@example.com"), Err(vec!(Violation::ContainsUsername)));
+        assert_eq!(policy.validate("bruce@example.com!", "batman", "bruce@example.com"), Err(vec!(Violation::ContainsEmail)));
+        assert_eq!(policy.with_user_info_check(false).validate("my-batman-password", "batman", "bruce@example.com"), Ok(()));
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(""), 0.0);
+        assert_eq!(entropy("aaaaaaaa"), entropy("a"));
+        assert!(entropy("Tr0ub4dor&3") > entropy("troubador"));
+
+        let policy = PasswordPolicy::new().with_min_entropy(40.0);
+        assert!(policy.validate("aaaaaaaaaaaa", "", "").is_err());
+        assert_eq!(policy.validate("correct horse battery staple", "", ""), Ok(()));
+    }
+
+    #[test]
+    fn test_denylist() {
+        let policy = PasswordPolicy::new().with_denylist(Arc::new(Denylist::from_passwords(&["password1", "iloveyou"])));
+        assert_eq!(policy.validate("password1", "batman", "bruce@example.com"), Err(vec!(Violation::Denylisted)));
+        assert_eq!(policy.validate("password2", "batman", "bruce@example.com"), Ok(()));
+    }
+}
@@ -1,22 +1,35 @@
-use redis::Commands;
-use std::collections::HashMap;
-use super::{Entity, User, AuthError, Role, UserStatus, PrivateUser};
+pub mod rate;
+
+use redis::{ Commands, Connection };
+use std::collections::{ HashMap, BTreeSet };
+use super::{Entity, User, AuthError, Role, UserStatus, PrivateUser, MfaSecret};
 use std::str::FromStr;
 use r2d2::{Pool, PooledConnection};
 use r2d2_redis::RedisConnectionManager;
-use crypto::md5;
-use crypto::digest::Digest;
 use chrono::Local;
 use std::fmt;
 use serde_json;
+use ::password::{ PasswordHasher, Argon2Hasher, PasswordMatch, verify_password };
+use ::session::{ TokenPolicy, Session, RefreshToken };
+use ::net::client::ClientInfo;
 
 enum StorageNames {
     Name,
     Id,
     Increment,
     List,
-    UserToken,
-    TokenToken
+    TokenToken,
+    Session,
+    UserSessions,
+    RefreshToken,
+    SessionRefreshTokens,
+    UserRefreshSessions,
+    Ticket,
+    MfaSecret,
+    RecoveryCodes,
+    Counter,
+    Roles,
+    RolePermissions
 }
 
 impl fmt::Display for StorageNames {
@@ -26,23 +39,190 @@ impl fmt::Display for StorageNames {
             StorageNames::Id => "authorize:users:id:",
             StorageNames::Increment => "authorize:increment",
             StorageNames::List => "authorize:users:list",
-            StorageNames::UserToken => "authorize:users:tokens:user:",
             StorageNames::TokenToken => "authorize:users:tokens:token:",
+            StorageNames::Session => "authorize:users:sessions:id:",
+            StorageNames::UserSessions => "authorize:users:sessions:user:",
+            StorageNames::RefreshToken => "authorize:users:refresh:token:",
+            StorageNames::SessionRefreshTokens => "authorize:users:refresh:session:",
+            StorageNames::UserRefreshSessions => "authorize:users:refresh:user:",
+            StorageNames::Ticket => "authorize:tickets:",
+            StorageNames::MfaSecret => "authorize:users:mfa:",
+            StorageNames::RecoveryCodes => "authorize:users:recovery:",
+            StorageNames::Counter => "authorize:counters:",
+            StorageNames::Roles => "authorize:users:roles:",
+            StorageNames::RolePermissions => "authorize:roles:permissions:",
         })
     }
 }
 
 pub struct RedisEntity {
     pool: Pool<RedisConnectionManager>,
-    prefix: String
+    prefix: String,
+    hasher: Box<PasswordHasher>,
+    token_policy: TokenPolicy
 }
 
 impl RedisEntity {
     pub fn new(s: &Pool<RedisConnectionManager>, prefix: String) -> RedisEntity {
         RedisEntity {
             pool: s.clone(),
-            prefix: prefix
+            prefix: prefix,
+            hasher: Box::new(Argon2Hasher::default()),
+            token_policy: TokenPolicy::default()
+        }
+    }
+
+    /// Use another algorithm for new passwords
+    pub fn with_hasher(mut self, hasher: Box<PasswordHasher>) -> RedisEntity {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Use another expiration rules for new and prolonged tokens
+    pub fn with_token_policy(mut self, token_policy: TokenPolicy) -> RedisEntity {
+        self.token_policy = token_policy;
+        self
+    }
+
+    fn rehash_password(&self, username: &str, password: &str) -> Option<AuthError> {
+        self.hasher.hash(password)
+            .and_then(|hash| self.get_conn()
+                .ok_or(AuthError::IOError)
+                .and_then(|con| con.hset(format!("{}{}{}", self.prefix, StorageNames::Name, username), "password", hash)
+                    .ok().ok_or(AuthError::IOError)
+                    .map(|_: bool| ())
+                ))
+            .err()
+    }
+
+    /// Move the single `role` field of the records written by older versions to the roles set of every user
+    ///
+    /// Users are readable either way, the migration only saves the extra lookups. Returns the number of moved roles.
+    pub fn migrate_roles(&self) -> Result<usize, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let ids: Vec<i32> = con.zrange(format!("{}{}", self.prefix, StorageNames::List), 0, -1).ok().ok_or(AuthError::IOError)?;
+        let mut migrated = 0;
+
+        for id in ids {
+            let username: Option<String> = con.get(self.key(StorageNames::Id, id)).ok().ok_or(AuthError::IOError)?;
+            if let Some(username) = username {
+                if self.migrate_user_roles(&con, &username)? {
+                    migrated += 1;
+                }
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /// Move the `role` field of the user to the roles set, true if there was one
+    fn migrate_user_roles(&self, con: &Connection, username: &str) -> Result<bool, AuthError> {
+        let name_key = self.key(StorageNames::Name, username);
+        let role: Option<String> = con.hget(name_key.as_str(), "role").ok().ok_or(AuthError::IOError)?;
+
+        match role {
+            Some(role) => {
+                con.sadd(self.key(StorageNames::Roles, username), role).ok().ok_or(AuthError::IOError).map(|_: bool| ())?;
+                con.hdel(name_key.as_str(), "role").ok().ok_or(AuthError::IOError).map(|_: bool| true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    fn key<T: fmt::Display>(&self, name: StorageNames, id: T) -> String {
+        format!("{}{}{}", self.prefix, name, id)
+    }
+
+    /// Load session by id, returns its token, owner name and the session
+    fn load_session(&self, con: &Connection, session_id: &str) -> Option<(String, String, Session)> {
+        con.hgetall(self.key(StorageNames::Session, session_id)).ok()
+            .and_then(|t: HashMap<String, String>| {
+                let number = |field: &str| t.get(field).and_then(|v| i64::from_str(v).ok()).unwrap_or(0);
+
+                match (t.get("token"), t.get("user")) {
+                    (Some(token), Some(user)) => Some((token.clone(), user.clone(), Session {
+                        id: session_id.to_string(),
+                        created_at: number("created"),
+                        last_seen: number("last_seen"),
+                        expires_at: number("expires"),
+                        user_agent: t.get("user_agent").cloned(),
+                        ip: t.get("ip").cloned()
+                    })),
+                    _ => None
+                }
+            })
+    }
+
+    fn save_session(&self, con: &Connection, token: &str, username: &str, session: &Session) -> Result<(), AuthError> {
+        let ttl = session.expires_at - Local::now().timestamp();
+
+        if ttl <= 0 {
+            return Err(AuthError::NotFound);
         }
+
+        let session_key = self.key(StorageNames::Session, &session.id);
+        let mut fields = vec!(("token", token.to_string()),
+                              ("user", username.to_string()),
+                              ("created", session.created_at.to_string()),
+                              ("last_seen", session.last_seen.to_string()),
+                              ("expires", session.expires_at.to_string()));
+
+        if let Some(ref user_agent) = session.user_agent {
+            fields.push(("user_agent", user_agent.clone()));
+        }
+
+        if let Some(ref ip) = session.ip {
+            fields.push(("ip", ip.clone()));
+        }
+
+        con.hset_multiple(session_key.as_str(), &fields)
+            .ok().ok_or(AuthError::IOError)
+            .and_then(|_: bool| con.expire(session_key.as_str(), ttl as usize).ok().ok_or(AuthError::IOError))
+            .and_then(|_: bool| con.set_ex(self.key(StorageNames::TokenToken, token), session.id.as_str(), ttl as usize)
+                .ok().ok_or(AuthError::IOError))
+            .map(|_: bool| ())
+    }
+
+    fn remove_session(&self, con: &Connection, token: &str, username: &str, session_id: &str) {
+        if let Err(e) = con.del(self.key(StorageNames::TokenToken, token)).map(|n: bool| n) {
+            warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::TokenToken, token, e);
+        }
+
+        if let Err(e) = con.del(self.key(StorageNames::Session, session_id)).map(|n: bool| n) {
+            warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::Session, session_id, e);
+        }
+
+        if let Err(e) = con.zrem(self.key(StorageNames::UserSessions, username), session_id).map(|n: bool| n) {
+            warn!("cannot delete key ({}) from list {}{}{} in redis DB ({})", session_id, self.prefix, StorageNames::UserSessions, username, e);
+        }
+    }
+
+    /// Delete every refresh token issued for the session, returns false if there were none
+    fn remove_refresh_tokens(&self, con: &Connection, username: &str, session_id: &str) -> bool {
+        let set_key = self.key(StorageNames::SessionRefreshTokens, session_id);
+        let tokens: Vec<String> = con.smembers(set_key.as_str()).unwrap_or(Vec::new());
+
+        for token in &tokens {
+            if let Err(e) = con.del(self.key(StorageNames::RefreshToken, token)).map(|n: bool| n) {
+                warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::RefreshToken, token, e);
+            }
+        }
+
+        if let Err(e) = con.del(set_key.as_str()).map(|n: bool| n) {
+            warn!("cannot delete key ({}) in redis DB ({})", set_key, e);
+        }
+
+        if let Err(e) = con.srem(self.key(StorageNames::UserRefreshSessions, username), session_id).map(|n: bool| n) {
+            warn!("cannot delete key ({}) from set {}{}{} in redis DB ({})", session_id, self.prefix, StorageNames::UserRefreshSessions, username, e);
+        }
+
+        tokens.len() > 0
+    }
+
+    /// Ids of user sessions from the oldest to the latest, some of them may be expired already
+    fn session_ids(&self, con: &Connection, username: &str) -> Result<Vec<String>, AuthError> {
+        con.zrange(self.key(StorageNames::UserSessions, username), 0, -1)
+            .ok().ok_or(AuthError::IOError)
     }
 
     fn get_conn(&self) -> Option<PooledConnection<RedisConnectionManager>> {
@@ -63,6 +243,15 @@ impl Entity for RedisEntity {
             .and_then(|con| con.hgetall(format!("{}{}{}", self.prefix, StorageNames::Name, username))
                 .ok().ok_or(AuthError::NotFound)
                 .and_then(|t: HashMap<String, String>| {
+                    let mut roles: BTreeSet<Role> = con.smembers(self.key(StorageNames::Roles, username))
+                        .ok().ok_or(AuthError::IOError)
+                        .map(|roles: Vec<String>| roles.iter().filter_map(|r| Role::from_str(r).ok()).collect())?;
+
+                    // records written before the roles set keep a single role in the hash
+                    if let Some(role) = t.get("role").and_then(|r| Role::from_str(r).ok()) {
+                        roles.insert(role);
+                    }
+
                     match t.len() > 0 {
                         true => Ok(PrivateUser {
                             id: i32::from_str(t.get("id").unwrap_or(&"0".to_string())).unwrap_or(0i32),
@@ -70,7 +259,7 @@ impl Entity for RedisEntity {
                             password: t.get("password").unwrap_or(&"default".to_string()).to_string(),
                             status: match u8::from_str(t.get("status").unwrap_or(&"0".to_string())).unwrap_or(0u8) { 0u8 => UserStatus::Created, 1u8 => UserStatus::Active, 2u8 => UserStatus::Disabled, _ => UserStatus::Unknown },
                             email: t.get("email").unwrap_or(&"default".to_string()).to_string(),
-                            role: Role::from_str(t.get("role").unwrap_or(&"unknown".to_string())).unwrap_or(Role::Custom("unknown".to_string())),
+                            roles: roles,
                             attributes: serde_json::from_str::<HashMap<String, String>>(t.get("attributes").unwrap_or(&"{}".to_string()).as_str()).unwrap_or(HashMap::new())
                         }),
                         false => Err(AuthError::NotFound)
@@ -80,12 +269,16 @@ impl Entity for RedisEntity {
     }
 
     fn get_user_by_name_and_pwd(&self, username: &str, password: &str) -> Result<User, AuthError> {
-        let mut sh = md5::Md5::new();
-        sh.input_str(password);
         self.get_user_by_name(username)
-            .and_then(|u| match u.password == sh.result_str() {
-                true => Ok(User::from(u)),
-                false => Err(AuthError::NotFound)
+            .and_then(|u| match verify_password(&*self.hasher, password, &u.password) {
+                PasswordMatch::Valid => Ok(User::from(u)),
+                PasswordMatch::Outdated => {
+                    if let Some(e) = self.rehash_password(&u.name, password) {
+                        warn!("cannot rehash password of user {} ({})", u.name, e);
+                    }
+                    Ok(User::from(u))
+                },
+                PasswordMatch::Invalid => Err(AuthError::NotFound)
             })
     }
 
@@ -104,7 +297,7 @@ impl Entity for RedisEntity {
             .or_else(|e| {
                 match e {
                     AuthError::NotFound => {
-                        self.get_conn().ok_or(AuthError::IOError)
+                        self.hasher.hash(password).and_then(|hash| self.get_conn().ok_or(AuthError::IOError)
                         .and_then(|con|
                             con.hincr(format!("{}{}", self.prefix, StorageNames::Increment), "users", 1)
                                 .ok().ok_or(AuthError::IOError)
@@ -115,25 +308,24 @@ impl Entity for RedisEntity {
                                         con.zadd(format!("{}{}", self.prefix, StorageNames::List), id, Local::now().timestamp() as i64)
                                             .ok().ok_or(AuthError::IOError)
                                             .and_then(|_: bool| {
-                                                let mut sh = md5::Md5::new();
-                                                sh.input_str(password);
                                                 con.hset_multiple(format!("{}{}{}", self.prefix, StorageNames::Name, name),
                                                      &vec!(("id", id.to_string()),
                                                            ("name", name.to_string()),
                                                            ("email", email.to_string()),
                                                            ("status", "0".to_string()),
-                                                           ("password", sh.result_str()),
-                                                           ("role", Role::Users.to_string()),
+                                                           ("password", hash.clone()),
                                                            ("attributes", json!(attributes).to_string())
                                                      )
                                                 )
                                                 .ok().ok_or(AuthError::IOError)
+                                                .and_then(|_: bool| con.sadd(self.key(StorageNames::Roles, name), Role::Users.to_string())
+                                                    .ok().ok_or(AuthError::IOError))
                                                 .and_then(|_: bool| self.get_user_by_name(name).map(|user| User::from(user)))
                                             }
                                         )
                                     )
                             )
-                        )
+                        ))
                     },
                     _  => Err(e),
                 }
@@ -148,6 +340,15 @@ impl Entity for RedisEntity {
                        if let Err(e) = con.del(format!("{}{}{}", self.prefix, StorageNames::Name, u)).map(|n: bool| n) {
                            warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::Name, u, e);
                        }
+                       if let Err(e) = con.del(self.key(StorageNames::MfaSecret, &u)).map(|n: bool| n) {
+                           warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::MfaSecret, u, e);
+                       }
+                       if let Err(e) = con.del(self.key(StorageNames::RecoveryCodes, &u)).map(|n: bool| n) {
+                           warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::RecoveryCodes, u, e);
+                       }
+                       if let Err(e) = con.del(self.key(StorageNames::Roles, &u)).map(|n: bool| n) {
+                           warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::Roles, u, e);
+                       }
                    },
                    _ => {
                        warn!("username by key ({}{}{}) not found in redis DB", self.prefix, StorageNames::Id, user_id);
@@ -191,55 +392,37 @@ impl Entity for RedisEntity {
             })
     }
 
-    fn add_token(&self, username: &str, token: &str) -> Option<AuthError> {
-        self.get_conn()
-            .and_then(|con| {
-                con.set(format!("{}{}{}", self.prefix, StorageNames::UserToken, username), token)
-                    .ok()
-                    .and_then(|_: bool|
-                        con.expire(format!("{}{}{}", self.prefix, StorageNames::UserToken, username), 3600).ok()
-                            .and_then(|_: bool| {
-                                con.set(format!("{}{}{}", self.prefix, StorageNames::TokenToken, token), username)
-                                    .ok()
-                                    .and_then(|_: bool| {
-                                        con.expire(format!("{}{}{}", self.prefix, StorageNames::TokenToken, token), 3600)
-                                            .ok()
-                                            .and_then(|_: bool| {None})
-
-                                    })
-                            })
-                    )
-            })
-    }
-
     fn get_user_by_token(&self, token: &str) -> Result<User, AuthError> {
-        self.get_conn()
-            .ok_or(AuthError::IOError)
-            .and_then(|con| con.get(format!("{}{}{}", self.prefix, StorageNames::TokenToken, token))
-                .ok().ok_or(AuthError::NotFound)
-                .and_then(|username: String| {
-                    self.get_user_by_name(&username).and_then(|u|
-                        match u.status == UserStatus::Active {
-                            true => match self.add_token(&username, token) {
-                                Some(e) => Err(e),
-                                _ => Ok(User::from(u))
-                            },
-                            false => Err(AuthError::NotActive)
-                        }
-                    )
-                })
-            )
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+
+        let session_id: String = con.get(self.key(StorageNames::TokenToken, token)).ok().ok_or(AuthError::NotFound)?;
+        let (_, username, mut session) = self.load_session(&con, &session_id).ok_or(AuthError::NotFound)?;
+
+        let u = self.get_user_by_name(&username)?;
+
+        if u.status != UserStatus::Active {
+            return Err(AuthError::NotActive);
+        }
+
+        match session.touch(&self.token_policy, Local::now().timestamp()) {
+            true => self.save_session(&con, token, &username, &session).map(|_| User::from(u)),
+            false => {
+                self.remove_session(&con, token, &username, &session.id);
+                Err(AuthError::NotFound)
+            }
+        }
     }
 
     fn get_token(&self, username: &str) -> Result<String, AuthError> {
-        self.get_conn()
-            .ok_or(AuthError::IOError)
-            .and_then(|con| con.get(format!("{}{}{}", self.prefix, StorageNames::UserToken, username))
-                .ok().ok_or(AuthError::NotFound)
-                .and_then(|token: String| {
-                    Ok(token)
-                })
-            )
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let now = Local::now().timestamp();
+
+        self.session_ids(&con, username)?.iter().rev()
+            .filter_map(|id| self.load_session(&con, id))
+            .filter(|&(_, ref user, ref session)| user == username && session.is_alive(now))
+            .map(|(token, _, _)| token)
+            .next()
+            .ok_or(AuthError::NotFound)
     }
 
     fn enable_user(&self, username: &str) -> Result<User, AuthError> {
@@ -267,35 +450,427 @@ impl Entity for RedisEntity {
     }
 
     fn delete_token(&self, token: &str) -> Option<AuthError> {
-        match self.get_user_by_token(token) {
-            Ok(u) => { match self.get_conn().ok_or(AuthError::IOError) {
-                Ok(con) => {
-                    if let Err(e) = con.del(format!("{}{}{}", self.prefix, StorageNames::UserToken, u.name)).map(|n: bool| n) {
-                        warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::UserToken, u.name, e);
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.get(self.key(StorageNames::TokenToken, token))
+                .ok().ok_or(AuthError::NotFound)
+                .and_then(|session_id: String| self.load_session(&con, &session_id).ok_or(AuthError::NotFound))
+                .map(|(_, username, session)| {
+                    self.remove_session(&con, token, &username, &session.id);
+                    self.remove_refresh_tokens(&con, &username, &session.id);
+                })
+            )
+            .err()
+    }
+
+    fn add_session(&self, username: &str, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        self.get_user_by_name(username)?;
+
+        let now = Local::now().timestamp();
+        let session = Session::new(client, now, self.token_policy.expires_at(now, now).unwrap_or(now));
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+
+        self.save_session(&con, token, username, &session)
+            .and_then(|_| con.zadd(self.key(StorageNames::UserSessions, username), session.id.as_str(), now)
+                .ok().ok_or(AuthError::IOError))
+            .map(|_: bool| session)
+    }
+
+    fn renew_session(&self, username: &str, session_id: &str, created_at: i64, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        self.get_user_by_name(username)?;
+
+        let now = Local::now().timestamp();
+        let mut session = Session::new(client, now, self.token_policy.expires_at(created_at, now).ok_or(AuthError::NotFound)?);
+        session.id = session_id.to_string();
+        session.created_at = created_at;
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+
+        if let Some((old_token, user, _)) = self.load_session(&con, session_id) {
+            match user == username {
+                true => self.remove_session(&con, &old_token, username, session_id),
+                false => return Err(AuthError::NotFound)
+            }
+        }
+
+        self.save_session(&con, token, username, &session)
+            .and_then(|_| con.zadd(self.key(StorageNames::UserSessions, username), session.id.as_str(), now)
+                .ok().ok_or(AuthError::IOError))
+            .map(|_: bool| session)
+    }
+
+    fn get_session_by_token(&self, token: &str) -> Result<Session, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let now = Local::now().timestamp();
+
+        con.get(self.key(StorageNames::TokenToken, token))
+            .ok().ok_or(AuthError::NotFound)
+            .and_then(|session_id: String| self.load_session(&con, &session_id).ok_or(AuthError::NotFound))
+            .and_then(|(_, _, session)| match session.is_alive(now) {
+                true => Ok(session),
+                false => Err(AuthError::NotFound)
+            })
+    }
+
+    fn list_sessions(&self, username: &str) -> Result<Vec<Session>, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let now = Local::now().timestamp();
+        let mut list: Vec<Session> = Vec::new();
+
+        for id in self.session_ids(&con, username)? {
+            match self.load_session(&con, &id) {
+                Some((_, ref user, ref session)) if user == username && session.is_alive(now) => {
+                    list.push(session.clone());
+                },
+                _ => {
+                    if let Err(e) = con.zrem(self.key(StorageNames::UserSessions, username), id.as_str()).map(|n: bool| n) {
+                        warn!("cannot delete key ({}) from list {}{}{} in redis DB ({})", id, self.prefix, StorageNames::UserSessions, username, e);
                     }
+                }
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn delete_session(&self, username: &str, session_id: &str) -> Option<AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| {
+                let removed = match self.load_session(&con, session_id) {
+                    Some((token, user, _)) => match user == username {
+                        true => {
+                            self.remove_session(&con, &token, username, session_id);
+                            true
+                        },
+                        false => return Err(AuthError::NotFound)
+                    },
+                    None => false
+                };
+
+                let owned: bool = con.sismember(self.key(StorageNames::UserRefreshSessions, username), session_id).unwrap_or(false);
+
+                match (owned && self.remove_refresh_tokens(&con, username, session_id)) || removed {
+                    true => Ok(()),
+                    false => Err(AuthError::NotFound)
+                }
+            })
+            .err()
+    }
 
-                    if let Err(e) = con.del(format!("{}{}{}", self.prefix, StorageNames::TokenToken, token)).map(|n: bool| n) {
-                        warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::TokenToken, token, e);
+    fn delete_sessions(&self, username: &str) -> Option<AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| self.session_ids(&con, username).map(|ids| {
+                for id in ids {
+                    if let Some((token, user, _)) = self.load_session(&con, &id) {
+                        if user == username {
+                            self.remove_session(&con, &token, username, &id);
+                        }
                     }
+                }
 
-                    None
-                },
-                Err(_) => Some(AuthError::IOError)
-            } },
-            Err(e) => Some(e)
+                if let Err(e) = con.del(self.key(StorageNames::UserSessions, username)).map(|n: bool| n) {
+                    warn!("cannot delete key ({}{}{}) in redis DB ({})", self.prefix, StorageNames::UserSessions, username, e);
+                }
+
+                let refresh_sessions: Vec<String> = con.smembers(self.key(StorageNames::UserRefreshSessions, username)).unwrap_or(Vec::new());
+                for id in refresh_sessions {
+                    self.remove_refresh_tokens(&con, username, &id);
+                }
+            }))
+            .err()
+    }
+
+    fn add_refresh_token(&self, username: &str, token: &str, session: &Session) -> Option<AuthError> {
+        if let Err(e) = self.get_user_by_name(username) {
+            return Some(e);
         }
+
+        let now = Local::now().timestamp();
+        let expires_at = self.token_policy.refresh_expires_at(session.created_at, now);
+        let ttl = (expires_at - now).max(1) as usize;
+        let session_id = session.id.as_str();
+        let token_key = self.key(StorageNames::RefreshToken, token);
+        let set_key = self.key(StorageNames::SessionRefreshTokens, session_id);
+        let user_key = self.key(StorageNames::UserRefreshSessions, username);
+
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.hset_multiple(token_key.as_str(), &[("user", username.to_string()),
+                                                                   ("session", session_id.to_string()),
+                                                                   ("created", session.created_at.to_string()),
+                                                                   ("expires", expires_at.to_string()),
+                                                                   ("used", "0".to_string())])
+                .ok().ok_or(AuthError::IOError)
+                .and_then(|_: bool| con.expire(token_key.as_str(), ttl).ok().ok_or(AuthError::IOError))
+                .and_then(|_: bool| con.sadd(set_key.as_str(), token).ok().ok_or(AuthError::IOError))
+                .and_then(|_: bool| con.expire(set_key.as_str(), ttl).ok().ok_or(AuthError::IOError))
+                .and_then(|_: bool| con.sadd(user_key.as_str(), session_id).ok().ok_or(AuthError::IOError))
+                // other sessions of the user may have tokens which live longer
+                .and_then(|_: bool| con.expire(user_key.as_str(), self.token_policy.refresh_lifetime as usize).ok().ok_or(AuthError::IOError))
+                .map(|_: bool| ())
+            )
+            .err()
     }
 
-    fn add_user_role(&self, username: &str, role: Role) -> Result<User, AuthError> {
+    fn take_refresh_token(&self, token: &str) -> Result<RefreshToken, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let token_key = self.key(StorageNames::RefreshToken, token);
+        let t: HashMap<String, String> = con.hgetall(token_key.as_str()).ok().ok_or(AuthError::IOError)?;
+
+        let refresh = match (t.get("user"), t.get("session"), t.get("expires").and_then(|v| i64::from_str(v).ok())) {
+            (Some(user), Some(session), Some(expires)) => {
+                // tokens stored before the session start was kept count from their own issue
+                let created = t.get("created").and_then(|v| i64::from_str(v).ok())
+                    .unwrap_or(expires - self.token_policy.refresh_lifetime);
+                RefreshToken::new(user, session, created, expires)
+            },
+            _ => return Err(AuthError::NotFound)
+        };
+
+        if !refresh.is_alive(Local::now().timestamp()) {
+            return Err(AuthError::NotFound);
+        }
+
+        // HINCRBY is atomic, so of two concurrent requests with the same token only one gets it unused
+        con.hincr(token_key.as_str(), "used", 1)
+            .ok().ok_or(AuthError::IOError)
+            .map(|used: i64| RefreshToken {
+                used: used > 1,
+                ..refresh
+            })
+    }
+
+    fn add_ticket(&self, kind: &str, ticket: &str, username: &str, ttl: i64) -> Option<AuthError> {
+        if let Err(e) = self.get_user_by_name(username) {
+            return Some(e);
+        }
+
+        if ttl <= 0 {
+            return None;
+        }
+
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.set_ex(self.key(StorageNames::Ticket, format!("{}:{}", kind, ticket)), username, ttl as usize)
+                .ok().ok_or(AuthError::IOError)
+                .map(|_: bool| ())
+            )
+            .err()
+    }
+
+    fn get_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.get(self.key(StorageNames::Ticket, format!("{}:{}", kind, ticket))).ok().ok_or(AuthError::IOError))
+            .and_then(|username: Option<String>| username.ok_or(AuthError::NotFound))
+    }
+
+    fn take_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let ticket_key = self.key(StorageNames::Ticket, format!("{}:{}", kind, ticket));
+        let username: Option<String> = con.get(ticket_key.as_str()).ok().ok_or(AuthError::IOError)?;
+        let username = username.ok_or(AuthError::NotFound)?;
+
+        // only the request which actually deleted the key gets the ticket
+        con.del(ticket_key.as_str())
+            .ok().ok_or(AuthError::IOError)
+            .and_then(|deleted: i64| match deleted {
+                1 => Ok(username),
+                _ => Err(AuthError::NotFound)
+            })
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        // HSET would create a half user for an unknown name
+        self.get_user_by_name(username)
+            .and_then(|u| match self.rehash_password(&u.name, password) {
+                Some(e) => Err(e),
+                None => self.get_user_by_name(&u.name).map(|user| User::from(user))
+            })
+    }
+
+    fn set_mfa_secret(&self, username: &str, secret: Option<MfaSecret>) -> Option<AuthError> {
+        if let Err(e) = self.get_user_by_name(username) {
+            return Some(e);
+        }
+
+        let mfa_key = self.key(StorageNames::MfaSecret, username);
+
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| match secret {
+                Some(secret) => con.hset_multiple(mfa_key.as_str(), &[("secret", secret.secret),
+                                                                     ("confirmed", (secret.confirmed as u8).to_string()),
+                                                                     ("last_step", secret.last_step.to_string())])
+                    .ok().ok_or(AuthError::IOError)
+                    .map(|_: bool| ()),
+                None => con.del(mfa_key.as_str())
+                    .ok().ok_or(AuthError::IOError)
+                    .map(|_: bool| ())
+            })
+            .err()
+    }
+
+    fn get_mfa_secret(&self, username: &str) -> Result<MfaSecret, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let t: HashMap<String, String> = con.hgetall(self.key(StorageNames::MfaSecret, username)).ok().ok_or(AuthError::IOError)?;
+
+        match t.get("secret") {
+            Some(secret) => Ok(MfaSecret {
+                secret: secret.to_string(),
+                confirmed: t.get("confirmed").map(|c| c == "1").unwrap_or(false),
+                last_step: t.get("last_step").and_then(|s| i64::from_str(s).ok()).unwrap_or(0)
+            }),
+            None => Err(AuthError::NotFound)
+        }
+    }
+
+    fn set_recovery_codes(&self, username: &str, codes: &[String]) -> Option<AuthError> {
+        if let Err(e) = self.get_user_by_name(username) {
+            return Some(e);
+        }
+
+        let codes_key = self.key(StorageNames::RecoveryCodes, username);
+
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.del(codes_key.as_str())
+                .ok().ok_or(AuthError::IOError)
+                .and_then(|_: bool| match codes.is_empty() {
+                    true => Ok(()),
+                    false => con.sadd(codes_key.as_str(), codes).ok().ok_or(AuthError::IOError).map(|_: i64| ())
+                })
+            )
+            .err()
+    }
+
+    fn take_recovery_code(&self, username: &str, code: &str) -> Option<AuthError> {
+        // SREM is atomic, a code removed by a concurrent request is gone for this one
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.srem(self.key(StorageNames::RecoveryCodes, username), code)
+                .ok().ok_or(AuthError::IOError)
+                .and_then(|removed: i64| match removed {
+                    1 => Ok(()),
+                    _ => Err(AuthError::NotFound)
+                })
+            )
+            .err()
+    }
+
+    fn count_recovery_codes(&self, username: &str) -> Result<usize, AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.scard(self.key(StorageNames::RecoveryCodes, username)).ok().ok_or(AuthError::IOError))
+    }
+
+    fn incr_counter(&self, key: &str, ttl: i64) -> Result<i64, AuthError> {
+        let con = self.get_conn().ok_or(AuthError::IOError)?;
+        let counter_key = self.key(StorageNames::Counter, key);
+        let value: i64 = con.incr(counter_key.as_str(), 1).ok().ok_or(AuthError::IOError)?;
+
+        // the first increment starts the window
+        if value == 1 {
+            con.expire(counter_key.as_str(), ttl.max(1) as usize).ok().ok_or(AuthError::IOError).map(|_: bool| ())?;
+        }
+
+        Ok(value)
+    }
+
+    fn get_counter(&self, key: &str) -> Result<i64, AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.get(self.key(StorageNames::Counter, key)).ok().ok_or(AuthError::IOError))
+            .map(|value: Option<i64>| value.unwrap_or(0))
+    }
+
+    fn set_counter(&self, key: &str, value: i64, ttl: i64) -> Option<AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.set_ex(self.key(StorageNames::Counter, key), value, ttl.max(1) as usize)
+                .ok().ok_or(AuthError::IOError)
+                .map(|_: bool| ())
+            )
+            .err()
+    }
+
+    fn reset_counter(&self, key: &str) -> Option<AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.del(self.key(StorageNames::Counter, key))
+                .ok().ok_or(AuthError::IOError)
+                .map(|_: bool| ())
+            )
+            .err()
+    }
+
+    fn set_user_attributes(&self, username: &str, attributes: HashMap<String, String>) -> Result<User, AuthError> {
         self.get_user_by_name(username)
             .and_then(|u| self.get_conn()
                 .ok_or(AuthError::IOError)
                 .and_then(|con|
-                    con.hset(format!("{}{}{}", self.prefix, StorageNames::Name, username), "role", role.to_string())
+                    con.hset(self.key(StorageNames::Name, username), "attributes", json!(attributes).to_string())
                     .ok().ok_or(AuthError::IOError)
                     .and_then(|_: bool| {
                         self.get_user_by_name(&u.name).map(|user| User::from(user))
                     })
                 ))
     }
+
+    fn add_user_role(&self, username: &str, role: Role) -> Result<User, AuthError> {
+        self.get_user_by_name(username)
+            .and_then(|u| self.get_conn()
+                .ok_or(AuthError::IOError)
+                .and_then(|con| self.migrate_user_roles(&con, username)
+                    .and_then(|_| con.sadd(self.key(StorageNames::Roles, username), role.to_string())
+                        .ok().ok_or(AuthError::IOError))
+                    .and_then(|_: bool| {
+                        self.get_user_by_name(&u.name).map(|user| User::from(user))
+                    })
+                ))
+    }
+
+    fn remove_user_role(&self, username: &str, role: &Role) -> Result<User, AuthError> {
+        self.get_user_by_name(username)
+            .and_then(|u| self.get_conn()
+                .ok_or(AuthError::IOError)
+                .and_then(|con| self.migrate_user_roles(&con, username)
+                    .and_then(|_| con.srem(self.key(StorageNames::Roles, username), role.to_string())
+                        .ok().ok_or(AuthError::IOError))
+                    .and_then(|_: bool| {
+                        self.get_user_by_name(&u.name).map(|user| User::from(user))
+                    })
+                ))
+    }
+
+    fn grant_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.sadd(self.key(StorageNames::RolePermissions, role), permission)
+                .ok().ok_or(AuthError::IOError)
+                .map(|_: bool| ())
+            )
+            .err()
+    }
+
+    fn revoke_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.srem(self.key(StorageNames::RolePermissions, role), permission)
+                .ok().ok_or(AuthError::IOError)
+                .map(|_: bool| ())
+            )
+            .err()
+    }
+
+    fn list_permissions(&self, role: &Role) -> Result<Vec<String>, AuthError> {
+        self.get_conn()
+            .ok_or(AuthError::IOError)
+            .and_then(|con| con.smembers(self.key(StorageNames::RolePermissions, role)).ok().ok_or(AuthError::IOError))
+            .map(|mut permissions: Vec<String>| {
+                permissions.sort();
+                permissions
+            })
+    }
 }
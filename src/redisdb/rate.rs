@@ -0,0 +1,76 @@
+use redis::{ self, Commands, PipelineCommands };
+use std::str::FromStr;
+use r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
+use ::AuthError;
+use ::limitation::rate::{ RateStore, RateAlgorithm, Decision, BucketState, WindowState, token_bucket, sliding_window };
+
+/// Rate limiter store shared by every instance of the application
+///
+/// The state of a key is read and written in a WATCH transaction, so concurrent hits never count twice.
+pub struct RedisRateStore {
+    pool: Pool<RedisConnectionManager>,
+    prefix: String
+}
+
+impl RedisRateStore {
+    pub fn new(s: &Pool<RedisConnectionManager>, prefix: String) -> RedisRateStore {
+        RedisRateStore {
+            pool: s.clone(),
+            prefix: prefix
+        }
+    }
+}
+
+impl RateStore for RedisRateStore {
+    fn hit(&self, key: &str, algorithm: &RateAlgorithm, cost: u64, now: i64) -> Result<Decision, AuthError> {
+        let con = self.pool.get().map_err(|e| {
+            error!("Cannot get redis pool: {}", e);
+            AuthError::IOError
+        })?;
+
+        let key = format!("{}authorize:rate:{}", self.prefix, key);
+        let mut decision = None;
+
+        redis::transaction(&*con, &[key.as_str()], |pipe| -> redis::RedisResult<Option<()>> {
+            let stored: Option<String> = con.get(key.as_str())?;
+            let numbers: Vec<f64> = stored.iter()
+                .flat_map(|s| s.split(':'))
+                .filter_map(|n| f64::from_str(n).ok())
+                .collect();
+
+            let (value, ttl, result) = match *algorithm {
+                RateAlgorithm::TokenBucket { capacity, refill_per_second } => {
+                    let state = match numbers.len() {
+                        2 => Some(BucketState { tokens: numbers[0], updated_at: numbers[1] as i64 }),
+                        _ => None
+                    };
+                    let (state, result) = token_bucket(state, capacity, refill_per_second, cost, now);
+                    // a full bucket is the same as no bucket
+                    let ttl = match refill_per_second > 0.0 {
+                        true => (capacity as f64 / refill_per_second).ceil() as usize + 1,
+                        false => 86400
+                    };
+                    (format!("{}:{}", state.tokens, state.updated_at), ttl, result)
+                },
+                RateAlgorithm::SlidingWindow { limit, window } => {
+                    let state = match numbers.len() {
+                        3 => Some(WindowState { window: numbers[0] as i64, current: numbers[1] as u64, previous: numbers[2] as u64 }),
+                        _ => None
+                    };
+                    let (state, result) = sliding_window(state, limit, window, cost, now);
+                    // the counts of the current window are needed during the next one
+                    (format!("{}:{}:{}", state.window, state.current, state.previous), (window * 2) as usize, result)
+                }
+            };
+
+            decision = Some(result);
+            pipe.set_ex(key.as_str(), value, ttl).ignore().query(&*con)
+        }).map_err(|e| {
+            error!("Cannot update rate limit {}: {}", key, e);
+            AuthError::IOError
+        })?;
+
+        decision.ok_or(AuthError::IOError)
+    }
+}
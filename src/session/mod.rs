@@ -0,0 +1,172 @@
+pub mod ticket;
+
+use std::cmp;
+use ::net::random_string;
+use ::net::client::ClientInfo;
+
+const SESSION_ID_LENGTH: u8 = 16;
+
+/// Thirty days
+const DEFAULT_REFRESH_LIFETIME: i64 = 2_592_000;
+
+/// Rules of access token expiration, passed to the entity
+///
+/// ```
+/// use auth_rocket::session::TokenPolicy;
+///
+/// let policy = TokenPolicy::new(3600, Some(7200), true);
+/// // fresh token lives for the idle timeout
+/// assert_eq!(policy.expires_at(1000, 1000), Some(4600));
+/// // sliding can't move expiration past the absolute lifetime
+/// assert_eq!(policy.expires_at(1000, 6000), Some(8200));
+/// assert_eq!(policy.expires_at(1000, 8200), None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenPolicy {
+    /// Seconds of inactivity after which the token expires
+    pub idle_timeout: i64,
+    /// Seconds since the token was issued after which it expires anyway, `None` means no limit
+    pub max_lifetime: Option<i64>,
+    /// Prolong the token on every authorized request
+    pub sliding: bool,
+    /// Seconds a refresh token stays valid, every rotation issues a token with the full lifetime
+    /// unless the session reaches `max_lifetime` earlier
+    pub refresh_lifetime: i64
+}
+
+impl TokenPolicy {
+    pub fn new(idle_timeout: i64, max_lifetime: Option<i64>, sliding: bool) -> Self {
+        TokenPolicy {
+            idle_timeout: idle_timeout,
+            max_lifetime: max_lifetime,
+            sliding: sliding,
+            refresh_lifetime: DEFAULT_REFRESH_LIFETIME
+        }
+    }
+
+    pub fn with_refresh_lifetime(mut self, refresh_lifetime: i64) -> Self {
+        self.refresh_lifetime = refresh_lifetime;
+        self
+    }
+
+    /// Seconds the token issued at `created` may live counting from `now`, `None` if it is expired
+    pub fn ttl(&self, created: i64, now: i64) -> Option<i64> {
+        let ttl = match self.max_lifetime {
+            Some(max) => cmp::min(self.idle_timeout, created + max - now),
+            None => self.idle_timeout
+        };
+
+        match ttl > 0 {
+            true => Some(ttl),
+            false => None
+        }
+    }
+
+    /// Timestamp when the token issued at `created` and used at `now` expires
+    pub fn expires_at(&self, created: i64, now: i64) -> Option<i64> {
+        self.ttl(created, now).map(|ttl| now + ttl)
+    }
+
+    /// Timestamp when the refresh token issued at `now` for the session started at `created` expires
+    ///
+    /// ```
+    /// use auth_rocket::session::TokenPolicy;
+    ///
+    /// let policy = TokenPolicy::new(3600, Some(86_400), true).with_refresh_lifetime(7200);
+    /// assert_eq!(policy.refresh_expires_at(1000, 1000), 8200);
+    /// // refresh can't prolong the session past the absolute lifetime
+    /// assert_eq!(policy.refresh_expires_at(1000, 84_000), 87_400);
+    /// ```
+    pub fn refresh_expires_at(&self, created: i64, now: i64) -> i64 {
+        match self.max_lifetime {
+            Some(max) => cmp::min(now + self.refresh_lifetime, created + max),
+            None => now + self.refresh_lifetime
+        }
+    }
+}
+
+impl Default for TokenPolicy {
+    /// One hour of inactivity, one day at most, refresh tokens live for thirty days
+    fn default() -> Self {
+        TokenPolicy::new(3600, Some(86_400), true)
+    }
+}
+
+/// One signed in device of the user
+///
+/// The access token itself is never exposed, sessions are referenced by `id`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub expires_at: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>
+}
+
+impl Session {
+    /// Fresh session for the client signed in at `now`
+    pub fn new(client: &ClientInfo, now: i64, expires_at: i64) -> Self {
+        Session {
+            id: random_string(SESSION_ID_LENGTH),
+            created_at: now,
+            last_seen: now,
+            expires_at: expires_at,
+            user_agent: client.user_agent.clone(),
+            ip: client.ip.clone()
+        }
+    }
+
+    pub fn is_alive(&self, now: i64) -> bool {
+        self.expires_at > now
+    }
+
+    /// Mark session as used at `now`, returns false if the policy says it is expired
+    pub fn touch(&mut self, policy: &TokenPolicy, now: i64) -> bool {
+        if !self.is_alive(now) {
+            return false;
+        }
+
+        self.last_seen = now;
+
+        if policy.sliding {
+            match policy.expires_at(self.created_at, now) {
+                Some(expires_at) => self.expires_at = expires_at,
+                None => return false
+            }
+        }
+
+        true
+    }
+}
+
+/// Long-lived token which can be exchanged once for a fresh access token of the session
+///
+/// All refresh tokens of a session form a family: when a token which was
+/// already used comes back, the session is considered stolen and revoked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefreshToken {
+    pub username: String,
+    pub session_id: String,
+    /// `created_at` of the session, it is kept by every renewal
+    pub session_created_at: i64,
+    pub expires_at: i64,
+    pub used: bool
+}
+
+impl RefreshToken {
+    pub fn new(username: &str, session_id: &str, session_created_at: i64, expires_at: i64) -> Self {
+        RefreshToken {
+            username: username.to_string(),
+            session_id: session_id.to_string(),
+            session_created_at: session_created_at,
+            expires_at: expires_at,
+            used: false
+        }
+    }
+
+    pub fn is_alive(&self, now: i64) -> bool {
+        self.expires_at > now
+    }
+}
@@ -0,0 +1,108 @@
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+use base64;
+use ::net::secure_random_bytes;
+
+const TICKET_LENGTH: usize = 32;
+
+/// Kind of tickets which confirm the email of a new user
+pub const VERIFY_EMAIL: &'static str = "verify_email";
+/// Kind of tickets which allow to set a new password without the old one
+pub const RESET_PASSWORD: &'static str = "reset_password";
+/// Kind of tickets between the password and the second factor of sign in
+pub const MFA_PENDING: &'static str = "mfa_pending";
+
+/// Fresh random single-use ticket to send to the user, only its hash is stored by the entity
+pub fn new_ticket() -> Option<String> {
+    secure_random_bytes(TICKET_LENGTH).map(|bytes| base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+/// Hash the ticket is stored under, so a leaked storage doesn't leak working links
+///
+/// ```
+/// use auth_rocket::session::ticket::hash_ticket;
+///
+/// assert_eq!(hash_ticket("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+/// ```
+pub fn hash_ticket(ticket: &str) -> String {
+    let mut sh = Sha256::new();
+    sh.input_str(ticket);
+    sh.result_str()
+}
+
+/// Email verification mode of `sign_up`, put it to the Rocket managed state with `AuthMailer` to enable it
+///
+/// ```
+/// use auth_rocket::session::ticket::Verification;
+///
+/// let verification = Verification::new("https://example.com/verify?token={token}");
+/// assert_eq!(verification.link("abc"), "https://example.com/verify?token=abc");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verification {
+    link: String,
+    ttl: i64
+}
+
+impl Verification {
+    /// `link` is the page of the frontend, `{token}` is replaced by the ticket
+    pub fn new(link: &str) -> Self {
+        Verification {
+            link: link.to_string(),
+            ttl: 86_400
+        }
+    }
+
+    /// Seconds the ticket is valid, one day by default
+    pub fn with_ttl(mut self, ttl: i64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    pub fn link(&self, ticket: &str) -> String {
+        self.link.replace("{token}", ticket)
+    }
+}
+
+/// Password reset by email, put it to the Rocket managed state with `AuthMailer` to enable it
+///
+/// ```
+/// use auth_rocket::session::ticket::PasswordReset;
+///
+/// let reset = PasswordReset::new("https://example.com/reset?token={token}");
+/// assert_eq!(reset.ttl(), 3600);
+/// assert_eq!(reset.link("abc"), "https://example.com/reset?token=abc");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordReset {
+    link: String,
+    ttl: i64
+}
+
+impl PasswordReset {
+    /// `link` is the page of the frontend with the new password form, `{token}` is replaced by the ticket
+    pub fn new(link: &str) -> Self {
+        PasswordReset {
+            link: link.to_string(),
+            ttl: 3600
+        }
+    }
+
+    /// Seconds the ticket is valid, one hour by default
+    pub fn with_ttl(mut self, ttl: i64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    pub fn link(&self, ticket: &str) -> String {
+        self.link.replace("{token}", ticket)
+    }
+}
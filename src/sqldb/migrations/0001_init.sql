@@ -0,0 +1,22 @@
+CREATE TABLE users (
+    id INTEGER NOT NULL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    password VARCHAR(255) NOT NULL,
+    status SMALLINT NOT NULL DEFAULT 0,
+    role VARCHAR(255) NOT NULL,
+    attributes TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    CONSTRAINT users_name_unique UNIQUE (name),
+    CONSTRAINT users_email_unique UNIQUE (email)
+);
+
+CREATE INDEX users_created_at ON users (created_at);
+
+CREATE TABLE tokens (
+    token VARCHAR(255) NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX tokens_user_id ON tokens (user_id);
@@ -0,0 +1,16 @@
+-- Tokens are replaced by sessions, users have to sign in once again after the upgrade
+DROP TABLE tokens;
+
+CREATE TABLE sessions (
+    id VARCHAR(64) NOT NULL PRIMARY KEY,
+    token VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    last_seen BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    user_agent VARCHAR(512),
+    ip VARCHAR(64),
+    CONSTRAINT sessions_token_unique UNIQUE (token)
+);
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
@@ -0,0 +1,9 @@
+CREATE TABLE refresh_tokens (
+    token VARCHAR(255) NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    session_id VARCHAR(64) NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX refresh_tokens_session_id ON refresh_tokens (session_id);
@@ -0,0 +1,7 @@
+CREATE TABLE tickets (
+    kind VARCHAR(32) NOT NULL,
+    ticket VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at BIGINT NOT NULL,
+    PRIMARY KEY (kind, ticket)
+);
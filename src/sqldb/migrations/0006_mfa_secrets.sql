@@ -0,0 +1,6 @@
+CREATE TABLE mfa_secrets (
+    user_id INTEGER NOT NULL PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    secret VARCHAR(255) NOT NULL,
+    confirmed INTEGER NOT NULL DEFAULT 0,
+    last_step BIGINT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,5 @@
+CREATE TABLE recovery_codes (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code VARCHAR(64) NOT NULL,
+    PRIMARY KEY (user_id, code)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE counters (
+    name VARCHAR(255) NOT NULL PRIMARY KEY,
+    value BIGINT NOT NULL DEFAULT 0,
+    expires_at BIGINT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE role_permissions (
+    role VARCHAR(255) NOT NULL,
+    permission VARCHAR(255) NOT NULL,
+    PRIMARY KEY (role, permission)
+);
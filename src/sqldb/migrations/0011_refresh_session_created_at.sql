@@ -0,0 +1,4 @@
+-- Start of the session, renewals keep it so the session can't outlive its absolute lifetime
+ALTER TABLE refresh_tokens ADD COLUMN session_created_at BIGINT;
+
+UPDATE refresh_tokens SET session_created_at = (SELECT sessions.created_at FROM sessions WHERE sessions.id = refresh_tokens.session_id);
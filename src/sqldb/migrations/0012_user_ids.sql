@@ -0,0 +1,6 @@
+-- Ids of new users are taken from this counter, so ids of deleted users are never given again
+CREATE TABLE user_ids (
+    last_id INTEGER NOT NULL
+);
+
+INSERT INTO user_ids (last_id) SELECT COALESCE(MAX(id), 0) FROM users;
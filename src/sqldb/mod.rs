@@ -0,0 +1,707 @@
+use std::collections::{ HashMap, BTreeSet };
+use super::{Entity, User, AuthError, Role, UserStatus, PrivateUser, MfaSecret};
+use std::str::FromStr;
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{self, Connection, Row, ErrorCode};
+use chrono::Local;
+use serde_json;
+use ::paging::range_bounds;
+use ::password::{ PasswordHasher, Argon2Hasher, PasswordMatch, verify_password };
+use ::session::{ TokenPolicy, Session, RefreshToken };
+use ::net::client::ClientInfo;
+
+/// Schema migrations embedded into the binary, applied in order by `SqlEntity::migrate`.
+///
+/// Scripts only use portable SQL, so the same files can be applied to PostgreSQL.
+/// User ids are assigned by the entity from the `user_ids` counter, no table relies on auto increment.
+const MIGRATIONS: &'static [(i64, &'static str)] = &[
+    (1, include_str!("migrations/0001_init.sql")),
+    (2, include_str!("migrations/0002_token_created_at.sql")),
+    (3, include_str!("migrations/0003_sessions.sql")),
+    (4, include_str!("migrations/0004_refresh_tokens.sql")),
+    (5, include_str!("migrations/0005_tickets.sql")),
+    (6, include_str!("migrations/0006_mfa_secrets.sql")),
+    (7, include_str!("migrations/0007_recovery_codes.sql")),
+    (8, include_str!("migrations/0008_counters.sql")),
+    (9, include_str!("migrations/0009_user_roles.sql")),
+    (10, include_str!("migrations/0010_role_permissions.sql")),
+    (11, include_str!("migrations/0011_refresh_session_created_at.sql")),
+    (12, include_str!("migrations/0012_user_ids.sql")),
+];
+
+const USER_COLUMNS: &'static str = "id, name, email, password, status, attributes";
+
+const SESSION_COLUMNS: &'static str = "sessions.id, sessions.created_at, sessions.last_seen, sessions.expires_at, sessions.user_agent, sessions.ip";
+
+pub struct SqlEntity {
+    pool: Pool<SqliteConnectionManager>,
+    hasher: Box<PasswordHasher>,
+    token_policy: TokenPolicy
+}
+
+impl SqlEntity {
+    /// Create entity and apply all pending migrations
+    pub fn new(s: &Pool<SqliteConnectionManager>) -> Result<SqlEntity, AuthError> {
+        let entity = SqlEntity {
+            pool: s.clone(),
+            hasher: Box::new(Argon2Hasher::default()),
+            token_policy: TokenPolicy::default()
+        };
+
+        entity.migrate().map(|_| entity)
+    }
+
+    /// Use another algorithm for new passwords
+    pub fn with_hasher(mut self, hasher: Box<PasswordHasher>) -> SqlEntity {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Use another expiration rules for new and prolonged tokens
+    pub fn with_token_policy(mut self, token_policy: TokenPolicy) -> SqlEntity {
+        self.token_policy = token_policy;
+        self
+    }
+
+    /// Apply embedded schema migrations which are not applied yet
+    pub fn migrate(&self) -> Result<(), AuthError> {
+        let mut con = self.get_conn()?;
+
+        con.execute_batch("CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT NOT NULL PRIMARY KEY,
+            applied_at BIGINT NOT NULL
+        )").map_err(sql_error)?;
+
+        for &(version, script) in MIGRATIONS {
+            let applied: i64 = con.query_row("SELECT COUNT(*) FROM schema_migrations WHERE version = ?", &[&version], |row| row.get(0))
+                .map_err(sql_error)?;
+
+            if applied > 0 {
+                continue;
+            }
+
+            info!("apply schema migration {}", version);
+
+            let tx = con.transaction().map_err(sql_error)?;
+            tx.execute_batch(script).map_err(sql_error)?;
+            tx.execute("INSERT INTO schema_migrations (version, applied_at) VALUES (?, ?)", &[&version, &Local::now().timestamp()])
+                .map_err(sql_error)?;
+            tx.commit().map_err(sql_error)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<SqliteConnectionManager>, AuthError> {
+        match self.pool.get() {
+            Ok(con) => {
+                con.execute_batch("PRAGMA foreign_keys = ON").map_err(sql_error)?;
+                Ok(con)
+            },
+            Err(e) => {
+                error!("Cannot get sql pool: {}", e);
+                Err(AuthError::IOError)
+            }
+        }
+    }
+
+    fn find_user(&self, con: &Connection, column: &str, value: &rusqlite::types::ToSql) -> Result<PrivateUser, AuthError> {
+        let mut user = con.query_row(&format!("SELECT {} FROM users WHERE {} = ?", USER_COLUMNS, column), &[value], user_from_row)
+            .map_err(sql_error)?;
+
+        user.roles = load_roles(con, user.id)?;
+        Ok(user)
+    }
+
+    fn rehash_password(&self, username: &str, password: &str) -> Option<AuthError> {
+        self.hasher.hash(password)
+            .and_then(|hash| self.set_user_column(username, "password", &hash))
+            .err()
+    }
+
+    fn set_user_column(&self, username: &str, column: &str, value: &rusqlite::types::ToSql) -> Result<User, AuthError> {
+        let con = self.get_conn()?;
+
+        match con.execute(&format!("UPDATE users SET {} = ? WHERE name = ?", column), &[value, &username]) {
+            Ok(0) => Err(AuthError::NotFound),
+            Ok(_) => self.find_user(&con, "name", &username).map(|user| User::from(user)),
+            Err(e) => Err(sql_error(e))
+        }
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> AuthError {
+    match e {
+        rusqlite::Error::QueryReturnedNoRows => AuthError::NotFound,
+        e => {
+            error!("SQL error: {}", e);
+            AuthError::IOError
+        }
+    }
+}
+
+fn status_to_code(status: &UserStatus) -> i32 {
+    match *status {
+        UserStatus::Created => 0,
+        UserStatus::Active => 1,
+        UserStatus::Disabled => 2,
+        UserStatus::Unknown => 3
+    }
+}
+
+fn session_from_row(row: &Row) -> Session {
+    Session {
+        id: row.get(0),
+        created_at: row.get(1),
+        last_seen: row.get(2),
+        expires_at: row.get(3),
+        user_agent: row.get(4),
+        ip: row.get(5)
+    }
+}
+
+/// Roles live in their own table, so users are loaded without them
+fn user_from_row(row: &Row) -> PrivateUser {
+    let status: i32 = row.get(4);
+    let attributes: String = row.get(5);
+
+    PrivateUser {
+        id: row.get(0),
+        name: row.get(1),
+        email: row.get(2),
+        password: row.get(3),
+        status: match status { 0 => UserStatus::Created, 1 => UserStatus::Active, 2 => UserStatus::Disabled, _ => UserStatus::Unknown },
+        roles: BTreeSet::new(),
+        attributes: serde_json::from_str::<HashMap<String, String>>(&attributes).unwrap_or(HashMap::new())
+    }
+}
+
+fn load_roles(con: &Connection, user_id: i32) -> Result<BTreeSet<Role>, AuthError> {
+    let mut stmt = con.prepare("SELECT role FROM user_roles WHERE user_id = ?").map_err(sql_error)?;
+    let rows = stmt.query_map(&[&user_id], |row| row.get::<_, String>(0)).map_err(sql_error)?;
+
+    let mut roles = BTreeSet::new();
+    for row in rows {
+        roles.insert(Role::from_str(&row.map_err(sql_error)?).unwrap_or(Role::Custom("unknown".to_string())));
+    }
+
+    Ok(roles)
+}
+
+impl Entity for SqlEntity {
+    fn add_user(&self, name: &str, email: &str, password: &str, attributes: HashMap<String, String>) -> Result<User, AuthError> {
+        let hash = self.hasher.hash(password)?;
+        let mut con = self.get_conn()?;
+
+        match self.find_user(&con, "name", &name) {
+            Ok(_) => return Err(AuthError::DuplicateUsername),
+            Err(AuthError::NotFound) => {},
+            Err(e) => return Err(e)
+        }
+
+        let result = {
+            let tx = con.transaction().map_err(sql_error)?;
+
+            // the counter row stays locked until the commit, so concurrent sign ups get different ids
+            let id = tx.execute("UPDATE user_ids SET last_id = last_id + 1", &[])
+                .and_then(|_| tx.query_row("SELECT last_id FROM user_ids", &[], |row| row.get::<_, i32>(0)));
+
+            // the role column is only read by older versions, roles live in user_roles
+            let inserted = id.and_then(|id| tx.execute(
+                "INSERT INTO users (id, name, email, password, status, role, attributes, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                &[&id, &name, &email, &hash, &status_to_code(&UserStatus::Created), &Role::Users.to_string(),
+                  &json!(attributes).to_string(), &Local::now().timestamp()]
+            ).and_then(|_| tx.execute(
+                "INSERT INTO user_roles (user_id, role) VALUES (?, ?)",
+                &[&id, &Role::Users.to_string()]
+            )));
+
+            match inserted {
+                Ok(_) => tx.commit(),
+                Err(e) => Err(e)
+            }
+        };
+
+        match result {
+            Ok(_) => self.find_user(&con, "name", &name).map(|user| User::from(user)),
+            Err(rusqlite::Error::SqliteFailure(ref e, ref message)) if e.code == ErrorCode::ConstraintViolation => {
+                match message.as_ref().map(|m| m.contains("email")).unwrap_or(false) {
+                    true => Err(AuthError::DuplicateEmail),
+                    false => Err(AuthError::DuplicateUsername)
+                }
+            },
+            Err(e) => Err(sql_error(e))
+        }
+    }
+
+    fn get_user_by_id(&self, user_id: i32) -> Result<User, AuthError> {
+        self.get_conn()
+            .and_then(|con| self.find_user(&con, "id", &user_id))
+            .map(|user| User::from(user))
+    }
+
+    fn get_user_by_name(&self, username: &str) -> Result<PrivateUser, AuthError> {
+        self.get_conn()
+            .and_then(|con| self.find_user(&con, "name", &username))
+    }
+
+    fn get_user_by_name_and_pwd(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        self.get_user_by_name(username)
+            .and_then(|u| match verify_password(&*self.hasher, password, &u.password) {
+                PasswordMatch::Valid => Ok(User::from(u)),
+                PasswordMatch::Outdated => {
+                    if let Some(e) = self.rehash_password(&u.name, password) {
+                        warn!("cannot rehash password of user {} ({})", u.name, e);
+                    }
+                    Ok(User::from(u))
+                },
+                PasswordMatch::Invalid => Err(AuthError::NotFound)
+            })
+    }
+
+    fn delete_user(&self, user_id: i32) -> Option<AuthError> {
+        match self.get_conn() {
+            Ok(con) => match con.execute("DELETE FROM users WHERE id = ?", &[&user_id]) {
+                Ok(0) => {
+                    warn!("user with id ({}) not found in sql DB", user_id);
+                    None
+                },
+                Ok(_) => None,
+                Err(e) => Some(sql_error(e))
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn list_users(&self, from: isize, count:isize) -> Result<Vec<User>, AuthError> {
+        let con = self.get_conn()?;
+
+        let total: i64 = con.query_row("SELECT COUNT(*) FROM users", &[], |row| row.get(0)).map_err(sql_error)?;
+
+        let (start, end) = match range_bounds(total as usize, from, count) {
+            Some(bounds) => bounds,
+            None => return Ok(Vec::new())
+        };
+
+        let mut stmt = con.prepare(&format!("SELECT {} FROM users ORDER BY created_at, id LIMIT ? OFFSET ?", USER_COLUMNS))
+            .map_err(sql_error)?;
+        let rows = stmt.query_map(&[&((end - start) as i64), &(start as i64)], user_from_row).map_err(sql_error)?;
+
+        let mut v: Vec<User> = Vec::new();
+        for row in rows {
+            let mut user = row.map_err(sql_error)?;
+            user.roles = load_roles(&con, user.id)?;
+            v.push(User::from(user));
+        }
+
+        Ok(v)
+    }
+
+    fn enable_user(&self, username: &str) -> Result<User, AuthError> {
+        self.set_user_column(username, "status", &status_to_code(&UserStatus::Active))
+    }
+
+    fn disable_user(&self, username: &str) -> Result<User, AuthError> {
+        self.set_user_column(username, "status", &status_to_code(&UserStatus::Disabled))
+    }
+
+    fn get_token(&self, username: &str) -> Result<String, AuthError> {
+        self.get_conn()
+            .and_then(|con| con.query_row(
+                "SELECT sessions.token FROM sessions INNER JOIN users ON users.id = sessions.user_id \
+                 WHERE users.name = ? AND sessions.expires_at > ? ORDER BY sessions.created_at DESC LIMIT 1",
+                &[&username, &Local::now().timestamp()],
+                |row| row.get(0)
+            ).map_err(sql_error))
+    }
+
+    fn get_user_by_token(&self, token: &str) -> Result<User, AuthError> {
+        let con = self.get_conn()?;
+        let now = Local::now().timestamp();
+
+        let mut session = con.query_row(&format!("SELECT {} FROM sessions WHERE token = ? AND expires_at > ?", SESSION_COLUMNS),
+                                        &[&token, &now], session_from_row)
+            .map_err(sql_error)?;
+
+        let user_id: i32 = con.query_row("SELECT user_id FROM sessions WHERE token = ?", &[&token], |row| row.get(0))
+            .map_err(sql_error)?;
+        let user = self.find_user(&con, "id", &user_id)?;
+
+        if user.status != UserStatus::Active {
+            return Err(AuthError::NotActive);
+        }
+
+        match session.touch(&self.token_policy, now) {
+            true => con.execute("UPDATE sessions SET last_seen = ?, expires_at = ? WHERE id = ?", &[&session.last_seen, &session.expires_at, &session.id])
+                .map_err(sql_error)
+                .map(|_| User::from(user)),
+            false => con.execute("DELETE FROM sessions WHERE id = ?", &[&session.id])
+                .map_err(sql_error)
+                .and_then(|_| Err(AuthError::NotFound))
+        }
+    }
+
+    fn delete_token(&self, token: &str) -> Option<AuthError> {
+        match self.get_conn() {
+            Ok(con) => match con.execute("DELETE FROM refresh_tokens WHERE session_id = (SELECT id FROM sessions WHERE token = ?)", &[&token])
+                .and_then(|_| con.execute("DELETE FROM sessions WHERE token = ?", &[&token])) {
+                Ok(0) => Some(AuthError::NotFound),
+                Ok(_) => None,
+                Err(e) => Some(sql_error(e))
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn add_session(&self, username: &str, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        let con = self.get_conn()?;
+        let user = self.find_user(&con, "name", &username)?;
+
+        let now = Local::now().timestamp();
+        let session = Session::new(client, now, self.token_policy.expires_at(now, now).unwrap_or(now));
+
+        con.execute("DELETE FROM sessions WHERE token = ?", &[&token])
+            .and_then(|_| con.execute(
+                "INSERT INTO sessions (id, token, user_id, created_at, last_seen, expires_at, user_agent, ip) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                &[&session.id, &token, &user.id, &session.created_at, &session.last_seen, &session.expires_at, &session.user_agent, &session.ip]
+            ))
+            .map_err(sql_error)
+            .map(|_| session)
+    }
+
+    fn renew_session(&self, username: &str, session_id: &str, created_at: i64, token: &str, client: &ClientInfo) -> Result<Session, AuthError> {
+        let con = self.get_conn()?;
+        let user = self.find_user(&con, "name", &username)?;
+
+        let foreign: i64 = con.query_row("SELECT COUNT(*) FROM sessions WHERE id = ? AND user_id != ?", &[&session_id, &user.id], |row| row.get(0))
+            .map_err(sql_error)?;
+
+        if foreign > 0 {
+            return Err(AuthError::NotFound);
+        }
+
+        let now = Local::now().timestamp();
+        let mut session = Session::new(client, now, self.token_policy.expires_at(created_at, now).ok_or(AuthError::NotFound)?);
+        session.id = session_id.to_string();
+        session.created_at = created_at;
+
+        con.execute("DELETE FROM sessions WHERE id = ? OR token = ?", &[&session_id, &token])
+            .and_then(|_| con.execute(
+                "INSERT INTO sessions (id, token, user_id, created_at, last_seen, expires_at, user_agent, ip) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                &[&session.id, &token, &user.id, &session.created_at, &session.last_seen, &session.expires_at, &session.user_agent, &session.ip]
+            ))
+            .map_err(sql_error)
+            .map(|_| session)
+    }
+
+    fn get_session_by_token(&self, token: &str) -> Result<Session, AuthError> {
+        self.get_conn()
+            .and_then(|con| con.query_row(&format!("SELECT {} FROM sessions WHERE token = ? AND expires_at > ?", SESSION_COLUMNS),
+                                          &[&token, &Local::now().timestamp()], session_from_row)
+                .map_err(sql_error))
+    }
+
+    fn list_sessions(&self, username: &str) -> Result<Vec<Session>, AuthError> {
+        let con = self.get_conn()?;
+
+        let mut stmt = con.prepare(&format!(
+            "SELECT {} FROM sessions INNER JOIN users ON users.id = sessions.user_id \
+             WHERE users.name = ? AND sessions.expires_at > ? ORDER BY sessions.created_at", SESSION_COLUMNS
+        )).map_err(sql_error)?;
+        let rows = stmt.query_map(&[&username, &Local::now().timestamp()], session_from_row).map_err(sql_error)?;
+
+        let mut list: Vec<Session> = Vec::new();
+        for row in rows {
+            list.push(row.map_err(sql_error)?);
+        }
+
+        Ok(list)
+    }
+
+    fn delete_session(&self, username: &str, session_id: &str) -> Option<AuthError> {
+        match self.get_conn() {
+            Ok(con) => match con.execute("DELETE FROM sessions WHERE id = ? AND user_id = (SELECT id FROM users WHERE name = ?)", &[&session_id, &username])
+                .and_then(|sessions| con.execute("DELETE FROM refresh_tokens WHERE session_id = ? AND user_id = (SELECT id FROM users WHERE name = ?)", &[&session_id, &username])
+                    .map(|tokens| sessions + tokens)) {
+                Ok(0) => Some(AuthError::NotFound),
+                Ok(_) => None,
+                Err(e) => Some(sql_error(e))
+            },
+            Err(e) => Some(e)
+        }
+    }
+
+    fn delete_sessions(&self, username: &str) -> Option<AuthError> {
+        self.get_conn()
+            .and_then(|con| con.execute("DELETE FROM sessions WHERE user_id = (SELECT id FROM users WHERE name = ?)", &[&username])
+                .and_then(|_| con.execute("DELETE FROM refresh_tokens WHERE user_id = (SELECT id FROM users WHERE name = ?)", &[&username]))
+                .map_err(sql_error))
+            .err()
+    }
+
+    fn add_refresh_token(&self, username: &str, token: &str, session: &Session) -> Option<AuthError> {
+        let con = match self.get_conn() {
+            Ok(con) => con,
+            Err(e) => return Some(e)
+        };
+
+        let now = Local::now().timestamp();
+        let expires_at = self.token_policy.refresh_expires_at(session.created_at, now);
+
+        self.find_user(&con, "name", &username)
+            .and_then(|user| con.execute("DELETE FROM refresh_tokens WHERE expires_at <= ?", &[&now])
+                .and_then(|_| con.execute("INSERT INTO refresh_tokens (token, user_id, session_id, session_created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+                                          &[&token, &user.id, &session.id, &session.created_at, &expires_at]))
+                .map_err(sql_error))
+            .err()
+    }
+
+    fn take_refresh_token(&self, token: &str) -> Result<RefreshToken, AuthError> {
+        let mut con = self.get_conn()?;
+        let refresh_lifetime = self.token_policy.refresh_lifetime;
+
+        // the increment and the read are one transaction, so of two concurrent requests only one reads the token unused
+        let taken = {
+            let tx = con.transaction().map_err(sql_error)?;
+
+            match tx.execute("UPDATE refresh_tokens SET used = used + 1 WHERE token = ? AND expires_at > ?", &[&token, &Local::now().timestamp()]) {
+                Ok(0) => return Err(AuthError::NotFound),
+                Ok(_) => (),
+                Err(e) => return Err(sql_error(e))
+            }
+
+            let taken = tx.query_row(
+                "SELECT users.name, refresh_tokens.session_id, refresh_tokens.session_created_at, refresh_tokens.expires_at, refresh_tokens.used \
+                 FROM refresh_tokens INNER JOIN users ON users.id = refresh_tokens.user_id WHERE refresh_tokens.token = ?",
+                &[&token],
+                |row| {
+                    let expires_at: i64 = row.get(3);
+                    RefreshToken {
+                        username: row.get(0),
+                        session_id: row.get(1),
+                        // tokens stored before the session start was kept count from their own issue
+                        session_created_at: row.get::<_, Option<i64>>(2).unwrap_or(expires_at - refresh_lifetime),
+                        expires_at: expires_at,
+                        used: row.get::<_, i64>(4) > 1
+                    }
+                }
+            ).map_err(sql_error)?;
+
+            tx.commit().map_err(sql_error)?;
+            taken
+        };
+
+        Ok(taken)
+    }
+
+    fn add_ticket(&self, kind: &str, ticket: &str, username: &str, ttl: i64) -> Option<AuthError> {
+        let con = match self.get_conn() {
+            Ok(con) => con,
+            Err(e) => return Some(e)
+        };
+
+        let now = Local::now().timestamp();
+
+        self.find_user(&con, "name", &username)
+            .and_then(|user| con.execute("DELETE FROM tickets WHERE expires_at <= ?", &[&now])
+                .and_then(|_| con.execute("INSERT OR REPLACE INTO tickets (kind, ticket, user_id, expires_at) VALUES (?, ?, ?, ?)",
+                                          &[&kind, &ticket, &user.id, &(now + ttl)]))
+                .map_err(sql_error))
+            .err()
+    }
+
+    fn get_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        self.get_conn()
+            .and_then(|con| con.query_row(
+                "SELECT users.name FROM tickets INNER JOIN users ON users.id = tickets.user_id \
+                 WHERE tickets.kind = ? AND tickets.ticket = ? AND tickets.expires_at > ?",
+                &[&kind, &ticket, &Local::now().timestamp()],
+                |row| row.get(0)
+            ).map_err(sql_error))
+    }
+
+    fn take_ticket(&self, kind: &str, ticket: &str) -> Result<String, AuthError> {
+        let username = self.get_ticket(kind, ticket)?;
+        let con = self.get_conn()?;
+
+        // only the request which actually deleted the row gets the ticket
+        match con.execute("DELETE FROM tickets WHERE kind = ? AND ticket = ?", &[&kind, &ticket]) {
+            Ok(0) => Err(AuthError::NotFound),
+            Ok(_) => Ok(username),
+            Err(e) => Err(sql_error(e))
+        }
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        self.hasher.hash(password).and_then(|hash| self.set_user_column(username, "password", &hash))
+    }
+
+    fn set_mfa_secret(&self, username: &str, secret: Option<MfaSecret>) -> Option<AuthError> {
+        let con = match self.get_conn() {
+            Ok(con) => con,
+            Err(e) => return Some(e)
+        };
+
+        self.find_user(&con, "name", &username)
+            .and_then(|user| match secret {
+                Some(secret) => con.execute("INSERT OR REPLACE INTO mfa_secrets (user_id, secret, confirmed, last_step) VALUES (?, ?, ?, ?)",
+                                            &[&user.id, &secret.secret, &secret.confirmed, &secret.last_step]),
+                None => con.execute("DELETE FROM mfa_secrets WHERE user_id = ?", &[&user.id])
+            }.map_err(sql_error))
+            .err()
+    }
+
+    fn get_mfa_secret(&self, username: &str) -> Result<MfaSecret, AuthError> {
+        let con = self.get_conn()?;
+
+        con.query_row(
+            "SELECT mfa_secrets.secret, mfa_secrets.confirmed, mfa_secrets.last_step FROM mfa_secrets \
+             INNER JOIN users ON users.id = mfa_secrets.user_id WHERE users.name = ?",
+            &[&username],
+            |row| MfaSecret {
+                secret: row.get(0),
+                confirmed: row.get(1),
+                last_step: row.get(2)
+            }
+        ).map_err(sql_error)
+    }
+
+    fn set_recovery_codes(&self, username: &str, codes: &[String]) -> Option<AuthError> {
+        let mut con = match self.get_conn() {
+            Ok(con) => con,
+            Err(e) => return Some(e)
+        };
+
+        let user = match self.find_user(&con, "name", &username) {
+            Ok(user) => user,
+            Err(e) => return Some(e)
+        };
+
+        let replaced = con.transaction().and_then(|tx| {
+            tx.execute("DELETE FROM recovery_codes WHERE user_id = ?", &[&user.id])?;
+            for code in codes {
+                tx.execute("INSERT INTO recovery_codes (user_id, code) VALUES (?, ?)", &[&user.id, code])?;
+            }
+            tx.commit()
+        });
+
+        replaced.map_err(sql_error).err()
+    }
+
+    fn take_recovery_code(&self, username: &str, code: &str) -> Option<AuthError> {
+        let con = match self.get_conn() {
+            Ok(con) => con,
+            Err(e) => return Some(e)
+        };
+
+        match con.execute("DELETE FROM recovery_codes WHERE user_id = (SELECT id FROM users WHERE name = ?) AND code = ?", &[&username, &code]) {
+            Ok(0) => Some(AuthError::NotFound),
+            Ok(_) => None,
+            Err(e) => Some(sql_error(e))
+        }
+    }
+
+    fn count_recovery_codes(&self, username: &str) -> Result<usize, AuthError> {
+        let con = self.get_conn()?;
+
+        con.query_row("SELECT COUNT(*) FROM recovery_codes INNER JOIN users ON users.id = recovery_codes.user_id WHERE users.name = ?",
+                      &[&username], |row| row.get::<_, i64>(0) as usize)
+            .map_err(sql_error)
+    }
+
+    fn incr_counter(&self, key: &str, ttl: i64) -> Result<i64, AuthError> {
+        let mut con = self.get_conn()?;
+        let now = Local::now().timestamp();
+
+        let tx = con.transaction().map_err(sql_error)?;
+        tx.execute("DELETE FROM counters WHERE name = ? AND expires_at <= ?", &[&key, &now]).map_err(sql_error)?;
+        tx.execute("INSERT OR IGNORE INTO counters (name, value, expires_at) VALUES (?, 0, ?)", &[&key, &(now + ttl)]).map_err(sql_error)?;
+        tx.execute("UPDATE counters SET value = value + 1 WHERE name = ?", &[&key]).map_err(sql_error)?;
+        let value: i64 = tx.query_row("SELECT value FROM counters WHERE name = ?", &[&key], |row| row.get(0)).map_err(sql_error)?;
+        tx.commit().map_err(sql_error)?;
+
+        Ok(value)
+    }
+
+    fn get_counter(&self, key: &str) -> Result<i64, AuthError> {
+        let con = self.get_conn()?;
+
+        match con.query_row("SELECT value FROM counters WHERE name = ? AND expires_at > ?", &[&key, &Local::now().timestamp()], |row| row.get(0)) {
+            Ok(value) => Ok(value),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            Err(e) => Err(sql_error(e))
+        }
+    }
+
+    fn set_counter(&self, key: &str, value: i64, ttl: i64) -> Option<AuthError> {
+        self.get_conn()
+            .and_then(|con| con.execute("INSERT OR REPLACE INTO counters (name, value, expires_at) VALUES (?, ?, ?)",
+                                        &[&key, &value, &(Local::now().timestamp() + ttl)])
+                .map_err(sql_error))
+            .err()
+    }
+
+    fn reset_counter(&self, key: &str) -> Option<AuthError> {
+        self.get_conn()
+            .and_then(|con| con.execute("DELETE FROM counters WHERE name = ?", &[&key]).map_err(sql_error))
+            .err()
+    }
+
+    fn set_user_attributes(&self, username: &str, attributes: HashMap<String, String>) -> Result<User, AuthError> {
+        self.set_user_column(username, "attributes", &json!(attributes).to_string())
+    }
+
+    fn add_user_role(&self, username: &str, role: Role) -> Result<User, AuthError> {
+        let con = self.get_conn()?;
+        let user = self.find_user(&con, "name", &username)?;
+
+        if !user.roles.contains(&role) {
+            con.execute("INSERT INTO user_roles (user_id, role) VALUES (?, ?)", &[&user.id, &role.to_string()])
+                .map_err(sql_error)?;
+        }
+
+        self.find_user(&con, "name", &username).map(|user| User::from(user))
+    }
+
+    fn remove_user_role(&self, username: &str, role: &Role) -> Result<User, AuthError> {
+        let con = self.get_conn()?;
+        let user = self.find_user(&con, "name", &username)?;
+
+        con.execute("DELETE FROM user_roles WHERE user_id = ? AND role = ?", &[&user.id, &role.to_string()])
+            .map_err(sql_error)?;
+
+        self.find_user(&con, "name", &username).map(|user| User::from(user))
+    }
+
+    fn grant_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        let role = role.to_string();
+        self.get_conn()
+            .and_then(|con| con.execute(
+                "INSERT INTO role_permissions (role, permission) SELECT ?, ? \
+                 WHERE NOT EXISTS (SELECT 1 FROM role_permissions WHERE role = ? AND permission = ?)",
+                &[&role, &permission, &role, &permission]
+            ).map_err(sql_error))
+            .err()
+    }
+
+    fn revoke_permission(&self, role: &Role, permission: &str) -> Option<AuthError> {
+        self.get_conn()
+            .and_then(|con| con.execute("DELETE FROM role_permissions WHERE role = ? AND permission = ?",
+                                        &[&role.to_string(), &permission]).map_err(sql_error))
+            .err()
+    }
+
+    fn list_permissions(&self, role: &Role) -> Result<Vec<String>, AuthError> {
+        let con = self.get_conn()?;
+        let mut stmt = con.prepare("SELECT permission FROM role_permissions WHERE role = ? ORDER BY permission").map_err(sql_error)?;
+        let rows = stmt.query_map(&[&role.to_string()], |row| row.get::<_, String>(0)).map_err(sql_error)?;
+
+        let mut permissions = Vec::new();
+        for row in rows {
+            permissions.push(row.map_err(sql_error)?);
+        }
+
+        Ok(permissions)
+    }
+}
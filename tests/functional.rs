@@ -2,6 +2,7 @@ extern crate redis;
 extern crate r2d2;
 extern crate r2d2_redis;
 extern crate auth_rocket;
+#[cfg(feature = "with-sql")] extern crate r2d2_sqlite;
 
 use redis::RedisError;
 use r2d2::Pool;
@@ -9,7 +10,8 @@ use r2d2_redis::RedisConnectionManager;
 use std::io::{ Error, ErrorKind };
 
 use auth_rocket::redisdb::RedisEntity;
-use auth_rocket::{ Entity, UserStatus, Role, AuthError };
+use auth_rocket::memorydb::MemoryEntity;
+use auth_rocket::{ Entity, UserStatus, Role, AuthError, ClientInfo, MfaSecret };
 use std::collections::HashMap;
 use redis::Commands;
 
@@ -23,6 +25,61 @@ fn test_redis_db() {
     functional_tests(&entity);
 }
 
+#[test]
+fn test_redis_single_role_records() {
+    let pool = connect_pool("redis://127.0.0.1/", true);
+    let entity: RedisEntity = RedisEntity::new(&pool, "functional_roles".to_string());
+    if let Ok(user) = entity.get_user_by_name("legacy") {
+        entity.delete_user(user.id);
+    }
+
+    let user = entity.add_user("legacy", "legacy@example.com", "qwertyu", HashMap::new()).unwrap();
+
+    // as written by the versions with a single role
+    let con = pool.get().unwrap();
+    con.del::<&str, i32>("functional_rolesauthorize:users:roles:legacy").unwrap();
+    con.hset::<&str, &str, &str, i32>("functional_rolesauthorize:users:name:legacy", "role", "admins").unwrap();
+
+    assert_eq!(entity.list_user_roles("legacy"), Ok(vec!(Role::Admins)));
+    assert_eq!(entity.migrate_roles(), Ok(1));
+    assert_eq!(entity.migrate_roles(), Ok(0));
+    assert_eq!(con.hget::<&str, &str, Option<String>>("functional_rolesauthorize:users:name:legacy", "role").unwrap(), None);
+    assert_eq!(entity.list_user_roles("legacy"), Ok(vec!(Role::Admins)));
+
+    assert_eq!(entity.delete_user(user.id), None);
+}
+
+#[test]
+fn test_memory_db() {
+    let entity = MemoryEntity::new();
+    functional_tests(&entity);
+}
+
+#[cfg(feature = "with-sql")]
+#[test]
+fn test_sql_db() {
+    use auth_rocket::sqldb::SqlEntity;
+    use auth_rocket::net::random_string;
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    let path = std::env::temp_dir().join(format!("auth_rocket_{}.sqlite", random_string(10)));
+    let pool = Pool::new(Default::default(), SqliteConnectionManager::file(&path)).unwrap();
+    let entity = SqlEntity::new(&pool).unwrap();
+    functional_tests(&entity);
+
+    let first = entity.add_user("first", "same@example.com", "qwertyu", HashMap::new()).unwrap();
+    assert_eq!(entity.add_user("first", "other@example.com", "qwertyu", HashMap::new()), Err(AuthError::DuplicateUsername));
+    assert_eq!(entity.add_user("second", "same@example.com", "qwertyu", HashMap::new()), Err(AuthError::DuplicateEmail));
+    assert_eq!(entity.migrate(), Ok(()));
+
+    // the id of the deleted user is never given again
+    assert_eq!(entity.delete_user(first.id), None);
+    let next = entity.add_user("next", "next@example.com", "qwertyu", HashMap::new()).unwrap();
+    assert!(next.id > first.id);
+
+    std::fs::remove_file(&path).unwrap_or(());
+}
+
 fn functional_tests(entity: &Entity) {
     remove_old_values(entity);
 
@@ -43,6 +100,49 @@ fn functional_tests(entity: &Entity) {
     assert_eq!(entity.get_user_by_token("just_my_token"), Err(AuthError::NotFound));
     assert_eq!(user.attributes, attrinbutes);
 
+    let phone = ClientInfo::new(Some("Phone".to_string()), Some("10.0.0.1".to_string()));
+    let first = entity.add_session(user.name.as_str(), "first_token", &phone).unwrap();
+    let second = entity.add_session(user.name.as_str(), "second_token", &ClientInfo::default()).unwrap();
+    assert_eq!(first.user_agent, Some("Phone".to_string()));
+    assert_eq!(entity.get_user_by_token("first_token").unwrap(), user);
+    assert_eq!(entity.get_user_by_token("second_token").unwrap(), user);
+    assert_eq!(entity.get_session_by_token("first_token").unwrap().id, first.id);
+    assert_eq!(entity.list_sessions(user.name.as_str()).unwrap().len(), 2);
+    assert_eq!(entity.delete_session(user.name.as_str(), &first.id), None);
+    assert_eq!(entity.delete_session(user.name.as_str(), &first.id), Some(AuthError::NotFound));
+    assert_eq!(entity.get_user_by_token("first_token"), Err(AuthError::NotFound));
+    assert_eq!(entity.get_user_by_token("second_token").unwrap(), user);
+    assert_eq!(entity.delete_sessions(user.name.as_str()), None);
+    assert_eq!(entity.get_session_by_token("second_token").map(|s| s.id), Err(AuthError::NotFound));
+    assert!(entity.list_sessions(user.name.as_str()).unwrap().iter().all(|s| s.id != second.id));
+
+    assert_eq!(entity.add_ticket("verify_email", "ticket_hash", user.name.as_str(), 60), None);
+    assert_eq!(entity.take_ticket("reset_password", "ticket_hash"), Err(AuthError::NotFound));
+    assert_eq!(entity.take_ticket("verify_email", "ticket_hash"), Ok(user.name.clone()));
+    assert_eq!(entity.take_ticket("verify_email", "ticket_hash"), Err(AuthError::NotFound));
+
+    assert_eq!(entity.set_password(user.name.as_str(), "new_password").unwrap(), user);
+    assert!(entity.get_user_by_name_and_pwd("Test user", "qwertyu").is_err());
+    assert_eq!(entity.get_user_by_name_and_pwd("Test user", "new_password").unwrap(), user);
+    assert_eq!(entity.set_password("nobody", "new_password"), Err(AuthError::NotFound));
+
+    let mut mfa = MfaSecret::new("JBSWY3DPEHPK3PXP");
+    assert_eq!(entity.get_mfa_secret(user.name.as_str()), Err(AuthError::NotFound));
+    assert_eq!(entity.set_mfa_secret(user.name.as_str(), Some(mfa.clone())), None);
+    mfa.confirmed = true;
+    mfa.last_step = 51_234_567;
+    assert_eq!(entity.set_mfa_secret(user.name.as_str(), Some(mfa.clone())), None);
+    assert_eq!(entity.get_mfa_secret(user.name.as_str()), Ok(mfa));
+    assert_eq!(entity.set_recovery_codes(user.name.as_str(), &["first_hash".to_string(), "second_hash".to_string()]), None);
+    assert_eq!(entity.count_recovery_codes(user.name.as_str()), Ok(2));
+    assert_eq!(entity.take_recovery_code(user.name.as_str(), "first_hash"), None);
+    assert_eq!(entity.take_recovery_code(user.name.as_str(), "first_hash"), Some(AuthError::NotFound));
+    assert_eq!(entity.set_recovery_codes(user.name.as_str(), &[]), None);
+    assert_eq!(entity.count_recovery_codes(user.name.as_str()), Ok(0));
+
+    assert_eq!(entity.set_mfa_secret(user.name.as_str(), None), None);
+    assert_eq!(entity.get_mfa_secret(user.name.as_str()), Err(AuthError::NotFound));
+
     let list = entity.list_users(0, 1_000_000).unwrap();
     assert_eq!(list.len(), 1);
     assert_eq!(user, list[0]);
@@ -51,13 +151,32 @@ fn functional_tests(entity: &Entity) {
     let user = entity.get_user_by_name(user.name.as_str()).unwrap();
     assert_eq!(user.status, UserStatus::Disabled);
 
+    assert_eq!(entity.list_user_roles(user.name.as_str()), Ok(vec!(Role::Users)));
+
     entity.add_user_role(user.name.as_str(), Role::Admins).unwrap();
     let user = entity.get_user_by_id(user.id).unwrap();
-    assert_eq!(user.role, Role::Admins);
+    assert!(user.has_role(&Role::Admins) && user.has_role(&Role::Users));
 
     entity.add_user_role(user.name.as_str(), Role::Custom("Batman".to_string())).unwrap();
-    let user = entity.get_user_by_id(user.id).unwrap();
-    assert_eq!(user.role, Role::Custom("Batman".to_string()));
+    entity.add_user_role(user.name.as_str(), Role::Admins).unwrap();
+    assert_eq!(entity.list_user_roles(user.name.as_str()), Ok(vec!(Role::Users, Role::Admins, Role::Custom("Batman".to_string()))));
+
+    let user = entity.remove_user_role(user.name.as_str(), &Role::Users).unwrap();
+    assert!(!user.has_role(&Role::Users));
+    let user = entity.remove_user_role(user.name.as_str(), &Role::Users).unwrap();
+    assert_eq!(user.roles.len(), 2);
+    assert_eq!(entity.remove_user_role("nobody", &Role::Users), Err(AuthError::NotFound));
+
+    let batman = Role::Custom("Batman".to_string());
+    assert_eq!(entity.grant_permission(&batman, "cave:enter"), None);
+    assert_eq!(entity.grant_permission(&batman, "cave:enter"), None);
+    assert_eq!(entity.grant_permission(&Role::Admins, "users:*"), None);
+    assert_eq!(entity.list_permissions(&batman), Ok(vec!("cave:enter".to_string())));
+    assert_eq!(entity.user_permissions(&user).unwrap().into_iter().collect::<Vec<_>>(), vec!("*".to_string(), "cave:enter".to_string(), "users:*".to_string()));
+
+    assert_eq!(entity.revoke_permission(&batman, "cave:enter"), None);
+    assert_eq!(entity.revoke_permission(&Role::Admins, "users:*"), None);
+    assert_eq!(entity.list_permissions(&batman), Ok(Vec::new()));
 
     assert_eq!(entity.delete_user(user.id), None);
     let list = entity.list_users(0, 1_000_000).unwrap();
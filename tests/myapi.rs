@@ -3,16 +3,29 @@ extern crate r2d2;
 extern crate r2d2_redis;
 extern crate auth_rocket;
 extern crate rocket;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
+#[cfg(feature = "with-sql")] extern crate r2d2_sqlite;
 
 use r2d2::Pool;
 use r2d2_redis::RedisConnectionManager;
 use auth_rocket::redisdb::RedisEntity;
-use auth_rocket::{ api, PrivateKey, AuthEntity, Role, Entity };
-use rocket::local::Client;
-use rocket::http::{ Status, Header, ContentType };
+use auth_rocket::memorydb::MemoryEntity;
+use auth_rocket::jwt::JwtConfig;
+use auth_rocket::limitation::source::{ TokenSources, TokenSource };
+use auth_rocket::limitation::cookie::CookieSession;
+use auth_rocket::limitation::lockout::LockoutPolicy;
+use auth_rocket::limitation::quota::{ QuotaPolicy, QuotaLimits };
+use auth_rocket::limitation::rate::{ RateLimiter, RateLimitFairing, RateAlgorithm, RateKey, MemoryRateStore, get_rate_limit_catchers };
+use auth_rocket::session::ticket::{ Verification, PasswordReset };
+use auth_rocket::mail::{ AuthMailer, FileMailer };
+use auth_rocket::mfa::totp::Totp;
+use auth_rocket::{ api, PrivateKey, KeyRing, AuthEntity, Role, Entity, MfaSecret };
+use rocket::local::{ Client, LocalRequest };
+use rocket::http::{ Status, Header, ContentType, Method, Cookie };
 use serde_json::{Value};
 use std::collections::HashMap;
+use std::{ env, fs };
+use std::time::{ SystemTime, UNIX_EPOCH };
 use redis::Commands;
 
 #[test]
@@ -38,6 +51,585 @@ fn test_redis_api() {
     tests(&client);
 }
 
+#[test]
+fn test_memory_api() {
+    let memory = MemoryEntity::new();
+
+    memory.add_user("admin", "test@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+    memory.add_user_role("admin", Role::Admins).unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    tests(&client);
+}
+
+#[cfg(feature = "with-sql")]
+#[test]
+fn test_sql_api() {
+    use auth_rocket::sqldb::SqlEntity;
+    use auth_rocket::net::random_string;
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    let path = env::temp_dir().join(format!("auth_rocket_api_{}.sqlite", random_string(10)));
+    let pool = Pool::new(Default::default(), SqliteConnectionManager::file(&path)).unwrap();
+    let sql = SqlEntity::new(&pool).unwrap();
+
+    sql.add_user("admin", "test@example.com", "qwertyu", HashMap::new()).unwrap();
+    sql.enable_user("admin").unwrap();
+    sql.add_user_role("admin", Role::Admins).unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(AuthEntity::new(Box::new(sql)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+
+    // the roles of the session user come from the user_roles table
+    let admin_token = sign_in(&client, "admin", "qwertyu");
+    assert_eq!(authorized(&client, Method::Get, "/api/users/list", &admin_token)["status"], 200);
+    assert_eq!(authorized(&client, Method::Post, "/api/users/user/1/unlock", &admin_token)["status"], 200);
+
+    fs::remove_file(&path).unwrap_or(());
+}
+
+#[test]
+fn test_key_ring_api() {
+    let memory = MemoryEntity::new();
+
+    memory.add_user("admin", "test@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+    memory.add_user_role("admin", Role::Admins).unwrap();
+
+    let ring = KeyRing::new("current", "there the test".to_string())
+        .with_verification_key("previous", "there the old test".to_string());
+
+    let sources = TokenSources::new(vec!(TokenSource::Bearer,
+                                         TokenSource::Header("access_token".to_string()),
+                                         TokenSource::Query("access_token".to_string())));
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(ring)
+        .manage(sources)
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    tests(&client);
+
+    let token = sign_in(&client, "admin", "qwertyu").replace("\"", "");
+    let response = client.get(format!("/api/users/user/1?access_token={}", token)).header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn test_cookie_api() {
+    let memory = MemoryEntity::new();
+
+    memory.add_user("admin", "test@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(CookieSession::new().with_secure(false))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+
+    let mut response = client.post("/api/users/sign_in")
+        .header(ContentType::JSON)
+        .body("{\"username\":\"admin\",\"password\":\"qwertyu\"}")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let cookies: Vec<Cookie> = response.headers().get("Set-Cookie")
+        .map(|c| Cookie::parse(c.to_string()).unwrap())
+        .collect();
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies.iter().any(|c| c.name() == "auth_session" && c.http_only()));
+
+    let v: Value = serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+    assert!(v["data"]["token"].is_null());
+    let csrf = v["data"]["csrf_token"].as_str().unwrap().to_string();
+
+    let with_cookies = |request: LocalRequest| cookies.iter().fold(request, |r, c| r.cookie(Cookie::new(c.name().to_string(), c.value().to_string())));
+
+    let response = with_cookies(client.get("/api/users/sessions").header(ContentType::JSON)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = with_cookies(client.post("/api/users/sign_out").header(ContentType::JSON)).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = with_cookies(client.post("/api/users/sign_out").header(ContentType::JSON).header(Header::new("X-CSRF-Token", "forged"))).dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = with_cookies(client.post("/api/users/sign_out").header(ContentType::JSON).header(Header::new("X-CSRF-Token", csrf))).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = with_cookies(client.get("/api/users/sessions").header(ContentType::JSON)).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn test_verification_api() {
+    let mails = env::temp_dir().join("auth_rocket_verification_api.txt");
+    fs::remove_file(&mails).unwrap_or(());
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(Verification::new("http://localhost/api/users/verify/{token}").with_ttl(60))
+        .manage(AuthMailer::new(Box::new(FileMailer::new(mails.clone()))))
+        .manage(AuthEntity::new(Box::new(MemoryEntity::new())))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+
+    let (status, created) = post_json(&client, "/api/users/sign_up/", json!({
+        "username": "test_user", "email": "test@ya.ru", "password": "test_password", "re_password": "test_password", "attributes": {}
+    }).to_string());
+    assert_eq!(status, Status::Created);
+    assert_eq!(created["data"]["status"], "Created");
+
+    let token = sign_in(&client, "test_user", "test_password");
+    assert_eq!(authorized(&client, Method::Get, "/api/users/sessions", &token)["status"], 401);
+
+    let mail = fs::read_to_string(&mails).unwrap();
+    assert!(mail.contains("To: test@ya.ru"));
+    let link = mail.split_whitespace().find(|word| word.starts_with("http://localhost")).unwrap();
+    let uri = link.trim_left_matches("http://localhost");
+
+    assert_eq!(client.get("/api/users/verify/wrong").dispatch().status(), Status::NotFound);
+
+    let mut response = client.get(uri.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let v: Value = serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+    assert_eq!(v["data"]["status"], "Active");
+    assert_eq!(authorized(&client, Method::Get, "/api/users/sessions", &token)["status"], 200);
+
+    assert_eq!(client.get(uri.to_string()).dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn test_password_reset_api() {
+    let mails = env::temp_dir().join("auth_rocket_password_reset_api.txt");
+    fs::remove_file(&mails).unwrap_or(());
+
+    let memory = MemoryEntity::new();
+    memory.add_user("test_user", "test@ya.ru", "test_password", HashMap::new()).unwrap();
+    memory.enable_user("test_user").unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(PasswordReset::new("http://localhost/reset?token={token}"))
+        .manage(AuthMailer::new(Box::new(FileMailer::new(mails.clone()))))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let token = sign_in(&client, "test_user", "test_password");
+
+    let (status, _) = post_json(&client, "/api/users/password/forgot", json!({"username": "nobody"}).to_string());
+    assert_eq!(status, Status::Accepted);
+    assert!(fs::metadata(&mails).is_err());
+
+    let (status, _) = post_json(&client, "/api/users/password/forgot", json!({"username": "test_user"}).to_string());
+    assert_eq!(status, Status::Accepted);
+
+    let mail = fs::read_to_string(&mails).unwrap();
+    assert!(mail.contains("To: test@ya.ru"));
+    let link = mail.split_whitespace().find(|word| word.starts_with("http://localhost")).unwrap();
+    let ticket = link.trim_left_matches("http://localhost/reset?token=");
+
+    let (status, _) = post_json(&client, "/api/users/password/reset", json!({"token": "wrong", "password": "new_password", "re_password": "new_password"}).to_string());
+    assert_eq!(status, Status::BadRequest);
+
+    let (status, v) = post_json(&client, "/api/users/password/reset", json!({"token": ticket, "password": "test_user1", "re_password": "test_user1"}).to_string());
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(v["violations"], json!([{"rule": "contains_username"}]));
+
+    let (status, _) = post_json(&client, "/api/users/password/reset", json!({"token": ticket, "password": "new_password", "re_password": "new_password"}).to_string());
+    assert_eq!(status, Status::Ok);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/sessions", &token)["status"], 401);
+
+    let (status, _) = post_json(&client, "/api/users/password/reset", json!({"token": ticket, "password": "other_password", "re_password": "other_password"}).to_string());
+    assert_eq!(status, Status::BadRequest);
+
+    let (status, _) = post_json(&client, "/api/users/sign_in/", json!({"username": "test_user", "password": "test_password"}).to_string());
+    assert_eq!(status, Status::Unauthorized);
+    sign_in(&client, "test_user", "new_password");
+}
+
+#[test]
+fn test_mfa_api() {
+    let memory = MemoryEntity::new();
+    memory.add_user("admin", "test@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+
+    let totp = Totp::new("Test").with_skew(3);
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(totp.clone())
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let token = sign_in(&client, "admin", "qwertyu");
+
+    let v = authorized_json(&client, Method::Post, "/api/users/mfa/totp", &token, String::new());
+    assert_eq!(v["status"], 200);
+    let secret = v["body"]["data"]["secret"].as_str().unwrap().to_string();
+    assert!(v["body"]["data"]["uri"].as_str().unwrap().starts_with("otpauth://totp/Test:admin?secret="));
+
+    // not confirmed yet, the password is enough
+    assert_ne!(sign_in(&client, "admin", "qwertyu"), "null");
+
+    let step = totp.step(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64);
+    let code = |step: i64| totp.code(&secret, step).unwrap();
+
+    let v = authorized_json(&client, Method::Post, "/api/users/mfa/totp/confirm", &token, json!({"code": "abcdef"}).to_string());
+    assert_eq!(v["status"], 400);
+    let v = authorized_json(&client, Method::Post, "/api/users/mfa/totp/confirm", &token, json!({"code": code(step)}).to_string());
+    assert_eq!(v["status"], 200);
+
+    let (status, challenge) = post_json(&client, "/api/users/sign_in/", json!({"username": "admin", "password": "qwertyu"}).to_string());
+    assert_eq!(status, Status::Ok);
+    assert_eq!(challenge["data"]["mfa_required"], true);
+    assert!(challenge["data"]["token"].is_null());
+
+    // the code of the confirmation can't be used again
+    let (status, _) = post_json(&client, "/api/users/sign_in/mfa", json!({"mfa_ticket": challenge["data"]["mfa_ticket"], "code": code(step)}).to_string());
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = post_json(&client, "/api/users/sign_in/mfa", json!({"mfa_ticket": challenge["data"]["mfa_ticket"], "code": code(step + 1)}).to_string());
+    assert_eq!(status, Status::Unauthorized);
+
+    let (_, challenge) = post_json(&client, "/api/users/sign_in/", json!({"username": "admin", "password": "qwertyu"}).to_string());
+    let (status, signed_in) = post_json(&client, "/api/users/sign_in/mfa", json!({"mfa_ticket": challenge["data"]["mfa_ticket"], "code": code(step + 1)}).to_string());
+    assert_eq!(status, Status::Ok);
+    let mfa_token = signed_in["data"]["token"].as_str().unwrap().to_string();
+    assert_eq!(authorized(&client, Method::Get, "/api/users/sessions", &mfa_token)["status"], 200);
+
+    let v = authorized_json(&client, Method::Post, "/api/users/mfa/recovery_codes", &mfa_token, String::new());
+    assert_eq!(v["status"], 200);
+    let recovery_code = v["body"]["data"]["recovery_codes"][0].as_str().unwrap().to_lowercase();
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/1", &mfa_token)["body"]["mfa"], json!({"enabled": true, "recovery_codes": 10}));
+
+    let (_, challenge) = post_json(&client, "/api/users/sign_in/", json!({"username": "admin", "password": "qwertyu"}).to_string());
+    let (status, _) = post_json(&client, "/api/users/sign_in/mfa", json!({"mfa_ticket": challenge["data"]["mfa_ticket"], "code": recovery_code}).to_string());
+    assert_eq!(status, Status::Ok);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/1", &mfa_token)["body"]["mfa"]["recovery_codes"], 9);
+
+    let (_, challenge) = post_json(&client, "/api/users/sign_in/", json!({"username": "admin", "password": "qwertyu"}).to_string());
+    let (status, _) = post_json(&client, "/api/users/sign_in/mfa", json!({"mfa_ticket": challenge["data"]["mfa_ticket"], "code": recovery_code}).to_string());
+    assert_eq!(status, Status::Unauthorized);
+
+    let v = authorized_json(&client, Method::Delete, "/api/users/mfa/totp", &mfa_token, json!({"code": code(step + 2)}).to_string());
+    assert_eq!(v["status"], 200);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/1", &mfa_token)["body"]["mfa"], json!({"enabled": false, "recovery_codes": 0}));
+    assert_ne!(sign_in(&client, "admin", "qwertyu"), "null");
+}
+
+#[test]
+fn test_lockout_api() {
+    let memory = MemoryEntity::new();
+    memory.add_user("admin", "admin@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+    memory.add_user_role("admin", Role::Admins).unwrap();
+    memory.add_user("test_user", "test@ya.ru", "test_password", HashMap::new()).unwrap();
+    memory.enable_user("test_user").unwrap();
+    memory.add_user("mfa_user", "mfa@ya.ru", "mfa_password", HashMap::new()).unwrap();
+    memory.enable_user("mfa_user").unwrap();
+    memory.set_mfa_secret("mfa_user", Some(MfaSecret { secret: "JBSWY3DPEHPK3PXP".to_string(), confirmed: true, last_step: 0 }));
+    memory.add_user("new_user", "new@ya.ru", "new_password", HashMap::new()).unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(LockoutPolicy::new().with_max_failures(2, 100))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let admin_token = sign_in(&client, "admin", "qwertyu");
+
+    for _ in 0..2 {
+        let (status, _) = post_json(&client, "/api/users/sign_in/", json!({"username": "test_user", "password": "wrong"}).to_string());
+        assert_eq!(status, Status::Unauthorized);
+    }
+
+    let (status, v) = post_json(&client, "/api/users/sign_in/", json!({"username": "test_user", "password": "test_password"}).to_string());
+    assert_eq!(status.code, 423);
+    assert!(v["retry_after"].as_i64().unwrap() > 0);
+
+    // other users are not affected
+    sign_in(&client, "admin", "qwertyu");
+
+    assert_eq!(authorized(&client, Method::Post, "/api/users/user/2/unlock", &admin_token)["status"], 200);
+    sign_in(&client, "test_user", "test_password");
+
+    // the right password doesn't forget the failures, wrong second factor codes count too
+    for _ in 0..2 {
+        let (status, challenge) = post_json(&client, "/api/users/sign_in/", json!({"username": "mfa_user", "password": "mfa_password"}).to_string());
+        assert_eq!(status, Status::Ok);
+        let (status, _) = post_json(&client, "/api/users/sign_in/mfa", json!({"mfa_ticket": challenge["data"]["mfa_ticket"], "code": "abcdef"}).to_string());
+        assert_eq!(status, Status::Unauthorized);
+    }
+
+    let (status, _) = post_json(&client, "/api/users/sign_in/", json!({"username": "mfa_user", "password": "mfa_password"}).to_string());
+    assert_eq!(status.code, 423);
+
+    // a user who hasn't verified the email yet is not locked out by signing in again and again
+    for _ in 0..3 {
+        let (status, _) = post_json(&client, "/api/users/sign_in/", json!({"username": "new_user", "password": "new_password"}).to_string());
+        assert_ne!(status.code, 423);
+    }
+}
+
+#[test]
+fn test_rate_limit_api() {
+    let memory = MemoryEntity::new();
+    memory.add_user("test_user", "test@ya.ru", "test_password", HashMap::new()).unwrap();
+    memory.enable_user("test_user").unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .catch(get_rate_limit_catchers())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(RateLimiter::new("sign_in", RateAlgorithm::SlidingWindow { limit: 2, window: 3600 }, RateKey::Ip, Box::new(MemoryRateStore::new())))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    sign_in(&client, "test_user", "test_password");
+    sign_in(&client, "test_user", "test_password");
+
+    let mut response = client.post("/api/users/sign_in/")
+        .header(ContentType::JSON)
+        .body(json!({"username": "test_user", "password": "test_password"}).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.headers().get_one("Retry-After").unwrap().parse::<i64>().unwrap() > 0);
+    assert_eq!(response.headers().get_one("X-RateLimit-Limit"), Some("2"));
+    assert_eq!(response.headers().get_one("X-RateLimit-Remaining"), Some("0"));
+    let v: Value = serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+    assert_eq!(v["error"], "Too many requests");
+
+    // the fairing limits every route and reports the limit on each response
+    let memory = MemoryEntity::new();
+    memory.add_user("test_user", "test@ya.ru", "test_password", HashMap::new()).unwrap();
+    memory.enable_user("test_user").unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .attach(RateLimitFairing::new(RateLimiter::new("api", RateAlgorithm::TokenBucket { capacity: 3, refill_per_second: 0.01 },
+                                                       RateKey::Route, Box::new(MemoryRateStore::new()))))
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let token = sign_in(&client, "test_user", "test_password");
+
+    let request = |client: &Client| {
+        let mut request = client.get("/api/users/user/1");
+        request.add_header(Header::new("Accept", "application/json"));
+        request.add_header(Header::new("access_token", token.replace("\"", "")));
+        request.dispatch()
+    };
+
+    for remaining in &["2", "1", "0"] {
+        let response = request(&client);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-RateLimit-Remaining"), Some(*remaining));
+    }
+
+    let response = request(&client);
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.headers().get_one("Retry-After").is_some());
+
+    // a client can't skip the limit by sending the internal header
+    let mut response = client.get("/api/users/user/1")
+        .header(Header::new("X-RateLimit-Denied", "0"))
+        .dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.body_string().unwrap().contains("Too many requests"));
+}
+
+#[test]
+fn test_quota_api() {
+    let memory = MemoryEntity::new();
+    memory.add_user("admin", "admin@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+    memory.add_user_role("admin", Role::Admins).unwrap();
+    memory.add_user("test_user", "test@ya.ru", "test_password", HashMap::new()).unwrap();
+    memory.enable_user("test_user").unwrap();
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .catch(get_rate_limit_catchers())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(QuotaPolicy::new()
+            .with_default(QuotaLimits::new(Some(2), Some(100)))
+            .with_role(Role::Admins, QuotaLimits::new(None, None)))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let admin_token = sign_in(&client, "admin", "qwertyu");
+    let token = sign_in(&client, "test_user", "test_password");
+
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/2", &token)["status"], 200);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/2", &token)["status"], 200);
+
+    let mut request = client.get("/api/users/user/2");
+    request.add_header(Header::new("Accept", "application/json"));
+    request.add_header(Header::new("access_token", token.replace("\"", "")));
+    let response = request.dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert_eq!(response.headers().get_one("X-RateLimit-Limit"), Some("2"));
+    assert!(response.headers().get_one("Retry-After").unwrap().parse::<i64>().unwrap() > 0);
+
+    // the report is not counted
+    let v = authorized(&client, Method::Get, "/api/users/quota", &token);
+    assert_eq!(v["status"], 200);
+    assert_eq!(v["body"]["data"][0], json!({"period": "daily", "limit": 2, "used": 2, "remaining": 0, "reset": v["body"]["data"][0]["reset"]}));
+    assert_eq!(v["body"]["data"][1]["remaining"], 98);
+
+    // admins are unlimited
+    for _ in 0..3 {
+        assert_eq!(authorized(&client, Method::Get, "/api/users/user/1", &admin_token)["status"], 200);
+    }
+
+    assert_eq!(authorized_json(&client, Method::Put, "/api/users/user/2/quota", &token, json!({"daily": 10}).to_string())["status"], 403);
+    assert_eq!(authorized_json(&client, Method::Put, "/api/users/user/2/quota", &admin_token, json!({"daily": -1}).to_string())["status"], 400);
+
+    let v = authorized_json(&client, Method::Put, "/api/users/user/2/quota", &admin_token, json!({"daily": "unlimited", "monthly": 3}).to_string());
+    assert_eq!(v["status"], 200);
+    assert_eq!(v["body"]["data"]["attributes"]["quota_daily"], "unlimited");
+    assert_eq!(v["body"]["quota"][1]["remaining"], 1);
+
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/2", &token)["status"], 200);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/2", &token)["status"], 429);
+
+    // only the periods in the body change, null removes the override
+    let v = authorized_json(&client, Method::Put, "/api/users/user/2/quota", &admin_token, json!({"daily": 5}).to_string());
+    assert_eq!(v["body"]["data"]["attributes"], json!({"quota_daily": "5", "quota_monthly": "3"}));
+    let v = authorized_json(&client, Method::Put, "/api/users/user/2/quota", &admin_token, json!({"monthly": null}).to_string());
+    assert_eq!(v["body"]["data"]["attributes"], json!({"quota_daily": "5"}));
+
+    // users can't grant themselves a quota on sign up
+    let (status, v) = post_json(&client, "/api/users/sign_up", json!({"username": "greedy", "email": "greedy@ya.ru", "password": "correct horse battery",
+                                                                       "re_password": "correct horse battery", "attributes": {"quota_daily": "unlimited"}}).to_string());
+    assert_eq!(status, Status::Created);
+    assert_eq!(v["data"]["attributes"], json!({}));
+}
+
+#[test]
+fn test_permission_api() {
+    let memory = MemoryEntity::new();
+    memory.add_user("admin", "admin@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+    memory.add_user_role("admin", Role::Admins).unwrap();
+    memory.add_user("test_user", "test@ya.ru", "test_password", HashMap::new()).unwrap();
+    memory.enable_user("test_user").unwrap();
+    memory.add_user("support", "support@ya.ru", "support_password", HashMap::new()).unwrap();
+    memory.enable_user("support").unwrap();
+    memory.add_user_role("support", Role::Custom("support".to_string())).unwrap();
+    memory.grant_permission(&Role::Custom("support".to_string()), "users:read");
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(PrivateKey::new("there the test".to_string()))
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let admin_token = sign_in(&client, "admin", "qwertyu");
+    let support_token = sign_in(&client, "support", "support_password");
+    let token = sign_in(&client, "test_user", "test_password");
+
+    let response = client.post("/api/users/user/2/unlock").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(authorized(&client, Method::Post, "/api/users/user/2/unlock", &token)["status"], 403);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/list", &token)["status"], 403);
+
+    // users:read lets see the others, users:write is still needed to change them
+    assert_eq!(authorized(&client, Method::Get, "/api/users/list", &support_token)["status"], 200);
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/2", &support_token)["body"]["data"]["name"], "test_user");
+    assert_eq!(authorized(&client, Method::Post, "/api/users/user/2/unlock", &support_token)["status"], 403);
+
+    // admins have every permission without grants
+    assert_eq!(authorized(&client, Method::Post, "/api/users/user/2/unlock", &admin_token)["status"], 200);
+}
+
+#[test]
+fn test_jwt_api() {
+    let memory = MemoryEntity::new();
+
+    memory.add_user("admin", "test@example.com", "qwertyu", HashMap::new()).unwrap();
+    memory.enable_user("admin").unwrap();
+    memory.add_user_role("admin", Role::Admins).unwrap();
+
+    let private_key = PrivateKey::new("there the test".to_string());
+    let jwt = JwtConfig::hs256(&private_key).with_revocation_check(true);
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(private_key)
+        .manage(jwt)
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    tests(&client);
+
+    let token = sign_in(&client, "admin", "qwertyu");
+    assert_eq!(authorized(&client, Method::Get, "/api/users/user/1", &token)["body"]["data"]["email"], "test@example.com");
+}
+
+#[test]
+fn test_jwt_claims_api() {
+    let memory = MemoryEntity::new();
+
+    let mut attributes = HashMap::new();
+    attributes.insert("phone".to_string(), "+79025555555".to_string());
+    memory.add_user("test_user", "test@ya.ru", "test_password", attributes).unwrap();
+    memory.enable_user("test_user").unwrap();
+
+    let private_key = PrivateKey::new("there the test".to_string());
+    let jwt = JwtConfig::hs256(&private_key);
+
+    let rocket = rocket::ignite()
+        .mount("/api/", api::get_user_routes())
+        .manage(private_key)
+        .manage(jwt)
+        .manage(AuthEntity::new(Box::new(memory)))
+    ;
+
+    let client = Client::new(rocket).expect("valid rocket instance");
+    let token = sign_in(&client, "test_user", "test_password");
+
+    // without the revocation check the user comes from the claims only, the personal data isn't in them
+    let v = authorized(&client, Method::Get, "/api/users/user/1", &token);
+    assert_eq!(v["status"], 200);
+    assert_eq!(v["body"]["data"], json!({
+        "id": 1, "name": "test_user", "email": "", "status": "Active", "roles": ["Users"], "attributes": {}
+    }));
+}
+
 fn tests(client: &Client) {
     sign_up(&client);
     let token: String = sign_in(&client, "test_user", "test_password");
@@ -51,9 +643,26 @@ fn tests(client: &Client) {
     get_list_users(&client, admin_token.clone());
     get_list_users_un_authorize(&client, token.clone());
     get_list_users_with_limits(&client, admin_token.clone());
+
+    sessions(&client);
+    refresh_tokens(&client);
+    token_sources(&client);
+    change_password(&client);
 }
 
 fn sign_up(client: &Client) {
+    let (status, v) = post_json(client, "/api/users/sign_up/", json!({
+        "username": "test_user", "email": "test@ya.ru", "password": "short", "re_password": "short", "attributes": {}
+    }).to_string());
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(v["violations"], json!([{"rule": "too_short", "min": 8}]));
+
+    let (status, v) = post_json(client, "/api/users/sign_up/", json!({
+        "username": "test_user", "email": "test@ya.ru", "password": "test_password", "re_password": "other_password", "attributes": {}
+    }).to_string());
+    assert_eq!(status, Status::BadRequest);
+    assert!(!v["error"].as_str().unwrap().is_empty());
+
     let mut request = client
         .post("/api/users/sign_up/")
         .body("{\"username\":\"test_user\",\"email\":\"test@ya.ru\",\"password\":\"test_password\",\"re_password\":\"test_password\",\"attributes\":{\"phone\":\"+79025555555\"}}")
@@ -69,7 +678,7 @@ fn sign_up(client: &Client) {
     assert_eq!(response.status(), Status::Created);
     assert_eq!(response.content_type(), Some(ContentType::JSON));
     assert_eq!(response.headers().get_one("Location"), Some("/api/users/user/2"));
-    assert_eq!(created_body, Some("{\"data\":{\"attributes\":{\"phone\":\"+79025555555\"},\"email\":\"test@ya.ru\",\"id\":2,\"name\":\"test_user\",\"role\":\"Users\",\"status\":\"Active\"}}".to_string()));
+    assert_eq!(created_body, Some("{\"data\":{\"attributes\":{\"phone\":\"+79025555555\"},\"email\":\"test@ya.ru\",\"id\":2,\"name\":\"test_user\",\"roles\":[\"Users\"],\"status\":\"Active\"}}".to_string()));
 }
 
 fn sign_in(client: &Client, user:&str, pwd: &str) -> String {
@@ -130,7 +739,7 @@ fn get_list_users(client: &Client, token: String) {
 
     let mut response = request.dispatch();
     assert_eq!(response.status(), Status::Ok);
-    assert_eq!(response.body_string(), Some("{\"data\":[{\"attributes\":{},\"email\":\"test@example.com\",\"id\":1,\"name\":\"admin\",\"role\":\"Admins\",\"status\":\"Active\"},{\"attributes\":{\"phone\":\"+79025555555\"},\"email\":\"test@ya.ru\",\"id\":2,\"name\":\"test_user\",\"role\":\"Users\",\"status\":\"Active\"}]}".to_string()));
+    assert_eq!(response.body_string(), Some("{\"data\":[{\"attributes\":{},\"email\":\"test@example.com\",\"id\":1,\"name\":\"admin\",\"roles\":[\"Users\",\"Admins\"],\"status\":\"Active\"},{\"attributes\":{\"phone\":\"+79025555555\"},\"email\":\"test@ya.ru\",\"id\":2,\"name\":\"test_user\",\"roles\":[\"Users\"],\"status\":\"Active\"}]}".to_string()));
 
 }
 
@@ -144,7 +753,7 @@ fn get_list_users_with_limits(client: &Client, token: String) {
 
     let mut response = request.dispatch();
     assert_eq!(response.status(), Status::Ok);
-    assert_eq!(response.body_string(), Some("{\"data\":[{\"attributes\":{\"phone\":\"+79025555555\"},\"email\":\"test@ya.ru\",\"id\":2,\"name\":\"test_user\",\"role\":\"Users\",\"status\":\"Active\"}]}".to_string()));
+    assert_eq!(response.body_string(), Some("{\"data\":[{\"attributes\":{\"phone\":\"+79025555555\"},\"email\":\"test@ya.ru\",\"id\":2,\"name\":\"test_user\",\"roles\":[\"Users\"],\"status\":\"Active\"}]}".to_string()));
 
 }
 
@@ -153,11 +762,146 @@ fn get_list_users_un_authorize(client: &Client, token: String) {
         .get("/api/users/list");
     request.add_header(Header::new("Content-type", "application/json"));
     request.add_header(Header::new("Accept", "application/json"));
-    request.add_header(Header::new("access_token", token.replace("\"", "")));
 
     let response = request.dispatch();
-
     assert_eq!(response.status(), Status::Unauthorized);
+
+    // signed in, but without the users:read permission
+    assert_eq!(authorized(client, Method::Get, "/api/users/list", &token)["status"], 403);
+    assert_eq!(authorized(client, Method::Get, "/api/users/list?limit=1", &token)["status"], 403);
+}
+
+fn authorized(client: &Client, method: Method, uri: &str, token: &str) -> Value {
+    let mut request = client.req(method, uri.to_string());
+
+    request.add_header(Header::new("Content-type", "application/json"));
+    request.add_header(Header::new("Accept", "application/json"));
+    request.add_header(Header::new("access_token", token.replace("\"", "")));
+
+    let mut response = request.dispatch();
+    let status = response.status();
+    let body = response.body_string().unwrap_or_default();
+    json!({ "status": status.code, "body": serde_json::from_str::<Value>(&body).unwrap_or(Value::Null) })
+}
+
+fn sessions(client: &Client) {
+    let first = sign_in(&client, "test_user", "test_password");
+    let second = sign_in(&client, "test_user", "test_password");
+    let third = sign_in(&client, "test_user", "test_password");
+
+    let v = authorized(client, Method::Get, "/api/users/sessions", &first);
+    assert_eq!(v["status"], 200);
+    let current = v["body"]["current"].as_str().unwrap().to_string();
+    assert!(v["body"]["data"].as_array().unwrap().iter().any(|s| s["id"] == current.as_str()));
+
+    let v = authorized(client, Method::Get, "/api/users/sessions", &second);
+    let second_id = v["body"]["current"].as_str().unwrap().to_string();
+    assert_ne!(current, second_id);
+
+    let v = authorized(client, Method::Delete, &format!("/api/users/sessions/{}", second_id), &first);
+    assert_eq!(v["status"], 200);
+    let v = authorized(client, Method::Delete, &format!("/api/users/sessions/{}", second_id), &first);
+    assert_eq!(v["status"], 404);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &second)["status"], 401);
+
+    assert_eq!(authorized(client, Method::Post, "/api/users/sign_out", &first)["status"], 200);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &first)["status"], 401);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &third)["status"], 200);
+
+    assert_eq!(authorized(client, Method::Delete, "/api/users/sessions", &third)["status"], 200);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &third)["status"], 401);
+}
+
+fn authorized_json(client: &Client, method: Method, uri: &str, token: &str, body: String) -> Value {
+    let mut request = client.req(method, uri.to_string()).body(body);
+
+    request.add_header(Header::new("Content-type", "application/json"));
+    request.add_header(Header::new("Accept", "application/json"));
+    request.add_header(Header::new("access_token", token.replace("\"", "")));
+
+    let mut response = request.dispatch();
+    let status = response.status();
+    let body = response.body_string().unwrap_or_default();
+    json!({ "status": status.code, "body": serde_json::from_str::<Value>(&body).unwrap_or(Value::Null) })
+}
+
+fn change_password(client: &Client) {
+    let current = sign_in(&client, "test_user", "test_password");
+    let other = sign_in(&client, "test_user", "test_password");
+
+    let v = authorized_json(client, Method::Post, "/api/users/password", &current, json!({
+        "password": "wrong_password", "new_password": "new_password", "re_password": "new_password"
+    }).to_string());
+    assert_eq!(v["status"], 403);
+
+    let v = authorized_json(client, Method::Post, "/api/users/password", &current, json!({
+        "password": "test_password", "new_password": "new_password", "re_password": "new_password"
+    }).to_string());
+    assert_eq!(v["status"], 200);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &other)["status"], 200);
+
+    let v = authorized_json(client, Method::Post, "/api/users/password", &current, json!({
+        "password": "new_password", "new_password": "test_password", "re_password": "test_password", "revoke_sessions": true
+    }).to_string());
+    assert_eq!(v["status"], 200);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &current)["status"], 200);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &other)["status"], 401);
+
+    assert_eq!(authorized(client, Method::Post, "/api/users/sign_out", &current)["status"], 200);
+}
+
+fn token_sources(client: &Client) {
+    let token = sign_in(&client, "admin", "qwertyu").replace("\"", "");
+
+    let response = client.get("/api/users/user/1")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/api/users/user/1")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer"))
+        .header(Header::new("access_token", token.clone()))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let mut request = client.get("/api/users/user/1").header(ContentType::JSON);
+    request.add_header(Header::new("access_token", token.clone()));
+    request.add_header(Header::new("access_token", token.clone()));
+    assert_eq!(request.dispatch().status(), Status::Unauthorized);
+}
+
+fn post_json(client: &Client, uri: &str, body: String) -> (Status, Value) {
+    let mut request = client.post(uri.to_string()).body(body);
+
+    request.add_header(Header::new("Content-type", "application/json"));
+    request.add_header(Header::new("Accept", "application/json"));
+
+    let mut response = request.dispatch();
+    let body = response.body_string().unwrap_or_default();
+    (response.status(), serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+fn refresh_tokens(client: &Client) {
+    let (status, signed_in) = post_json(client, "/api/users/sign_in/", "{\"username\":\"test_user\",\"password\":\"test_password\"}".to_string());
+    assert_eq!(status, Status::Ok);
+    let refresh = signed_in["data"]["refresh_token"].as_str().unwrap().to_string();
+
+    let (status, refreshed) = post_json(client, "/api/users/token/refresh", json!({"refresh_token": refresh}).to_string());
+    assert_eq!(status, Status::Ok);
+    assert_eq!(refreshed["data"]["session"], signed_in["data"]["session"]);
+    let token = refreshed["data"]["token"].as_str().unwrap().to_string();
+    assert_ne!(refreshed["data"]["refresh_token"], signed_in["data"]["refresh_token"]);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &token)["status"], 200);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", signed_in["data"]["token"].as_str().unwrap())["status"], 401);
+
+    let (status, _) = post_json(client, "/api/users/token/refresh", json!({"refresh_token": refresh}).to_string());
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(authorized(client, Method::Get, "/api/users/sessions", &token)["status"], 401);
+
+    let (status, _) = post_json(client, "/api/users/token/refresh", json!({"refresh_token": refreshed["data"]["refresh_token"]}).to_string());
+    assert_eq!(status, Status::Unauthorized);
 }
 
 fn connect_pool(connect_str: &str, reconnect: bool) -> Pool<RedisConnectionManager> {